@@ -0,0 +1,84 @@
+//! Compares the resizing and thumbnail paths of the importer with the `image` crate's, on
+//! synthetic 4K and 8K screenshots. Run with `cargo bench --bench resize`.
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{FilterType, resize};
+use image::{DynamicImage, ImageReader, Rgb, RgbImage};
+use std::hint::black_box;
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+use steam_screenshot_importer::image_import::ResizeFilterType;
+use steam_screenshot_importer::resample::{decode_jpeg_preview, resize_image};
+
+const THUMB_WIDTH: u32 = 200;
+const RUNS: u32 = 5;
+const SIZES: [(&str, u32, u32); 2] = [("4K", 3840, 2160), ("8K", 7680, 4320)];
+
+fn main() {
+    for (name, width, height) in SIZES {
+        let img = screenshot(width, height);
+        let (half_width, half_height) = (width / 2, height / 2);
+        println!("{name} ({width}x{height})");
+
+        let filter = ResizeFilterType::Lanczos3;
+        report("image::imageops::resize", || {
+            resize(&img, half_width, half_height, FilterType::from(filter))
+        });
+        report("resize_image", || {
+            resize_image(&img, half_width, half_height, filter, false).unwrap()
+        });
+        report("resize_image (linear light)", || {
+            resize_image(&img, half_width, half_height, filter, true).unwrap()
+        });
+
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, 90)
+            .encode_image(&img)
+            .unwrap();
+        let thumb_height = height * THUMB_WIDTH / width;
+        report("thumbnail from full decode", || {
+            let full = ImageReader::new(Cursor::new(&jpeg))
+                .with_guessed_format()
+                .unwrap()
+                .decode()
+                .unwrap();
+            resize_image(&full, THUMB_WIDTH, thumb_height, filter, false).unwrap()
+        });
+        report("thumbnail from DCT-scaled preview", || {
+            let preview = decode_jpeg_preview(Cursor::new(&jpeg), THUMB_WIDTH * 2)
+                .unwrap()
+                .unwrap();
+            resize_image(&preview, THUMB_WIDTH, thumb_height, filter, false).unwrap()
+        });
+        println!();
+    }
+}
+
+/// A gradient with fine lines over it, so that neither flat colors nor noise skew the results.
+fn screenshot(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        if x % 7 == 0 || y % 5 == 0 {
+            return Rgb([255, 255, 255]);
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        let pixel = Rgb([
+            (x * 255 / width) as u8,
+            (y * 255 / height) as u8,
+            ((x + y) % 256) as u8,
+        ]);
+        pixel
+    }))
+}
+
+/// Prints the fastest of a few runs, which is the least affected by everything else going on.
+fn report<T>(name: &str, mut run: impl FnMut() -> T) {
+    let fastest = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(run());
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or(Duration::ZERO);
+    println!("  {name:<36} {:>8.1} ms", fastest.as_secs_f64() * 1000.0);
+}
@@ -0,0 +1,171 @@
+use crate::archive::split_entry_path;
+use crate::screenshot_library::ScreenshotTags;
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Upper 32 bits of the `SteamID`s of individual accounts in the public universe.
+const INDIVIDUAL_STEAM_ID_PREFIX: u64 = 0x0110_0001;
+
+/// Caption and tags to add to screenshots when they're registered with Steam. IDs are decimal
+/// strings because they don't fit in a JavaScript number.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ScreenshotAnnotations {
+    /// Shown as the screenshot's location. `{name}`, `{date}` and `{folder}` are replaced with the
+    /// file name, capture date and name of the folder the screenshot is in
+    #[serde(default)]
+    pub caption: Option<String>,
+    /// 64-bit `SteamID`s of the friends in the screenshot
+    #[serde(default)]
+    pub tagged_users: Vec<String>,
+    /// IDs of the Workshop items in the screenshot
+    #[serde(default)]
+    pub workshop_items: Vec<String>,
+}
+
+/// Annotations of an import, for all of its screenshots and for single files.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportAnnotations {
+    #[serde(default)]
+    pub all: ScreenshotAnnotations,
+    /// Annotations by file path, whose caption replaces the one for all screenshots and whose
+    /// tags are added to theirs
+    #[serde(default)]
+    pub files: HashMap<String, ScreenshotAnnotations>,
+}
+
+impl ImportAnnotations {
+    /// Checks the IDs so an import with a typo in one fails before anything is imported.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message naming the first invalid `SteamID` or Workshop item ID.
+    pub fn parse(&self) -> Result<Annotator, String> {
+        Ok(Annotator {
+            all: ParsedAnnotations::parse(&self.all)?,
+            files: self
+                .files
+                .iter()
+                .map(|(file_path, annotations)| {
+                    Ok((file_path.clone(), ParsedAnnotations::parse(annotations)?))
+                })
+                .collect::<Result<_, String>>()?,
+        })
+    }
+}
+
+#[derive(Default)]
+struct ParsedAnnotations {
+    caption: Option<String>,
+    tagged_users: Vec<u64>,
+    workshop_items: Vec<u64>,
+}
+
+impl ParsedAnnotations {
+    fn parse(annotations: &ScreenshotAnnotations) -> Result<Self, String> {
+        let tagged_users = annotations
+            .tagged_users
+            .iter()
+            .map(|id| {
+                id.trim()
+                    .parse()
+                    .ok()
+                    .filter(|id: &u64| id >> 32 == INDIVIDUAL_STEAM_ID_PREFIX)
+                    .ok_or_else(|| {
+                        format!("Invalid SteamID {id}, expected one like 76561197960287930")
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        let workshop_items = annotations
+            .workshop_items
+            .iter()
+            .map(|id| {
+                id.trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid Workshop item ID {id}"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            caption: annotations
+                .caption
+                .as_ref()
+                .filter(|caption| !caption.trim().is_empty())
+                .cloned(),
+            tagged_users,
+            workshop_items,
+        })
+    }
+}
+
+/// Works out the caption and tags of each screenshot of an import.
+#[derive(Default)]
+pub struct Annotator {
+    all: ParsedAnnotations,
+    files: HashMap<String, ParsedAnnotations>,
+}
+
+impl Annotator {
+    /// The tags of the screenshot imported from `file_path`, with its caption template filled in
+    /// from the path and the Unix timestamp it's dated at, or the current time if it's `None`.
+    #[must_use]
+    pub fn tags(&self, file_path: &str, capture_time: Option<i64>) -> ScreenshotTags {
+        let file = self.files.get(file_path);
+        let caption = file
+            .and_then(|file| file.caption.as_deref())
+            .or(self.all.caption.as_deref());
+
+        let mut tagged_users = self.all.tagged_users.clone();
+        let mut workshop_items = self.all.workshop_items.clone();
+        if let Some(file) = file {
+            tagged_users.extend(&file.tagged_users);
+            workshop_items.extend(&file.workshop_items);
+        }
+        tagged_users.sort_unstable();
+        tagged_users.dedup();
+        workshop_items.sort_unstable();
+        workshop_items.dedup();
+
+        ScreenshotTags {
+            location: caption.map(|caption| fill_template(caption, file_path, capture_time)),
+            tagged_users,
+            workshop_items,
+        }
+    }
+}
+
+fn fill_template(template: &str, file_path: &str, capture_time: Option<i64>) -> String {
+    let name = Path::new(file_path)
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let date = capture_time
+        .and_then(|capture_time| Local.timestamp_opt(capture_time, 0).earliest())
+        .unwrap_or_else(Local::now)
+        .format("%Y-%m-%d")
+        .to_string();
+
+    template
+        .replace("{name}", name)
+        .replace("{date}", &date)
+        .replace("{folder}", folder_name(file_path))
+        .trim()
+        .to_string()
+}
+
+/// Name of the folder a screenshot is in, or of the archive for files at the root of one.
+fn folder_name(file_path: &str) -> &str {
+    let (path, archive) = match split_entry_path(file_path) {
+        Some((archive, entry)) => (entry, Some(archive)),
+        None => (file_path, None),
+    };
+    Path::new(path)
+        .parent()
+        .and_then(Path::file_name)
+        .and_then(|name| name.to_str())
+        .or_else(|| archive.and_then(|archive| Path::new(archive).file_stem()?.to_str()))
+        .unwrap_or_default()
+}
@@ -0,0 +1,408 @@
+use crate::image_import::SUPPORTED_EXTENSIONS;
+use chrono::{Local, NaiveDate, TimeZone};
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::ops::ControlFlow;
+use std::path::Path;
+
+/// Extensions of the archives screenshots can be imported from, for file pickers. `.gz` covers
+/// both `.tar.gz` archives and single gzipped screenshots like `shot.png.gz`.
+pub const ARCHIVE_EXTENSIONS: &[&str] = &["zip", "7z", "tar", "tgz", "gz"];
+
+/// Separates the path of an archive from the path of an entry inside it, as in
+/// `screenshots.zip!/2024/shot.png`.
+const ENTRY_SEPARATOR: &str = "!/";
+
+#[derive(Clone, Copy)]
+enum ArchiveKind {
+    Zip,
+    SevenZip,
+    Tar,
+    TarGz,
+    /// A single gzipped file, whose only entry is named after it without the `.gz`
+    Gz,
+}
+
+impl ArchiveKind {
+    fn of(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "zip" => Some(Self::Zip),
+            "7z" => Some(Self::SevenZip),
+            "tar" => Some(Self::Tar),
+            "tgz" => Some(Self::TarGz),
+            "gz" => {
+                let is_tar = Path::new(path.file_stem()?)
+                    .extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("tar"));
+                Some(if is_tar { Self::TarGz } else { Self::Gz })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A screenshot inside an archive.
+pub struct ArchiveEntry {
+    /// Path of the entry inside the archive, with `/` separators
+    pub name: String,
+    /// Unix timestamp the entry was last modified at, if the archive records it
+    pub modified: Option<i64>,
+}
+
+/// Whether `path` is an archive that screenshots can be imported from.
+#[must_use]
+pub fn is_archive(path: &Path) -> bool {
+    ArchiveKind::of(path).is_some()
+}
+
+/// Joins the path of an archive and of an entry inside it.
+#[must_use]
+pub fn entry_path(archive: &str, name: &str) -> String {
+    format!("{archive}{ENTRY_SEPARATOR}{name}")
+}
+
+/// Splits an entry path made by [`entry_path`] into the paths of the archive and the entry.
+#[must_use]
+pub fn split_entry_path(file_path: &str) -> Option<(&str, &str)> {
+    file_path
+        .match_indices(ENTRY_SEPARATOR)
+        .map(|(index, _)| {
+            (
+                &file_path[..index],
+                &file_path[index + ENTRY_SEPARATOR.len()..],
+            )
+        })
+        .find(|(archive, _)| is_archive(Path::new(archive)))
+}
+
+fn is_supported(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            SUPPORTED_EXTENSIONS
+                .iter()
+                .any(|supported| supported.eq_ignore_ascii_case(extension))
+        })
+}
+
+/// Lists the screenshots with a supported extension inside an archive.
+///
+/// # Errors
+///
+/// Returns an error if `archive` isn't a supported archive or could not be read.
+pub fn list_entries(archive: &Path) -> Result<Vec<ArchiveEntry>, String> {
+    let mut entries = Vec::new();
+    visit_entries(archive, |name, modified, _, _| {
+        if is_supported(name) {
+            entries.push(ArchiveEntry {
+                name: name.to_string(),
+                modified,
+            });
+        }
+        Ok(ControlFlow::Continue(()))
+    })?;
+    Ok(entries)
+}
+
+/// Calls `read` with the name, modification time and contents of every entry of `archive` that
+/// `wanted` returns true for, in the order they're stored in. The archive is only read once, so
+/// this is much faster than reading the entries one by one from solid or compressed archives.
+/// Entries larger than `max_size` bytes aren't read into memory, `read` gets an error for them
+/// instead. Stops early if `read` returns [`ControlFlow::Break`].
+///
+/// # Errors
+///
+/// Returns an error if `archive` isn't a supported archive or could not be read.
+pub fn read_entries(
+    archive: &Path,
+    max_size: u64,
+    wanted: impl Fn(&str) -> bool,
+    mut read: impl FnMut(&str, Option<i64>, Result<Vec<u8>, String>) -> ControlFlow<()>,
+) -> Result<(), String> {
+    let too_large = |name: &str| {
+        format!(
+            "{name} is larger than the memory budget of {} MiB",
+            max_size / 1024 / 1024
+        )
+    };
+
+    visit_entries(archive, |name, modified, size, reader| {
+        if !wanted(name) {
+            return Ok(ControlFlow::Continue(()));
+        }
+        // The declared size can be missing or wrong, so reading also stops past the limit
+        if size.is_some_and(|size| size > max_size) {
+            return Ok(read(name, modified, Err(too_large(name))));
+        }
+        let mut bytes = Vec::new();
+        reader
+            .take(max_size.saturating_add(1))
+            .read_to_end(&mut bytes)
+            .map_err(|error| format!("Failed to read {name}: {error}"))?;
+        if bytes.len() as u64 > max_size {
+            return Ok(read(name, modified, Err(too_large(name))));
+        }
+        Ok(read(name, modified, Ok(bytes)))
+    })
+}
+
+type VisitResult = Result<ControlFlow<()>, String>;
+
+/// Visits an entry with its name, modification time, uncompressed size if the archive records it
+/// and contents.
+type Visitor<'a> = dyn FnMut(&str, Option<i64>, Option<u64>, &mut dyn Read) -> VisitResult + 'a;
+
+fn visit_entries(
+    archive: &Path,
+    mut visit: impl FnMut(&str, Option<i64>, Option<u64>, &mut dyn Read) -> VisitResult,
+) -> Result<(), String> {
+    let kind = ArchiveKind::of(archive)
+        .ok_or_else(|| format!("{} is not a supported archive", archive.display()))?;
+    let open = || {
+        File::open(archive)
+            .map(BufReader::new)
+            .map_err(|error| format!("Failed to open {}: {error}", archive.display()))
+    };
+
+    let result = match kind {
+        ArchiveKind::Zip => visit_zip(open()?, &mut visit),
+        ArchiveKind::SevenZip => visit_7z(archive, &mut visit),
+        ArchiveKind::Tar => visit_tar(open()?, &mut visit),
+        ArchiveKind::TarGz => visit_tar(GzDecoder::new(open()?), &mut visit),
+        ArchiveKind::Gz => visit_gz(archive, open()?, &mut visit),
+    };
+    result.map_err(|error| format!("Failed to read {}: {error}", archive.display()))
+}
+
+fn visit_zip(reader: BufReader<File>, visit: &mut Visitor) -> Result<(), String> {
+    let mut archive = zip::ZipArchive::new(reader).map_err(|error| error.to_string())?;
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(|error| error.to_string())?;
+        if !entry.is_file() {
+            continue;
+        }
+
+        let name = entry.name().to_string();
+        let modified = entry.last_modified().and_then(|time| {
+            NaiveDate::from_ymd_opt(
+                i32::from(time.year()),
+                u32::from(time.month()),
+                u32::from(time.day()),
+            )?
+            .and_hms_opt(
+                u32::from(time.hour()),
+                u32::from(time.minute()),
+                u32::from(time.second()),
+            )
+            .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+            .map(|time| time.timestamp())
+        });
+        let size = entry.size();
+        if visit(&name, modified, Some(size), &mut entry)?.is_break() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn visit_tar(reader: impl Read, visit: &mut Visitor) -> Result<(), String> {
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive.entries().map_err(|error| error.to_string())? {
+        let mut entry = entry.map_err(|error| error.to_string())?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let name = entry
+            .path()
+            .map_err(|error| error.to_string())?
+            .to_string_lossy()
+            .replace('\\', "/");
+        let modified = entry
+            .header()
+            .mtime()
+            .ok()
+            .and_then(|mtime| i64::try_from(mtime).ok());
+        let size = entry.header().size().ok();
+        if visit(&name, modified, size, &mut entry)?.is_break() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn visit_7z(archive: &Path, visit: &mut Visitor) -> Result<(), String> {
+    let mut reader = sevenz_rust::SevenZReader::open(archive, sevenz_rust::Password::empty())
+        .map_err(|error| error.to_string())?;
+
+    // Errors of the visitor are passed out through here, as the reader only returns its own
+    let mut visit_error = None;
+    reader
+        .for_each_entries(|entry, reader| {
+            if entry.is_directory() {
+                return Ok(true);
+            }
+            let modified = entry
+                .has_last_modified_date
+                .then(|| entry.last_modified_date().to_unix_time());
+            match visit(entry.name(), modified, Some(entry.size()), reader) {
+                Ok(ControlFlow::Continue(())) => {
+                    // Entries of solid archives have to be read to get to the next one
+                    io::copy(reader, &mut io::sink())?;
+                    Ok(true)
+                }
+                Ok(ControlFlow::Break(())) => Ok(false),
+                Err(error) => {
+                    visit_error = Some(error);
+                    Ok(false)
+                }
+            }
+        })
+        .map_err(|error| error.to_string())?;
+
+    visit_error.map_or(Ok(()), Err)
+}
+
+fn visit_gz(archive: &Path, reader: BufReader<File>, visit: &mut Visitor) -> Result<(), String> {
+    let mut decoder = GzDecoder::new(reader);
+    let name = archive
+        .file_stem()
+        .ok_or("Archive has no file name")?
+        .to_string_lossy()
+        .to_string();
+    // A modification time of 0 means the gzip header doesn't record it
+    let modified = decoder
+        .header()
+        .map(flate2::GzHeader::mtime)
+        .filter(|&mtime| mtime != 0)
+        .map(i64::from);
+    visit(&name, modified, None, &mut decoder).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    fn write_tar(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut builder = tar::Builder::new(File::create(path).unwrap());
+        for (name, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mtime(1_700_000_000);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, name, *contents).unwrap();
+        }
+        builder.finish().unwrap();
+    }
+
+    fn read_all(archive: &Path, max_size: u64) -> Vec<(String, Result<Vec<u8>, String>)> {
+        let mut entries = Vec::new();
+        read_entries(
+            archive,
+            max_size,
+            |_| true,
+            |name, _, bytes| {
+                entries.push((name.to_string(), bytes));
+                ControlFlow::Continue(())
+            },
+        )
+        .unwrap();
+        entries
+    }
+
+    #[test]
+    fn recognizes_archives_by_extension() {
+        assert!(is_archive(Path::new("shots.ZIP")));
+        assert!(is_archive(Path::new("shots.tar.gz")));
+        assert!(is_archive(Path::new("shot.png.gz")));
+        assert!(!is_archive(Path::new("shot.png")));
+        assert!(matches!(
+            ArchiveKind::of(Path::new("shots.tar.gz")),
+            Some(ArchiveKind::TarGz)
+        ));
+        assert!(matches!(
+            ArchiveKind::of(Path::new("shot.png.gz")),
+            Some(ArchiveKind::Gz)
+        ));
+    }
+
+    #[test]
+    fn splits_entry_paths() {
+        let path = entry_path("C:/shots.zip", "2024/shot.png");
+        assert_eq!(
+            split_entry_path(&path),
+            Some(("C:/shots.zip", "2024/shot.png"))
+        );
+        assert_eq!(split_entry_path("C:/shots!/shot.png"), None);
+    }
+
+    #[test]
+    fn lists_supported_entries_with_their_modification_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("shots.tar");
+        write_tar(
+            &archive,
+            &[
+                ("a/shot.png", b"png"),
+                ("notes.txt", b"text"),
+                ("b.JPG", b"jpg"),
+            ],
+        );
+
+        let entries = list_entries(&archive).unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["a/shot.png", "b.JPG"]);
+        assert!(
+            entries
+                .iter()
+                .all(|entry| entry.modified == Some(1_700_000_000))
+        );
+    }
+
+    #[test]
+    fn entries_larger_than_the_limit_are_not_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("shots.tar");
+        write_tar(
+            &archive,
+            &[("small.png", &[1; 10]), ("large.png", &[2; 11])],
+        );
+
+        let entries = read_all(&archive, 10);
+        assert_eq!(entries[0], ("small.png".to_string(), Ok(vec![1; 10])));
+        assert_eq!(entries[1].0, "large.png");
+        assert!(entries[1].1.is_err());
+    }
+
+    #[test]
+    fn gzipped_screenshots_are_read_as_their_only_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("shot.png.gz");
+        let mut encoder = GzEncoder::new(File::create(&archive).unwrap(), Compression::fast());
+        encoder.write_all(b"png").unwrap();
+        encoder.finish().unwrap();
+
+        let names: Vec<_> = list_entries(&archive)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, ["shot.png"]);
+        assert_eq!(
+            read_all(&archive, 3),
+            [("shot.png".to_string(), Ok(b"png".to_vec()))]
+        );
+        // The size of gzipped files isn't known up front, so the limit is found while reading
+        assert!(read_all(&archive, 2)[0].1.is_err());
+    }
+}
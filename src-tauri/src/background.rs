@@ -0,0 +1,245 @@
+use image::imageops::{FilterType, fast_blur, resize};
+use image::{DynamicImage, Rgb, Rgb32FImage, RgbImage, Rgba32FImage, RgbaImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::array;
+
+/// Longest side of the downscaled copy that blurred backgrounds are made from.
+const BLUR_SIZE: u32 = 64;
+const BLUR_SIGMA: f32 = 2.0;
+const CHECKER_LIGHT: [u8; 3] = [204, 204, 204];
+const CHECKER_DARK: [u8; 3] = [153, 153, 153];
+
+/// What the transparent parts of screenshots are filled with, as JPEGs can't store transparency.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Deserialize,
+    Serialize,
+    specta::Type,
+    clap::ValueEnum,
+)]
+pub enum Background {
+    /// A single color
+    #[default]
+    Solid,
+    /// The grey checkerboard image editors show behind transparency
+    Checkerboard,
+    /// A blurred copy of the opaque parts of the screenshot
+    BlurredFill,
+}
+
+/// Parses a color written as `RRGGBB` or `#RRGGBB`.
+///
+/// # Errors
+///
+/// Returns an error if `color` isn't six hexadecimal digits.
+pub fn parse_color(color: &str) -> Result<[u8; 3], String> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    let invalid = || format!("Invalid color {color}, expected RRGGBB");
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+
+    let channel = |start: usize| u8::from_str_radix(&hex[start..start + 2], 16);
+    match (channel(0), channel(2), channel(4)) {
+        (Ok(red), Ok(green), Ok(blue)) => Ok([red, green, blue]),
+        _ => Err(invalid()),
+    }
+}
+
+/// Composites a screenshot with an alpha channel onto `background`, using `color` for solid
+/// backgrounds and for blurred ones of screenshots that have nothing opaque to blur. Images that
+/// have an alpha channel but no transparent pixels only have the channel dropped.
+#[must_use]
+pub fn flatten(img: DynamicImage, background: Background, color: [u8; 3]) -> DynamicImage {
+    if !img.color().has_alpha() {
+        return img;
+    }
+    let img = img.into_rgba8();
+    if img.pixels().all(|pixel| pixel[3] == u8::MAX) {
+        return DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(img).into_rgb8());
+    }
+
+    let (width, height) = img.dimensions();
+    let mut flattened = match background {
+        Background::Solid => RgbImage::from_pixel(width, height, Rgb(color)),
+        Background::Checkerboard => {
+            // Scaled with the image, so the squares can still be seen in the thumbnail
+            let square = (width.max(height) / 128).max(8);
+            RgbImage::from_fn(width, height, |x, y| {
+                if (x / square + y / square) % 2 == 0 {
+                    Rgb(CHECKER_LIGHT)
+                } else {
+                    Rgb(CHECKER_DARK)
+                }
+            })
+        }
+        Background::BlurredFill => blurred_fill(&img, color),
+    };
+
+    flattened
+        .par_chunks_mut(3)
+        .zip(img.par_chunks(4))
+        .for_each(|(back, pixel)| {
+            for (back, &front) in back.iter_mut().zip(pixel) {
+                *back = blend(front, *back, pixel[3]);
+            }
+        });
+
+    DynamicImage::ImageRgb8(flattened)
+}
+
+/// Fills the image with the blurred colors of its opaque parts. Colors are weighted by their
+/// alpha, so the color stored in fully transparent pixels doesn't bleed in.
+fn blurred_fill(img: &RgbaImage, color: [u8; 3]) -> RgbImage {
+    let (width, height) = img.dimensions();
+    let longest = width.max(height);
+    let small_width = (width * BLUR_SIZE / longest).max(1);
+    let small_height = (height * BLUR_SIZE / longest).max(1);
+
+    let mut premultiplied = img.clone();
+    premultiplied.par_chunks_mut(4).for_each(|pixel| {
+        for channel in 0..3 {
+            pixel[channel] = blend(pixel[channel], 0, pixel[3]);
+        }
+    });
+    let small = resize(
+        &premultiplied,
+        small_width,
+        small_height,
+        FilterType::Triangle,
+    );
+    let small = fast_blur(&DynamicImage::ImageRgba8(small).into_rgba32f(), BLUR_SIGMA);
+
+    let fill = DynamicImage::ImageRgb32F(fill_gaps(&small, color)).into_rgb8();
+    resize(&fill, width, height, FilterType::Triangle)
+}
+
+/// Unpremultiplies a premultiplied image, filling what isn't opaque from ever smaller copies of
+/// it. Areas far away from anything opaque get the average color around them instead of a hard
+/// edge, and `color` is only used if nothing is opaque at all.
+fn fill_gaps(level: &Rgba32FImage, color: [u8; 3]) -> Rgb32FImage {
+    let (width, height) = level.dimensions();
+    if width == 1 && height == 1 {
+        let pixel = level.get_pixel(0, 0);
+        let fill = if pixel[3] <= f32::EPSILON {
+            color.map(|channel| f32::from(channel) / 255.0)
+        } else {
+            [
+                pixel[0] / pixel[3],
+                pixel[1] / pixel[3],
+                pixel[2] / pixel[3],
+            ]
+        };
+        return Rgb32FImage::from_pixel(1, 1, Rgb(fill));
+    }
+
+    let smaller = resize(
+        level,
+        width.div_ceil(2),
+        height.div_ceil(2),
+        FilterType::Triangle,
+    );
+    let coarse = resize(
+        &fill_gaps(&smaller, color),
+        width,
+        height,
+        FilterType::Triangle,
+    );
+    Rgb32FImage::from_fn(width, height, |x, y| {
+        let pixel = level.get_pixel(x, y);
+        let coarse = coarse.get_pixel(x, y);
+        let transparency = 1.0 - pixel[3].min(1.0);
+        Rgb(array::from_fn(|channel| {
+            pixel[channel] + transparency * coarse[channel]
+        }))
+    })
+}
+
+/// Blends a channel of a pixel over the same channel of the background with `alpha`.
+fn blend(front: u8, back: u8, alpha: u8) -> u8 {
+    let alpha = u16::from(alpha);
+    let blended = (u16::from(front) * alpha + u16::from(back) * (255 - alpha) + 127) / 255;
+    u8::try_from(blended).unwrap_or(u8::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("#ff8000"), Ok([255, 128, 0]));
+        assert_eq!(parse_color("0A0b0C"), Ok([10, 11, 12]));
+        for invalid in [
+            "#f80", "#ff800", "#gg8000", "ff80000", "#ff80é", "black", "",
+        ] {
+            assert_eq!(
+                parse_color(invalid),
+                Err(format!("Invalid color {invalid}, expected RRGGBB"))
+            );
+        }
+    }
+
+    #[test]
+    fn blends_transparent_pixels_onto_a_solid_color() {
+        let img = RgbaImage::from_fn(3, 1, |x, _| match x {
+            0 => Rgba([200, 100, 50, 128]),
+            1 => Rgba([200, 100, 50, 0]),
+            _ => Rgba([200, 100, 50, 255]),
+        });
+
+        let flattened = flatten(
+            DynamicImage::ImageRgba8(img),
+            Background::Solid,
+            [0, 0, 255],
+        );
+
+        let pixels: Vec<_> = flattened.as_rgb8().unwrap().pixels().copied().collect();
+        assert_eq!(
+            pixels,
+            [Rgb([100, 50, 152]), Rgb([0, 0, 255]), Rgb([200, 100, 50])]
+        );
+    }
+
+    #[test]
+    fn leaves_opaque_images_unchanged() {
+        let opaque = RgbImage::from_pixel(2, 2, Rgb([200, 100, 50]));
+
+        let flattened = flatten(
+            DynamicImage::ImageRgb8(opaque.clone()),
+            Background::Checkerboard,
+            [0; 3],
+        );
+        assert_eq!(flattened.as_rgb8(), Some(&opaque));
+
+        let with_alpha = DynamicImage::ImageRgb8(opaque.clone()).into_rgba8();
+        let flattened = flatten(
+            DynamicImage::ImageRgba8(with_alpha),
+            Background::BlurredFill,
+            [0; 3],
+        );
+        assert_eq!(flattened.as_rgb8(), Some(&opaque));
+    }
+
+    #[test]
+    fn fills_transparent_images_with_the_color() {
+        let transparent = RgbaImage::from_pixel(4, 3, Rgba([200, 100, 50, 0]));
+
+        let flattened = flatten(
+            DynamicImage::ImageRgba8(transparent),
+            Background::BlurredFill,
+            [10, 20, 30],
+        );
+
+        let flattened = flattened.as_rgb8().unwrap();
+        assert_eq!(flattened.dimensions(), (4, 3));
+        assert!(flattened.pixels().all(|&pixel| pixel == Rgb([10, 20, 30])));
+    }
+}
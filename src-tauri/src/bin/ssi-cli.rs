@@ -0,0 +1,376 @@
+use clap::Parser;
+use serde_json::json;
+use simple_logger::SimpleLogger;
+use std::fs::read_to_string;
+use std::io::{Write, stdout};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicU32, Ordering};
+use steam_screenshot_importer::annotations::ImportAnnotations;
+use steam_screenshot_importer::background::parse_color;
+use steam_screenshot_importer::capture_time::CaptureTimeSource;
+use steam_screenshot_importer::file_selection::{FileFilter, expand_paths};
+use steam_screenshot_importer::image_import::{
+    Background, CancellationToken, DEFAULT_DUPLICATE_THRESHOLD, DEFAULT_MEMORY_BUDGET_MB,
+    DEFAULT_SHARPEN_RADIUS, DuplicatePolicy, HdrTransfer, ImportError, ImportOptions,
+    ImportProgress, ImportReport, MetadataPolicy, ResizeFilterType, StereoLayout, ToneMapOperator,
+    VrMode, run_import,
+};
+use steam_screenshot_importer::screenshot_library::{LibraryBackend, RecordingLibrary};
+use steam_screenshot_importer::steam_helper;
+use steam_screenshot_importer::tone_map::DEFAULT_WHITE_POINT;
+
+/// Exit status of imports that were cancelled before every screenshot was imported, which is
+/// what shells report for commands stopped with Ctrl+C.
+const CANCELLED_EXIT_CODE: u8 = 130;
+
+/// Import screenshots into the Steam screenshot library from the command line
+#[derive(Parser)]
+#[command(version)]
+#[allow(clippy::struct_excessive_bools)]
+struct Args {
+    /// Screenshot files, or directories and archives to search for screenshots
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Only import screenshots in the directories that match one of these glob patterns
+    #[arg(long = "include", value_name = "PATTERN")]
+    include: Vec<String>,
+
+    /// Leave out screenshots in the directories that match these glob patterns
+    #[arg(long = "exclude", value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Only import screenshots captured on or after this date (YYYY-MM-DD)
+    #[arg(long, value_name = "DATE")]
+    captured_after: Option<String>,
+
+    /// Only import screenshots captured on or before this date (YYYY-MM-DD)
+    #[arg(long, value_name = "DATE")]
+    captured_before: Option<String>,
+
+    /// Steam app ID of the game to import the screenshots for
+    #[arg(short, long)]
+    app_id: u32,
+
+    /// Quality of re-encoded JPEGs
+    #[arg(
+        short = 'q',
+        long,
+        default_value_t = 95,
+        value_parser = clap::value_parser!(u8).range(1..=100)
+    )]
+    jpeg_quality: u8,
+
+    /// Filter used when downscaling screenshots and creating thumbnails
+    #[arg(short, long, value_enum, default_value_t = ResizeFilterType::Lanczos3)]
+    filter_type: ResizeFilterType,
+
+    /// Resize in linear light, so fine bright details don't get darker when downscaling
+    #[arg(long)]
+    linear_resize: bool,
+
+    /// Strength of the unsharp mask applied after resizing, 0 to not sharpen
+    #[arg(long, default_value_t = 0.0)]
+    sharpen: f32,
+
+    /// Blur radius of the unsharp mask in pixels
+    #[arg(long, default_value_t = DEFAULT_SHARPEN_RADIUS)]
+    sharpen_radius: f32,
+
+    /// How HDR screenshots are mapped to SDR
+    #[arg(short, long, value_enum, default_value_t = ToneMapOperator::AcesFilmic)]
+    tone_map: ToneMapOperator,
+
+    /// Exposure adjustment of HDR screenshots in stops
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    exposure: f32,
+
+    /// Brightness of HDR screenshots, relative to SDR white, that's mapped to white
+    #[arg(long, default_value_t = DEFAULT_WHITE_POINT)]
+    white_point: f32,
+
+    /// How the values of 16-bit screenshots are encoded, unless their color profile marks them as
+    /// PQ or HLG
+    #[arg(long, value_enum, default_value_t = HdrTransfer::Srgb)]
+    hdr_transfer: HdrTransfer,
+
+    /// What transparent parts of screenshots are filled with
+    #[arg(short, long, value_enum, default_value_t = Background::Solid)]
+    background: Background,
+
+    /// Color of solid backgrounds, as RRGGBB
+    #[arg(long, default_value = "000000", value_parser = parse_color)]
+    background_color: [u8; 3],
+
+    /// Embed an sRGB ICC profile in re-encoded JPEGs
+    #[arg(long)]
+    embed_srgb_profile: bool,
+
+    /// Which metadata of the original screenshots is kept in the imported JPEGs
+    #[arg(short, long, value_enum, default_value_t = MetadataPolicy::KeepSelected)]
+    metadata: MetadataPolicy,
+
+    /// Where the date shown in Steam for each screenshot comes from
+    #[arg(short, long, value_enum, default_value_t = CaptureTimeSource::ImportTime)]
+    capture_time: CaptureTimeSource,
+
+    /// What to do with screenshots that are already in the Steam library
+    #[arg(short, long, value_enum, default_value_t = DuplicatePolicy::Import)]
+    duplicates: DuplicatePolicy,
+
+    /// Maximum perceptual hash distance (0-64) for a screenshot to count as a duplicate
+    #[arg(
+        long,
+        default_value_t = DEFAULT_DUPLICATE_THRESHOLD,
+        value_parser = clap::value_parser!(u32).range(0..=64)
+    )]
+    duplicate_threshold: u32,
+
+    /// Maximum number of screenshots processed at the same time, 0 for one per CPU core
+    #[arg(long, default_value_t = 0)]
+    max_workers: u32,
+
+    /// Memory in MiB that the screenshots processed at the same time may take together
+    #[arg(
+        long,
+        value_name = "MIB",
+        default_value_t = DEFAULT_MEMORY_BUDGET_MB,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    memory_budget: u32,
+
+    /// Import VR screenshots of this type, or detect it from each screenshot's aspect ratio
+    #[arg(long, value_enum, default_value_t = VrMode::Off)]
+    vr: VrMode,
+
+    /// How the images for both eyes of stereo VR screenshots are arranged
+    #[arg(long, value_enum, default_value_t = StereoLayout::Auto)]
+    stereo_layout: StereoLayout,
+
+    /// Caption shown as each screenshot's location. {name}, {date} and {folder} are replaced with
+    /// the file name, capture date and folder name
+    #[arg(long, value_name = "TEMPLATE")]
+    caption: Option<String>,
+
+    /// Tag a friend in every screenshot by the 64-bit ID of their Steam account
+    #[arg(long = "tag-user", value_name = "STEAMID")]
+    tag_users: Vec<String>,
+
+    /// Tag a Workshop item in every screenshot
+    #[arg(long = "tag-workshop-item", value_name = "ID")]
+    tag_workshop_items: Vec<String>,
+
+    /// JSON file with captions and tags for all screenshots ("all") and for single files
+    /// ("files", by path), which the options above are added to
+    #[arg(long, value_name = "FILE")]
+    annotations: Option<PathBuf>,
+
+    /// Print progress and results as JSON lines
+    #[arg(long)]
+    json: bool,
+
+    /// Open the game's Steam screenshot library after importing
+    #[arg(long)]
+    open_library: bool,
+
+    /// Library the screenshots are registered with
+    #[arg(short, long, value_enum, default_value_t = LibraryBackend::Steamworks)]
+    library: LibraryBackend,
+
+    /// Log each import step
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+struct ConsoleProgress {
+    json: bool,
+    last_percent: AtomicU32,
+}
+
+impl ImportProgress for ConsoleProgress {
+    fn report(&self, progress: f32) {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let percent = progress.clamp(0.0, 100.0) as u32;
+        if self.last_percent.fetch_max(percent, Ordering::SeqCst) >= percent {
+            return;
+        }
+
+        let mut stdout = stdout().lock();
+        if self.json {
+            let _ = writeln!(stdout, "{}", json!({ "progress": progress }));
+        } else {
+            let _ = write!(stdout, "\rImporting... {percent}%");
+            let _ = stdout.flush();
+        }
+    }
+}
+
+fn collect_files(args: &Args) -> Result<Vec<String>, String> {
+    let paths: Vec<String> = args
+        .paths
+        .iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    let filter = FileFilter::default()
+        .include(args.include.clone())
+        .exclude(args.exclude.clone())
+        .captured_between(args.captured_after.clone(), args.captured_before.clone());
+    expand_paths(&paths, &filter)
+}
+
+fn import_annotations(args: &Args) -> Result<ImportAnnotations, String> {
+    let mut annotations = match &args.annotations {
+        Some(path) => {
+            let text = read_to_string(path)
+                .map_err(|error| format!("Failed to read {}: {error}", path.display()))?;
+            serde_json::from_str(&text)
+                .map_err(|error| format!("Invalid annotations in {}: {error}", path.display()))?
+        }
+        None => ImportAnnotations::default(),
+    };
+    if args.caption.is_some() {
+        annotations.all.caption.clone_from(&args.caption);
+    }
+    annotations
+        .all
+        .tagged_users
+        .extend(args.tag_users.iter().cloned());
+    annotations
+        .all
+        .workshop_items
+        .extend(args.tag_workshop_items.iter().cloned());
+    Ok(annotations)
+}
+
+fn import_options(args: &Args) -> ImportOptions {
+    ImportOptions::new(args.app_id, args.jpeg_quality, args.filter_type)
+        .linear_resize(args.linear_resize)
+        .sharpen(args.sharpen, args.sharpen_radius)
+        .tone_map(args.tone_map, args.exposure, args.white_point)
+        .hdr_transfer(args.hdr_transfer)
+        .background(args.background, args.background_color)
+        .embed_srgb_profile(args.embed_srgb_profile)
+        .metadata(args.metadata)
+        .capture_time(args.capture_time)
+        .duplicates(args.duplicates, args.duplicate_threshold)
+        .parallelism(args.max_workers, args.memory_budget)
+        .vr(args.vr, args.stereo_layout)
+        .open_library(args.open_library)
+}
+
+fn main() -> ExitCode {
+    if let Some(app_id) = steam_helper::requested_app_id() {
+        return steam_helper::run(app_id);
+    }
+    let args = Args::parse();
+
+    if args.verbose {
+        SimpleLogger::new()
+            .with_level(log::LevelFilter::Info)
+            .init()
+            .unwrap();
+    }
+
+    let (files, annotations) =
+        match collect_files(&args).and_then(|files| Ok((files, import_annotations(&args)?))) {
+            Ok(inputs) => inputs,
+            Err(error) => {
+                eprintln!("{error}");
+                return ExitCode::FAILURE;
+            }
+        };
+    let options = import_options(&args);
+    let progress = ConsoleProgress {
+        json: args.json,
+        last_percent: AtomicU32::new(0),
+    };
+
+    // Stop at the next step of each screenshot on Ctrl+C so the cache is still emptied
+    let cancellation = CancellationToken::default();
+    let handler_cancellation = cancellation.clone();
+    if let Err(error) = ctrlc::set_handler(move || handler_cancellation.cancel()) {
+        eprintln!("Failed to set Ctrl+C handler: {error}");
+    }
+
+    let recording_library = RecordingLibrary::default();
+    let result = match args.library {
+        LibraryBackend::Recording => run_import(
+            &files,
+            options,
+            &annotations,
+            &recording_library,
+            &progress,
+            &cancellation,
+            None,
+        ),
+        backend @ (LibraryBackend::Steamworks | LibraryBackend::Userdata) => backend
+            .connect(args.app_id)
+            .map_err(ImportError::from)
+            .and_then(|library| {
+                run_import(
+                    &files,
+                    options,
+                    &annotations,
+                    library.as_ref(),
+                    &progress,
+                    &cancellation,
+                    None,
+                )
+            }),
+    };
+
+    if args.json {
+        let mut output = match &result {
+            Ok(report) => json!({
+                "summary": format!("Imported {} screenshots.", report.imported()),
+                "errors": [],
+                "duplicates": report.duplicates(),
+                "cancelled": report.cancelled(),
+            }),
+            Err(error) => json!(error),
+        };
+        if matches!(args.library, LibraryBackend::Recording) {
+            output["recorded"] = json!(recording_library.screenshots());
+        }
+        println!("{output}");
+    } else {
+        print_result(&result);
+    }
+
+    match &result {
+        Ok(report) if report.cancelled().is_empty() => ExitCode::SUCCESS,
+        Ok(_) => ExitCode::from(CANCELLED_EXIT_CODE),
+        Err(_) => ExitCode::FAILURE,
+    }
+}
+
+fn print_result(result: &Result<ImportReport, ImportError>) {
+    println!();
+    let duplicates = match result {
+        Ok(report) => {
+            println!("Imported {} screenshots.", report.imported());
+            if !report.cancelled().is_empty() {
+                println!(
+                    "Cancelled before {} screenshots were imported.",
+                    report.cancelled().len()
+                );
+            }
+            report.duplicates()
+        }
+        Err(error) => {
+            println!("{error}");
+            for failure in error.errors() {
+                println!("  {failure}");
+            }
+            error.duplicates()
+        }
+    };
+
+    if !duplicates.is_empty() {
+        println!("Duplicates:");
+        for duplicate in duplicates {
+            println!("  {duplicate}");
+        }
+    }
+}
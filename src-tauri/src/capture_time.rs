@@ -0,0 +1,408 @@
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use exif::{In, Tag, Value};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, metadata};
+use std::io::{BufRead, BufReader, Cursor, Seek, SeekFrom};
+use std::path::Path;
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+const PNG_CREATION_TIME_KEYWORD: &[u8] = b"Creation Time";
+const MAX_PNG_TEXT_LENGTH: u32 = 64 * 1024;
+
+/// Where the date shown in Steam for an imported screenshot comes from.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, specta::Type, clap::ValueEnum)]
+pub enum CaptureTimeSource {
+    /// Date screenshots at the time they're imported, like Steam does
+    #[default]
+    ImportTime,
+    /// Read the capture time from EXIF or PNG metadata, falling back to the modification time
+    Metadata,
+    /// Use the time the file was last modified
+    FileModified,
+}
+
+/// Returns the Unix timestamp the screenshot at `path` was captured at, or `None` if it should
+/// be dated at import time.
+#[must_use]
+pub fn capture_time(path: &Path, source: CaptureTimeSource) -> Option<i64> {
+    find_capture_time(
+        &path.display().to_string(),
+        source,
+        || File::open(path).ok().map(BufReader::new),
+        || modified_time(path),
+    )
+}
+
+/// Like [`capture_time`], but for a screenshot that's already been read into memory, such as an
+/// entry of an archive, that was last modified at `modified`.
+#[must_use]
+pub fn capture_time_of_bytes(
+    name: &str,
+    bytes: &[u8],
+    modified: Option<i64>,
+    source: CaptureTimeSource,
+) -> Option<i64> {
+    find_capture_time(name, source, || Some(Cursor::new(bytes)), || modified)
+}
+
+fn find_capture_time<R: BufRead + Seek>(
+    name: &str,
+    source: CaptureTimeSource,
+    open: impl Fn() -> Option<R>,
+    modified: impl FnOnce() -> Option<i64>,
+) -> Option<i64> {
+    let (source_name, timestamp) = match source {
+        CaptureTimeSource::ImportTime => return None,
+        CaptureTimeSource::Metadata => open()
+            .and_then(exif_capture_time)
+            .map(|timestamp| ("EXIF", timestamp))
+            .or_else(|| {
+                open()
+                    .and_then(png_capture_time)
+                    .map(|timestamp| ("PNG", timestamp))
+            })
+            .or_else(|| modified().map(|timestamp| ("modification time", timestamp)))?,
+        CaptureTimeSource::FileModified => ("modification time", modified()?),
+    };
+
+    info!("Using {source_name} capture time {timestamp} for {name}");
+    Some(timestamp)
+}
+
+fn modified_time(path: &Path) -> Option<i64> {
+    let modified = metadata(path).ok()?.modified().ok()?;
+    Some(DateTime::<Utc>::from(modified).timestamp())
+}
+
+fn exif_capture_time(mut reader: impl BufRead + Seek) -> Option<i64> {
+    let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+
+    let field = exif
+        .get_field(Tag::DateTimeOriginal, In::PRIMARY)
+        .or_else(|| exif.get_field(Tag::DateTime, In::PRIMARY))?;
+    let Value::Ascii(values) = &field.value else {
+        return None;
+    };
+    let mut date_time = exif::DateTime::from_ascii(values.first()?).ok()?;
+
+    let offset_tag = if field.tag == Tag::DateTimeOriginal {
+        Tag::OffsetTimeOriginal
+    } else {
+        Tag::OffsetTime
+    };
+    if let Some(offset) = exif.get_field(offset_tag, In::PRIMARY)
+        && let Value::Ascii(offsets) = &offset.value
+        && let Some(offset) = offsets.first()
+    {
+        // A missing or malformed offset just means the time is treated as local time
+        let _ = date_time.parse_offset(offset);
+    }
+
+    let naive = NaiveDate::from_ymd_opt(
+        i32::from(date_time.year),
+        u32::from(date_time.month),
+        u32::from(date_time.day),
+    )?
+    .and_hms_opt(
+        u32::from(date_time.hour),
+        u32::from(date_time.minute),
+        u32::from(date_time.second),
+    )?;
+
+    match date_time.offset {
+        Some(offset) => FixedOffset::east_opt(i32::from(offset) * 60)?
+            .from_local_datetime(&naive)
+            .single()
+            .map(|time| time.timestamp()),
+        None => local_timestamp(&naive),
+    }
+}
+
+/// Reads the `Creation Time` text chunk of a PNG, or its `tIME` chunk if there is none.
+fn png_capture_time(mut reader: impl BufRead + Seek) -> Option<i64> {
+    let mut signature = [0; 8];
+    reader.read_exact(&mut signature).ok()?;
+    if &signature != PNG_SIGNATURE {
+        return None;
+    }
+
+    let mut modification_time = None;
+    loop {
+        let mut header = [0; 8];
+        if reader.read_exact(&mut header).is_err() {
+            break;
+        }
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let chunk_type = &header[4..8];
+
+        match chunk_type {
+            b"IEND" => break,
+            b"tIME" if length == 7 => {
+                let mut data = [0; 7];
+                reader.read_exact(&mut data).ok()?;
+                modification_time = png_time(data);
+            }
+            b"tEXt" | b"iTXt" if length <= MAX_PNG_TEXT_LENGTH => {
+                let mut data = vec![0; length as usize];
+                reader.read_exact(&mut data).ok()?;
+                if let Some(timestamp) = png_text_creation_time(chunk_type, &data) {
+                    return Some(timestamp);
+                }
+            }
+            _ => skip(&mut reader, i64::from(length))?,
+        }
+
+        // Skip the chunk's CRC
+        skip(&mut reader, 4)?;
+    }
+
+    modification_time
+}
+
+fn skip(reader: &mut impl Seek, length: i64) -> Option<()> {
+    reader.seek(SeekFrom::Current(length)).ok().map(|_| ())
+}
+
+fn png_time(data: [u8; 7]) -> Option<i64> {
+    let year = i32::from(u16::from_be_bytes([data[0], data[1]]));
+    let time = NaiveDate::from_ymd_opt(year, u32::from(data[2]), u32::from(data[3]))?.and_hms_opt(
+        u32::from(data[4]),
+        u32::from(data[5]),
+        u32::from(data[6]),
+    )?;
+    Some(time.and_utc().timestamp())
+}
+
+fn png_text_creation_time(chunk_type: &[u8], data: &[u8]) -> Option<i64> {
+    let (keyword, text) = data.split_at(data.iter().position(|&byte| byte == 0)?);
+    if keyword != PNG_CREATION_TIME_KEYWORD {
+        return None;
+    }
+    let text = &text[1..];
+
+    let text = if chunk_type == b"iTXt" {
+        // Compressed international text is rare for timestamps, so it isn't supported
+        let (&compressed, text) = text.split_first()?;
+        if compressed != 0 {
+            return None;
+        }
+        // Skip the compression method, language tag and translated keyword
+        let text = text.get(1..)?;
+        let text = &text[text.iter().position(|&byte| byte == 0)? + 1..];
+        &text[text.iter().position(|&byte| byte == 0)? + 1..]
+    } else {
+        text
+    };
+
+    parse_text_time(std::str::from_utf8(text).ok()?.trim())
+}
+
+fn parse_text_time(text: &str) -> Option<i64> {
+    if let Ok(time) = DateTime::parse_from_rfc2822(text) {
+        return Some(time.timestamp());
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.timestamp());
+    }
+
+    [
+        "%Y:%m:%d %H:%M:%S",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+    .and_then(|naive| local_timestamp(&naive))
+}
+
+fn local_timestamp(naive: &NaiveDateTime) -> Option<i64> {
+    Local
+        .from_local_datetime(naive)
+        .earliest()
+        .map(|time| time.timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::Field;
+    use exif::experimental::Writer;
+
+    /// A PNG with an empty IHDR chunk followed by `chunks`. CRCs are left as zeros, as they
+    /// aren't checked.
+    fn png(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        for (chunk_type, data) in [(b"IHDR", &[0; 13][..])].iter().chain(chunks) {
+            png.extend(u32::try_from(data.len()).unwrap().to_be_bytes());
+            png.extend(*chunk_type);
+            png.extend(*data);
+            png.extend([0; 4]);
+        }
+        png.extend([0, 0, 0, 0]);
+        png.extend(b"IEND");
+        png.extend([0; 4]);
+        png
+    }
+
+    fn text_chunk(text: &str) -> Vec<u8> {
+        [PNG_CREATION_TIME_KEYWORD, b"\0", text.as_bytes()].concat()
+    }
+
+    /// 2024-05-06 07:08:09 UTC
+    const TIME_CHUNK: [u8; 7] = [0x07, 0xe8, 5, 6, 7, 8, 9];
+    const TIME_CHUNK_TIMESTAMP: i64 = 1_714_979_289;
+
+    /// A JPEG whose only segment is an EXIF APP1 segment with `fields`.
+    fn jpeg_with_exif(fields: &[Field]) -> Vec<u8> {
+        let mut writer = Writer::new();
+        for field in fields {
+            writer.push_field(field);
+        }
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        let tiff = tiff.into_inner();
+
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1];
+        jpeg.extend(u16::try_from(tiff.len() + 8).unwrap().to_be_bytes());
+        jpeg.extend(b"Exif\0\0");
+        jpeg.extend(tiff);
+        jpeg.extend([0xff, 0xd9]);
+        jpeg
+    }
+
+    fn ascii_field(tag: Tag, text: &str) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![text.as_bytes().to_vec()]),
+        }
+    }
+
+    fn metadata_time(bytes: &[u8]) -> Option<i64> {
+        capture_time_of_bytes("shot", bytes, None, CaptureTimeSource::Metadata)
+    }
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> i64 {
+        let naive = NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, second)
+            .unwrap();
+        local_timestamp(&naive).unwrap()
+    }
+
+    #[test]
+    fn reads_creation_time_text_chunks() {
+        let text = text_chunk("2024-05-06T09:08:09+02:00");
+        let png = png(&[(b"tIME", &[0x07, 0xd0, 1, 1, 0, 0, 0]), (b"tEXt", &text)]);
+        assert_eq!(metadata_time(&png), Some(TIME_CHUNK_TIMESTAMP));
+    }
+
+    #[test]
+    fn reads_uncompressed_international_text_chunks() {
+        let itxt = [
+            PNG_CREATION_TIME_KEYWORD,
+            b"\0\0\0en\0Creation Time\0",
+            b"Mon, 06 May 2024 07:08:09 +0000",
+        ]
+        .concat();
+        assert_eq!(
+            metadata_time(&png(&[(b"iTXt", &itxt)])),
+            Some(TIME_CHUNK_TIMESTAMP)
+        );
+
+        let compressed = [PNG_CREATION_TIME_KEYWORD, b"\0\x01\0\0\0x"].concat();
+        assert_eq!(
+            png_capture_time(Cursor::new(png(&[(b"iTXt", &compressed)]))),
+            None
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_modification_time_chunk() {
+        let other_text = b"Software\0Game".to_vec();
+        let png = png(&[(b"tEXt", &other_text), (b"tIME", &TIME_CHUNK)]);
+        assert_eq!(metadata_time(&png), Some(TIME_CHUNK_TIMESTAMP));
+    }
+
+    #[test]
+    fn skips_oversized_text_chunks() {
+        let mut png = png(&[(b"tIME", &TIME_CHUNK)]);
+        // An oversized chunk in place of IEND, which isn't read into memory
+        let end = png.len() - 12;
+        png.truncate(end);
+        png.extend((MAX_PNG_TEXT_LENGTH + 1).to_be_bytes());
+        png.extend(b"tEXt");
+        png.extend(PNG_CREATION_TIME_KEYWORD);
+        assert_eq!(
+            png_capture_time(Cursor::new(png)),
+            Some(TIME_CHUNK_TIMESTAMP)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_pngs() {
+        let mut truncated = png(&[(b"tIME", &TIME_CHUNK)]);
+        // Cut off in the middle of the tIME chunk
+        truncated.truncate(PNG_SIGNATURE.len() + 25 + 8 + 3);
+        assert_eq!(png_capture_time(Cursor::new(truncated)), None);
+        assert_eq!(png_capture_time(Cursor::new(b"not a png".to_vec())), None);
+    }
+
+    #[test]
+    fn reads_exif_capture_times_with_their_offset() {
+        let jpeg = jpeg_with_exif(&[
+            ascii_field(Tag::DateTimeOriginal, "2024:05:06 09:08:09"),
+            ascii_field(Tag::OffsetTimeOriginal, "+02:00"),
+        ]);
+        assert_eq!(metadata_time(&jpeg), Some(TIME_CHUNK_TIMESTAMP));
+    }
+
+    #[test]
+    fn exif_capture_times_without_offset_are_local() {
+        let jpeg = jpeg_with_exif(&[ascii_field(Tag::DateTimeOriginal, "2024:05:06 07:08:09")]);
+        assert_eq!(metadata_time(&jpeg), Some(local(2024, 5, 6, 7, 8, 9)));
+
+        // The offset of the modification time doesn't apply to the original time
+        let jpeg = jpeg_with_exif(&[
+            ascii_field(Tag::DateTimeOriginal, "2024:05:06 07:08:09"),
+            ascii_field(Tag::OffsetTime, "+02:00"),
+        ]);
+        assert_eq!(metadata_time(&jpeg), Some(local(2024, 5, 6, 7, 8, 9)));
+    }
+
+    #[test]
+    fn falls_back_to_the_exif_modification_time() {
+        let jpeg = jpeg_with_exif(&[
+            ascii_field(Tag::DateTime, "2024:05:06 10:08:09"),
+            ascii_field(Tag::OffsetTime, "+03:00"),
+        ]);
+        assert_eq!(metadata_time(&jpeg), Some(TIME_CHUNK_TIMESTAMP));
+    }
+
+    #[test]
+    fn parses_text_time_formats() {
+        assert_eq!(
+            parse_text_time("Mon, 06 May 2024 07:08:09 +0000"),
+            Some(TIME_CHUNK_TIMESTAMP)
+        );
+        assert_eq!(
+            parse_text_time("2024-05-06T07:08:09Z"),
+            Some(TIME_CHUNK_TIMESTAMP)
+        );
+        let expected = Some(local(2024, 5, 6, 7, 8, 9));
+        assert_eq!(parse_text_time("2024:05:06 07:08:09"), expected);
+        assert_eq!(parse_text_time("2024-05-06 07:08:09"), expected);
+        assert_eq!(parse_text_time("2024-05-06T07:08:09"), expected);
+        assert_eq!(parse_text_time("yesterday"), None);
+    }
+
+    #[test]
+    fn sources_fall_back_to_the_modification_time() {
+        let modified = Some(TIME_CHUNK_TIMESTAMP);
+        let time = |source| capture_time_of_bytes("shot", b"no metadata", modified, source);
+        assert_eq!(time(CaptureTimeSource::ImportTime), None);
+        assert_eq!(time(CaptureTimeSource::Metadata), modified);
+        assert_eq!(time(CaptureTimeSource::FileModified), modified);
+    }
+}
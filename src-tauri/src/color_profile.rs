@@ -0,0 +1,397 @@
+use crate::tone_map::{HdrTransfer, Primaries};
+use image::{DynamicImage, ImageBuffer, Pixel};
+use moxcms::{
+    Chromaticity, CicpColorPrimaries, CicpProfile, CmsError, ColorPrimaries, ColorProfile,
+    DataColorSpace, Layout, MatrixCoefficients, ToneReprCurve, TransferCharacteristics,
+    TransformExecutor, TransformOptions,
+};
+use std::io::{BufRead, Seek, SeekFrom};
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// Where the colors of a screenshot are defined.
+#[derive(Debug, Default)]
+pub enum SourceProfile {
+    /// No color information, or sRGB
+    #[default]
+    Srgb,
+    /// An embedded ICC profile
+    Icc(Vec<u8>),
+    /// A PNG cICP chunk: color primaries, transfer characteristics, matrix coefficients and
+    /// whether the full range is used
+    Cicp([u8; 4]),
+    /// A PNG gAMA chunk, with the white point and primaries of the cHRM chunk as xy pairs
+    Gamma {
+        gamma: f32,
+        chromaticities: Option<[f32; 8]>,
+    },
+}
+
+impl SourceProfile {
+    /// Reads the color chunks of a PNG and picks the one that takes precedence with its ICC
+    /// profile, in the order cICP, iCCP, sRGB and then gAMA with cHRM.
+    pub fn of_png(mut reader: impl BufRead + Seek, icc: Option<Vec<u8>>) -> Self {
+        let mut signature = [0; 8];
+        if reader.read_exact(&mut signature).is_err() || &signature != PNG_SIGNATURE {
+            return icc.map_or(Self::Srgb, Self::Icc);
+        }
+
+        let mut srgb = false;
+        let mut gamma = None;
+        let mut chromaticities = None;
+        // Color chunks always come before the image data
+        while let Some((chunk_type, data)) = read_chunk(&mut reader) {
+            match (&chunk_type, data.as_slice()) {
+                (b"cICP", &[primaries, transfer, matrix, full_range]) => {
+                    return Self::Cicp([primaries, transfer, matrix, full_range]);
+                }
+                (b"sRGB", _) => srgb = true,
+                (b"gAMA", &[a, b, c, d]) => gamma = Some(png_fixed_point([a, b, c, d])),
+                (b"cHRM", data) if data.len() == 32 => {
+                    let mut values = [0.0; 8];
+                    for (value, bytes) in values.iter_mut().zip(data.chunks_exact(4)) {
+                        *value = png_fixed_point([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                    }
+                    chromaticities = Some(values);
+                }
+                _ => {}
+            }
+        }
+
+        match (icc, gamma) {
+            (Some(icc), _) => Self::Icc(icc),
+            (None, Some(gamma)) if !srgb && gamma > 0.0 => Self::Gamma {
+                gamma,
+                chromaticities,
+            },
+            _ => Self::Srgb,
+        }
+    }
+
+    /// How the colors of an HDR screenshot are encoded, if its cICP chunk or the cICP tag of its
+    /// ICC profile says so. Its colors are left to tone mapping, which converts them from these
+    /// primaries.
+    #[must_use]
+    pub fn hdr_signal(&self) -> Option<(HdrTransfer, Primaries)> {
+        match self {
+            Self::Cicp([primaries, transfer, ..]) => cicp_hdr_signal(*primaries, *transfer),
+            Self::Icc(icc) => {
+                let cicp = ColorProfile::new_from_slice(icc).ok()?.cicp?;
+                cicp_hdr_signal(
+                    cicp.color_primaries as u8,
+                    cicp.transfer_characteristics as u8,
+                )
+            }
+            Self::Srgb | Self::Gamma { .. } => None,
+        }
+    }
+
+    /// The profile to convert from, or `None` if the colors are sRGB or have to be tone mapped.
+    fn color_profile(&self) -> Result<Option<ColorProfile>, String> {
+        match self {
+            Self::Srgb => Ok(None),
+            Self::Icc(_) if self.hdr_signal().is_some() => Ok(None),
+            Self::Icc(icc) => ColorProfile::new_from_slice(icc)
+                .map(Some)
+                .map_err(|error| format!("Failed to read ICC profile: {error}")),
+            Self::Cicp([primaries, transfer, matrix, full_range]) => {
+                if self.hdr_signal().is_some() {
+                    return Ok(None);
+                }
+                let error = |error: CmsError| format!("Unsupported cICP chunk: {error}");
+                let mut profile = ColorProfile::new_srgb();
+                profile.update_rgb_colorimetry_from_cicp(CicpProfile {
+                    color_primaries: CicpColorPrimaries::try_from(*primaries).map_err(error)?,
+                    transfer_characteristics: TransferCharacteristics::try_from(*transfer)
+                        .map_err(error)?,
+                    matrix_coefficients: MatrixCoefficients::try_from(*matrix).map_err(error)?,
+                    full_range: *full_range != 0,
+                });
+                Ok(Some(profile))
+            }
+            Self::Gamma {
+                gamma,
+                chromaticities,
+            } => {
+                // Browsers treat a gamma of 1/2.2 without chromaticities as sRGB, so it's
+                // what the screenshot was meant to look like
+                if chromaticities.is_none() && (gamma - 1.0 / 2.2).abs() < 0.001 {
+                    return Ok(None);
+                }
+                let mut profile = ColorProfile::new_srgb();
+                if let Some(
+                    [
+                        white_x,
+                        white_y,
+                        red_x,
+                        red_y,
+                        green_x,
+                        green_y,
+                        blue_x,
+                        blue_y,
+                    ],
+                ) = *chromaticities
+                {
+                    let white_point = Chromaticity {
+                        x: white_x,
+                        y: white_y,
+                    };
+                    profile.update_rgb_colorimetry(
+                        white_point.to_xyyb(),
+                        ColorPrimaries {
+                            red: Chromaticity { x: red_x, y: red_y },
+                            green: Chromaticity {
+                                x: green_x,
+                                y: green_y,
+                            },
+                            blue: Chromaticity {
+                                x: blue_x,
+                                y: blue_y,
+                            },
+                        },
+                    );
+                }
+                let curve = ToneReprCurve::Parametric(vec![1.0 / gamma]);
+                profile.cicp = None;
+                profile.red_trc = Some(curve.clone());
+                profile.green_trc = Some(curve.clone());
+                profile.blue_trc = Some(curve);
+                Ok(Some(profile))
+            }
+        }
+    }
+}
+
+/// The transfer and primaries of cICP values, if the transfer is PQ or HLG.
+fn cicp_hdr_signal(primaries: u8, transfer: u8) -> Option<(HdrTransfer, Primaries)> {
+    let transfer = match transfer {
+        16 => HdrTransfer::Pq,
+        18 => HdrTransfer::Hlg,
+        _ => return None,
+    };
+    // HDR is almost always BT.2020, so that's assumed for anything else
+    let primaries = match primaries {
+        1 => Primaries::Bt709,
+        12 => Primaries::DisplayP3,
+        _ => Primaries::Bt2020,
+    };
+    Some((transfer, primaries))
+}
+
+fn read_chunk(reader: &mut (impl BufRead + Seek)) -> Option<([u8; 4], Vec<u8>)> {
+    let mut header = [0; 8];
+    reader.read_exact(&mut header).ok()?;
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let chunk_type = [header[4], header[5], header[6], header[7]];
+    if &chunk_type == b"IDAT" || &chunk_type == b"IEND" {
+        return None;
+    }
+
+    let data = if length <= 32 && matches!(&chunk_type, b"cICP" | b"sRGB" | b"gAMA" | b"cHRM") {
+        let mut data = vec![0; length as usize];
+        reader.read_exact(&mut data).ok()?;
+        data
+    } else {
+        reader.seek(SeekFrom::Current(i64::from(length))).ok()?;
+        Vec::new()
+    };
+    // Skip the chunk's CRC
+    reader.seek(SeekFrom::Current(4)).ok()?;
+    Some((chunk_type, data))
+}
+
+/// Reads a PNG value stored as an integer times 100000.
+fn png_fixed_point(bytes: [u8; 4]) -> f32 {
+    #[allow(clippy::cast_precision_loss)]
+    let value = u32::from_be_bytes(bytes) as f32;
+    value / 100_000.0
+}
+
+/// Converts a screenshot to sRGB from the color space of `source`, so wide gamut screenshots
+/// don't look washed out once their profile is gone. Returns `None` if the screenshot is already
+/// sRGB, or if it's a float or HDR image whose colors are left to tone mapping.
+///
+/// # Errors
+///
+/// Returns an error if the profile is invalid or not supported.
+pub fn to_srgb(img: &DynamicImage, source: &SourceProfile) -> Result<Option<DynamicImage>, String> {
+    let Some(profile) = conversion_profile(source)? else {
+        return Ok(None);
+    };
+
+    let srgb = ColorProfile::new_srgb();
+    let has_alpha = img.color().has_alpha();
+    let layout = if has_alpha { Layout::Rgba } else { Layout::Rgb };
+    let options = TransformOptions::default();
+    let error = |error: CmsError| format!("Failed to convert to sRGB: {error}");
+
+    let converted = match img {
+        DynamicImage::ImageLuma8(_)
+        | DynamicImage::ImageLumaA8(_)
+        | DynamicImage::ImageRgb8(_)
+        | DynamicImage::ImageRgba8(_) => {
+            let transform = profile
+                .create_transform_8bit(layout, &srgb, layout, options)
+                .map_err(error)?;
+            if has_alpha {
+                DynamicImage::ImageRgba8(transform_buffer(&img.to_rgba8(), &*transform)?)
+            } else {
+                DynamicImage::ImageRgb8(transform_buffer(&img.to_rgb8(), &*transform)?)
+            }
+        }
+        DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => {
+            let transform = profile
+                .create_transform_16bit(layout, &srgb, layout, options)
+                .map_err(error)?;
+            if has_alpha {
+                DynamicImage::ImageRgba16(transform_buffer(&img.to_rgba16(), &*transform)?)
+            } else {
+                DynamicImage::ImageRgb16(transform_buffer(&img.to_rgb16(), &*transform)?)
+            }
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(converted))
+}
+
+/// Whether [`to_srgb`] would convert the colors of an 8-bit or 16-bit screenshot from `source`,
+/// which can be known before its pixels are decoded.
+#[must_use]
+pub fn needs_conversion(source: &SourceProfile) -> bool {
+    matches!(conversion_profile(source), Ok(Some(_)))
+}
+
+/// The RGB profile to convert from, or `None` if the colors are already sRGB.
+fn conversion_profile(source: &SourceProfile) -> Result<Option<ColorProfile>, String> {
+    let Some(profile) = source.color_profile()? else {
+        return Ok(None);
+    };
+    if profile.color_space != DataColorSpace::Rgb || is_srgb(&profile)? {
+        return Ok(None);
+    }
+    Ok(Some(profile))
+}
+
+/// Whether converting from `profile` to sRGB changes any color by more than rounding, as many
+/// screenshots are tagged with some version of an sRGB profile.
+fn is_srgb(profile: &ColorProfile) -> Result<bool, String> {
+    let transform = profile
+        .create_transform_8bit(
+            Layout::Rgb,
+            &ColorProfile::new_srgb(),
+            Layout::Rgb,
+            TransformOptions::default(),
+        )
+        .map_err(|error| format!("Unsupported color profile: {error}"))?;
+
+    let levels: Vec<u8> = (0..=u8::MAX).step_by(17).collect();
+    let mut samples = Vec::with_capacity(levels.len().pow(3) * 3);
+    for &red in &levels {
+        for &green in &levels {
+            for &blue in &levels {
+                samples.extend([red, green, blue]);
+            }
+        }
+    }
+    let mut converted = vec![0; samples.len()];
+    transform
+        .transform(&samples, &mut converted)
+        .map_err(|error| format!("Failed to convert to sRGB: {error}"))?;
+
+    Ok(samples
+        .iter()
+        .zip(&converted)
+        .all(|(sample, converted)| sample.abs_diff(*converted) <= 1))
+}
+
+fn transform_buffer<P: Pixel>(
+    src: &ImageBuffer<P, Vec<P::Subpixel>>,
+    transform: &dyn TransformExecutor<P::Subpixel>,
+) -> Result<ImageBuffer<P, Vec<P::Subpixel>>, String>
+where
+    P::Subpixel: Default,
+{
+    let mut dst = ImageBuffer::new(src.width(), src.height());
+    transform
+        .transform(src, &mut dst)
+        .map_err(|error| format!("Failed to convert to sRGB: {error}"))?;
+    Ok(dst)
+}
+
+/// An sRGB ICC profile, to embed in re-encoded JPEGs.
+///
+/// # Errors
+///
+/// Returns an error if the profile could not be encoded.
+pub fn srgb_icc_profile() -> Result<Vec<u8>, String> {
+    ColorProfile::new_srgb()
+        .encode()
+        .map_err(|error| format!("Failed to encode sRGB profile: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A PNG signature and IHDR chunk followed by `chunks`, which is all [`SourceProfile::of_png`]
+    /// reads. CRCs are left as zeros, as they aren't checked.
+    fn png_with_chunks(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        for (chunk_type, data) in [(b"IHDR", &[0; 13][..])].iter().chain(chunks) {
+            png.extend(u32::try_from(data.len()).unwrap().to_be_bytes());
+            png.extend(*chunk_type);
+            png.extend(*data);
+            png.extend([0; 4]);
+        }
+        png.extend([0, 0, 0, 0]);
+        png.extend(b"IEND");
+        png
+    }
+
+    #[test]
+    fn reads_cicp_chunks_before_other_color_chunks() {
+        let png = png_with_chunks(&[(b"gAMA", &[0, 0, 177, 143]), (b"cICP", &[9, 16, 0, 1])]);
+        let profile = SourceProfile::of_png(Cursor::new(png), None);
+        assert!(matches!(profile, SourceProfile::Cicp([9, 16, 0, 1])));
+    }
+
+    #[test]
+    fn hdr_cicp_chunks_are_left_to_tone_mapping() {
+        let pq = SourceProfile::Cicp([9, 16, 0, 1]);
+        assert_eq!(pq.hdr_signal(), Some((HdrTransfer::Pq, Primaries::Bt2020)));
+        assert!(!needs_conversion(&pq));
+
+        let hlg = SourceProfile::Cicp([12, 18, 0, 1]);
+        assert_eq!(
+            hlg.hdr_signal(),
+            Some((HdrTransfer::Hlg, Primaries::DisplayP3))
+        );
+    }
+
+    #[test]
+    fn hdr_icc_profiles_are_left_to_tone_mapping() {
+        let icc = ColorProfile::new_bt2020_hlg().encode().unwrap();
+        let hlg = SourceProfile::Icc(icc);
+        assert_eq!(
+            hlg.hdr_signal(),
+            Some((HdrTransfer::Hlg, Primaries::Bt2020))
+        );
+        assert!(!needs_conversion(&hlg));
+
+        let icc = ColorProfile::new_display_p3().encode().unwrap();
+        assert_eq!(SourceProfile::Icc(icc).hdr_signal(), None);
+    }
+
+    #[test]
+    fn sdr_cicp_chunks_are_converted() {
+        let display_p3 = SourceProfile::Cicp([12, 13, 0, 1]);
+        assert_eq!(display_p3.hdr_signal(), None);
+        assert!(needs_conversion(&display_p3));
+        assert!(!needs_conversion(&SourceProfile::Cicp([1, 13, 0, 1])));
+    }
+}
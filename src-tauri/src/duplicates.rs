@@ -0,0 +1,215 @@
+use crate::steam_locate::most_recent_account_id;
+use crate::userdata::{app_screenshots_dir, file_sha256};
+use image::{DynamicImage, ImageReader};
+use log::{info, warn};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::read_dir;
+use std::path::Path;
+use std::sync::Mutex;
+
+pub const DEFAULT_DUPLICATE_THRESHOLD: u32 = 4;
+
+/// What to do with screenshots that already exist in the Steam library.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Deserialize,
+    Serialize,
+    specta::Type,
+    clap::ValueEnum,
+)]
+pub enum DuplicatePolicy {
+    /// Import every screenshot without looking for duplicates
+    #[default]
+    Import,
+    /// Skip screenshots that are identical or very similar to one already in the library
+    Skip,
+    /// Import duplicates anyway, but report them
+    Report,
+}
+
+/// A screenshot in the library that an imported screenshot matched.
+pub struct DuplicateMatch {
+    pub file_name: String,
+    /// Number of differing perceptual hash bits, 0 for identical files
+    pub distance: u32,
+}
+
+struct IndexedScreenshot {
+    file_name: String,
+    sha256: Option<String>,
+    perceptual_hash: Option<u64>,
+}
+
+/// Content and perceptual hashes of the screenshots in a game's Steam library.
+pub struct DuplicateIndex {
+    screenshots: Mutex<Vec<IndexedScreenshot>>,
+    threshold: u32,
+}
+
+impl DuplicateIndex {
+    /// Hashes the screenshots the most recent Steam user already has for `app_id`.
+    pub fn load(app_id: u32, threshold: u32) -> Result<Self, String> {
+        let screenshots_dir = app_screenshots_dir(most_recent_account_id()?, app_id)?;
+        let thumbnails_dir = screenshots_dir.join("thumbnails");
+        info!(
+            "Hashing existing screenshots in {}",
+            screenshots_dir.display()
+        );
+
+        let entries = match read_dir(&screenshots_dir) {
+            Ok(entries) => entries.filter_map(Result::ok).collect(),
+            Err(error) => {
+                warn!(
+                    "Failed to read {}, not checking for duplicates: {error}",
+                    screenshots_dir.display()
+                );
+                Vec::new()
+            }
+        };
+
+        let screenshots = entries
+            .par_iter()
+            .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+            .map(|entry| {
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                // Steam's thumbnails are much faster to decode and hash the same as the original
+                let thumbnail_path = thumbnails_dir.join(&file_name);
+                let hash_source = if thumbnail_path.is_file() {
+                    thumbnail_path
+                } else {
+                    entry.path()
+                };
+
+                IndexedScreenshot {
+                    sha256: file_sha256(&entry.path()).ok(),
+                    perceptual_hash: decode(&hash_source).ok().map(|img| perceptual_hash(&img)),
+                    file_name,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        info!("Hashed {} existing screenshots", screenshots.len());
+        Ok(Self {
+            screenshots: Mutex::new(screenshots),
+            threshold,
+        })
+    }
+
+    /// Finds the library screenshot with exactly the same contents as a screenshot with the
+    /// `sha256` content hash, or else the one that looks the most like it within the threshold.
+    pub fn find(&self, sha256: Option<&str>, perceptual_hash: u64) -> Option<DuplicateMatch> {
+        let screenshots = self.screenshots.lock().ok()?;
+        let identical = screenshots
+            .iter()
+            .find(|screenshot| sha256.is_some() && screenshot.sha256.as_deref() == sha256)
+            .map(|screenshot| DuplicateMatch {
+                file_name: screenshot.file_name.clone(),
+                distance: 0,
+            });
+        identical.or_else(|| {
+            screenshots
+                .iter()
+                .filter_map(|screenshot| {
+                    let distance = (screenshot.perceptual_hash? ^ perceptual_hash).count_ones();
+                    (distance <= self.threshold).then(|| DuplicateMatch {
+                        file_name: screenshot.file_name.clone(),
+                        distance,
+                    })
+                })
+                .min_by_key(|duplicate| duplicate.distance)
+        })
+    }
+
+    /// Adds a newly imported screenshot so later files in the same import are checked against it.
+    pub fn insert(&self, file_name: String, sha256: Option<String>, perceptual_hash: u64) {
+        if let Ok(mut screenshots) = self.screenshots.lock() {
+            screenshots.push(IndexedScreenshot {
+                file_name,
+                sha256,
+                perceptual_hash: Some(perceptual_hash),
+            });
+        }
+    }
+}
+
+fn decode(path: &Path) -> Result<DynamicImage, String> {
+    ImageReader::open(path)
+        .map_err(|error| error.to_string())?
+        .decode()
+        .map_err(|error| error.to_string())
+}
+
+/// Computes a 64-bit difference hash, which stays the same when an image is resized or
+/// re-encoded.
+pub fn perceptual_hash(img: &DynamicImage) -> u64 {
+    let pixels = img.thumbnail_exact(9, 8).into_luma8();
+    let mut hash = 0;
+
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if pixels.get_pixel(x, y)[0] < pixels.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+            Luma([u8::try_from((x * 7 + y * 3) % 256).unwrap()])
+        }))
+    }
+
+    fn index(threshold: u32) -> DuplicateIndex {
+        DuplicateIndex {
+            screenshots: Mutex::new(Vec::new()),
+            threshold,
+        }
+    }
+
+    #[test]
+    fn perceptual_hash_survives_resizing() {
+        let original = perceptual_hash(&gradient(1920, 1080));
+        let resized = perceptual_hash(&gradient(1920, 1080).thumbnail(480, 270));
+
+        assert!((original ^ resized).count_ones() <= DEFAULT_DUPLICATE_THRESHOLD);
+    }
+
+    #[test]
+    fn finds_inserted_screenshots() {
+        let index = index(DEFAULT_DUPLICATE_THRESHOLD);
+        assert!(index.find(Some("abc"), 0b1010).is_none());
+
+        index.insert("first.jpg".to_string(), Some("abc".to_string()), 0b1010);
+
+        let similar = index.find(Some("def"), 0b1011).unwrap();
+        assert_eq!(similar.file_name, "first.jpg");
+        assert_eq!(similar.distance, 1);
+        assert!(index.find(None, u64::MAX).is_none());
+    }
+
+    #[test]
+    fn prefers_identical_screenshots() {
+        let index = index(DEFAULT_DUPLICATE_THRESHOLD);
+        index.insert("similar.jpg".to_string(), None, 0);
+        index.insert("identical.jpg".to_string(), Some("abc".to_string()), 0b111);
+
+        let duplicate = index.find(Some("abc"), 0).unwrap();
+        assert_eq!(duplicate.file_name, "identical.jpg");
+        assert_eq!(duplicate.distance, 0);
+    }
+}
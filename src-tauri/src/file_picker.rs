@@ -1,27 +1,49 @@
-#[tauri::command]
-#[specta::specta]
-pub fn pick_screenshot_files() -> Vec<String> {
+use crate::archive::ARCHIVE_EXTENSIONS;
+use crate::image_import::SUPPORTED_EXTENSIONS;
+
+/// A file dialog that starts in the user's pictures folder.
+fn dialog() -> rfd::FileDialog {
     let default_dir = directories::UserDirs::new()
         .and_then(|dirs| dirs.picture_dir().map(std::path::Path::to_path_buf));
 
-    let mut dialog = rfd::FileDialog::new()
-        .set_title("Select screenshots to import")
-        .add_filter(
-            "Images",
-            &[
-                "png", "jpg", "jpeg", "bmp", "ico", "tiff", "tif", "webp", "avif", "pnm", "dds",
-                "tga", "exr",
-            ],
-        );
-
-    if let Some(dir) = default_dir {
-        dialog = dialog.set_directory(dir);
+    let dialog = rfd::FileDialog::new();
+    match default_dir {
+        Some(dir) => dialog.set_directory(dir),
+        None => dialog,
     }
+}
 
-    dialog
+#[tauri::command]
+#[specta::specta]
+pub fn pick_screenshot_files() -> Vec<String> {
+    dialog()
+        .set_title("Select screenshots to import")
+        .add_filter("Images", SUPPORTED_EXTENSIONS)
+        .add_filter("Archives", ARCHIVE_EXTENSIONS)
         .pick_files()
         .unwrap_or_default()
         .into_iter()
         .map(|p| p.to_string_lossy().to_string())
         .collect()
 }
+
+#[tauri::command]
+#[specta::specta]
+pub fn pick_screenshot_folders() -> Vec<String> {
+    dialog()
+        .set_title("Select folders to import")
+        .pick_folders()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|p| p.to_string_lossy().to_string())
+        .collect()
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn pick_folder() -> Option<String> {
+    dialog()
+        .set_title("Select a folder")
+        .pick_folder()
+        .map(|p| p.to_string_lossy().to_string())
+}
@@ -0,0 +1,291 @@
+use crate::archive::{self, is_archive};
+use crate::capture_time::{CaptureTimeSource, capture_time};
+use crate::image_import::SUPPORTED_EXTENSIONS;
+use chrono::{Local, NaiveDate, TimeZone};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use walkdir::WalkDir;
+
+/// Which screenshots in the selected folders and archives are imported. Files that are selected
+/// directly are always imported.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct FileFilter {
+    /// Glob patterns of which a screenshot has to match at least one, or all screenshots if empty
+    include: Vec<String>,
+    /// Glob patterns of screenshots to leave out
+    exclude: Vec<String>,
+    /// Earliest capture date to import, as `YYYY-MM-DD`
+    captured_after: Option<String>,
+    /// Latest capture date to import, as `YYYY-MM-DD`
+    captured_before: Option<String>,
+}
+
+impl FileFilter {
+    #[must_use]
+    pub fn include(mut self, patterns: Vec<String>) -> Self {
+        self.include = patterns;
+        self
+    }
+
+    #[must_use]
+    pub fn exclude(mut self, patterns: Vec<String>) -> Self {
+        self.exclude = patterns;
+        self
+    }
+
+    #[must_use]
+    pub fn captured_between(mut self, after: Option<String>, before: Option<String>) -> Self {
+        self.captured_after = after;
+        self.captured_before = before;
+        self
+    }
+}
+
+/// A [`FileFilter`] with its patterns and dates parsed.
+struct CompiledFilter {
+    include: GlobSet,
+    exclude: GlobSet,
+    captured_after: Option<NaiveDate>,
+    captured_before: Option<NaiveDate>,
+}
+
+impl CompiledFilter {
+    fn new(filter: &FileFilter) -> Result<Self, String> {
+        let date = |date: &Option<String>| {
+            date.as_deref()
+                .map(str::trim)
+                .filter(|date| !date.is_empty())
+                .map(|date| {
+                    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                        .map_err(|error| format!("Invalid date {date}: {error}"))
+                })
+                .transpose()
+        };
+
+        Ok(Self {
+            include: compile_globs(&filter.include)?,
+            exclude: compile_globs(&filter.exclude)?,
+            captured_after: date(&filter.captured_after)?,
+            captured_before: date(&filter.captured_before)?,
+        })
+    }
+
+    /// Whether the screenshot at `relative_path` within a selected folder or archive should be
+    /// imported. `captured` is only called if its capture date is needed.
+    fn matches(&self, relative_path: &str, captured: impl FnOnce() -> Option<i64>) -> bool {
+        let included = self.include.is_empty() || self.include.is_match(relative_path);
+        if !included || self.exclude.is_match(relative_path) {
+            return false;
+        }
+        if self.captured_after.is_none() && self.captured_before.is_none() {
+            return true;
+        }
+
+        let Some(captured) = captured()
+            .and_then(|timestamp| Local.timestamp_opt(timestamp, 0).single())
+            .map(|time| time.date_naive())
+        else {
+            return false;
+        };
+        self.captured_after.is_none_or(|after| captured >= after)
+            && self.captured_before.is_none_or(|before| captured <= before)
+    }
+}
+
+/// Compiles glob patterns matched case-insensitively against `/`-separated relative paths, where
+/// `*` and `?` don't match separators and `**/` matches any number of folders. Patterns without a
+/// `/` only have to match the file name, like in `.gitignore`.
+fn compile_globs(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut globs = GlobSetBuilder::new();
+    for pattern in patterns.iter().map(|pattern| pattern.trim()) {
+        if pattern.is_empty() {
+            continue;
+        }
+        let pattern = pattern.replace('\\', "/");
+        let full_pattern = if pattern.contains('/') {
+            pattern.clone()
+        } else {
+            format!("**/{pattern}")
+        };
+        let glob = GlobBuilder::new(&full_pattern)
+            .case_insensitive(true)
+            .literal_separator(true)
+            .backslash_escape(false)
+            .build()
+            .map_err(|error| format!("Invalid pattern {pattern}: {error}"))?;
+        globs.add(glob);
+    }
+    globs
+        .build()
+        .map_err(|error| format!("Invalid patterns: {error}"))
+}
+
+fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            SUPPORTED_EXTENSIONS
+                .iter()
+                .any(|supported| supported.eq_ignore_ascii_case(extension))
+        })
+}
+
+/// Expands `paths` into the screenshots to import. Folders are searched recursively for files
+/// with a supported extension that pass `filter`, and archives are expanded into the paths of
+/// such entries (see [`archive::entry_path`]). Other files are kept as they are.
+///
+/// # Errors
+///
+/// Returns an error if a pattern or date in `filter` is invalid or an archive could not be read.
+pub fn expand_paths(paths: &[String], filter: &FileFilter) -> Result<Vec<String>, String> {
+    let filter = CompiledFilter::new(filter)?;
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+
+    for path in paths {
+        let root = Path::new(path);
+        if is_archive(root) {
+            let mut entries: Vec<String> = archive::list_entries(root)?
+                .into_iter()
+                .filter(|entry| filter.matches(&entry.name, || entry.modified))
+                .map(|entry| archive::entry_path(path, &entry.name))
+                .collect();
+            entries.sort_unstable();
+            files.extend(entries.into_iter().filter(|file| seen.insert(file.clone())));
+            continue;
+        }
+        if !root.is_dir() {
+            if seen.insert(path.clone()) {
+                files.push(path.clone());
+            }
+            continue;
+        }
+
+        let mut dir_files: Vec<String> = WalkDir::new(root)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file() && is_supported(entry.path()))
+            .filter(|entry| {
+                let relative_path = entry
+                    .path()
+                    .strip_prefix(root)
+                    .unwrap_or(entry.path())
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                filter.matches(&relative_path, || {
+                    capture_time(entry.path(), CaptureTimeSource::Metadata)
+                })
+            })
+            .map(|entry| entry.path().to_string_lossy().to_string())
+            .collect();
+        dir_files.sort_unstable();
+        files.extend(
+            dir_files
+                .into_iter()
+                .filter(|file| seen.insert(file.clone())),
+        );
+    }
+
+    Ok(files)
+}
+
+/// Lists the screenshots an import of `paths` with `filter` would include, without importing them.
+///
+/// # Errors
+///
+/// Returns an error if a pattern or date in `filter` is invalid or an archive could not be read.
+#[tauri::command]
+#[specta::specta]
+pub async fn preview_import(paths: Vec<String>, filter: FileFilter) -> Result<Vec<String>, String> {
+    // Walking folders and reading capture dates can take a while, so it's kept off the runtime
+    tauri::async_runtime::spawn_blocking(move || expand_paths(&paths, &filter))
+        .await
+        .map_err(|error| format!("Failed to search for screenshots: {error}"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(patterns: &[&str], path: &str) -> bool {
+        let patterns: Vec<String> = patterns.iter().map(ToString::to_string).collect();
+        compile_globs(&patterns).unwrap().is_match(path)
+    }
+
+    #[test]
+    fn star_stays_within_a_folder() {
+        assert!(matches(&["shots/*.png"], "shots/a.png"));
+        assert!(!matches(&["shots/*.png"], "shots/nested/a.png"));
+        assert!(!matches(&["*/a.png"], "one/two/a.png"));
+    }
+
+    #[test]
+    fn double_star_matches_any_folders() {
+        assert!(matches(&["**/thumbnails/**"], "thumbnails/a.png"));
+        assert!(matches(&["**/thumbnails/**"], "game/thumbnails/x/a.png"));
+        assert!(matches(&["shots/**/*.jpg"], "shots/a.jpg"));
+        assert!(matches(&["shots/**/*.jpg"], "shots/2024/01/a.jpg"));
+        assert!(!matches(&["shots/**/*.jpg"], "other/shots/a.jpg"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character_but_not_separators() {
+        assert!(matches(&["shot_?.png"], "shot_1.png"));
+        assert!(!matches(&["shot_?.png"], "shot_12.png"));
+        assert!(!matches(&["a?b/c.png"], "a/b/c.png"));
+    }
+
+    #[test]
+    fn patterns_without_separator_match_the_file_name() {
+        assert!(matches(&["*.PNG"], "deep/folder/shot.png"));
+        assert!(matches(&["shot.png"], "folder/shot.png"));
+        assert!(!matches(&["folder"], "folder/shot.png"));
+    }
+
+    #[test]
+    fn backslashes_are_separators() {
+        assert!(matches(&["shots\\*.png"], "shots/a.png"));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(matches(&["shot_[0-9].png"], "shot_5.png"));
+        assert!(!matches(&["shot_[!0-9].png"], "shot_5.png"));
+        assert!(matches(&["shot_[!0-9].png"], "shot_x.png"));
+    }
+
+    #[test]
+    fn many_stars_match_long_paths_quickly() {
+        let path = format!("{}c", "a".repeat(10_000));
+        assert!(!matches(&["*a*a*a*a*a*a*a*b"], &path));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(compile_globs(&["shot_[0-9.png".to_string()]).is_err());
+    }
+
+    #[test]
+    fn filters_by_pattern_and_capture_date() {
+        let filter = CompiledFilter::new(
+            &FileFilter::default()
+                .include(vec!["*.png".to_string(), " ".to_string()])
+                .exclude(vec!["thumbnails/**".to_string()])
+                .captured_between(Some("2024-01-01".to_string()), None),
+        )
+        .unwrap();
+        let new_year = Local
+            .with_ymd_and_hms(2024, 1, 1, 12, 0, 0)
+            .unwrap()
+            .timestamp();
+
+        assert!(filter.matches("a.png", || Some(new_year)));
+        assert!(!filter.matches("a.jpg", || Some(new_year)));
+        assert!(!filter.matches("thumbnails/a.png", || Some(new_year)));
+        assert!(!filter.matches("a.png", || Some(new_year - 86_400)));
+        assert!(!filter.matches("a.png", || None));
+    }
+}
@@ -0,0 +1,84 @@
+use crate::color_profile::SourceProfile;
+use image::{DynamicImage, ImageBuffer, Pixel};
+use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+/// Reads the size of the primary image of a HEIC/HEIF file from its header.
+pub fn dimensions(bytes: &[u8]) -> Result<(u32, u32), String> {
+    let context = HeifContext::read_from_bytes(bytes).map_err(|error| error.to_string())?;
+    let handle = context
+        .primary_image_handle()
+        .map_err(|error| error.to_string())?;
+    Ok((handle.width(), handle.height()))
+}
+
+/// Decodes the primary image of a HEIC/HEIF file, along with its ICC profile. Rotation and
+/// mirroring are applied by libheif while decoding.
+pub fn decode(bytes: &[u8]) -> Result<(DynamicImage, SourceProfile), String> {
+    let lib_heif = LibHeif::new();
+    let context = HeifContext::read_from_bytes(bytes).map_err(|error| error.to_string())?;
+    let handle = context
+        .primary_image_handle()
+        .map_err(|error| error.to_string())?;
+
+    let has_alpha = handle.has_alpha_channel();
+    let high_bit_depth = handle.luma_bits_per_pixel() > 8;
+    let chroma = match (high_bit_depth, has_alpha) {
+        (false, false) => RgbChroma::Rgb,
+        (false, true) => RgbChroma::Rgba,
+        (true, false) => RgbChroma::HdrRgbLe,
+        (true, true) => RgbChroma::HdrRgbaLe,
+    };
+    let image = lib_heif
+        .decode(&handle, ColorSpace::Rgb(chroma), None)
+        .map_err(|error| error.to_string())?;
+    let plane = image
+        .planes()
+        .interleaved
+        .ok_or("HEIC image has no interleaved plane")?;
+
+    // Rows can be padded up to their stride
+    let channels = if has_alpha { 4 } else { 3 };
+    let sample_size = if high_bit_depth { 2 } else { 1 };
+    let row_len = plane.width as usize * channels * sample_size;
+    let rows = plane
+        .data
+        .chunks(plane.stride)
+        .map(|row| &row[..row_len.min(row.len())]);
+
+    let img = if high_bit_depth {
+        // Samples are stored in the low bits, e.g. 0-1023 for 10-bit images
+        let shift = 16 - u32::from(plane.bit_depth).min(16);
+        let samples = rows
+            .flat_map(|row| row.chunks_exact(2))
+            .map(|sample| u16::from_le_bytes([sample[0], sample[1]]) << shift)
+            .collect();
+        if has_alpha {
+            DynamicImage::ImageRgba16(from_samples(plane.width, plane.height, samples)?)
+        } else {
+            DynamicImage::ImageRgb16(from_samples(plane.width, plane.height, samples)?)
+        }
+    } else {
+        let samples = rows.flatten().copied().collect();
+        if has_alpha {
+            DynamicImage::ImageRgba8(from_samples(plane.width, plane.height, samples)?)
+        } else {
+            DynamicImage::ImageRgb8(from_samples(plane.width, plane.height, samples)?)
+        }
+    };
+
+    let profile = handle
+        .color_profile_raw()
+        .map_or(SourceProfile::Srgb, |profile| {
+            SourceProfile::Icc(profile.data)
+        });
+    Ok((img, profile))
+}
+
+fn from_samples<P: Pixel>(
+    width: u32,
+    height: u32,
+    samples: Vec<P::Subpixel>,
+) -> Result<ImageBuffer<P, Vec<P::Subpixel>>, String> {
+    ImageBuffer::from_raw(width, height, samples)
+        .ok_or_else(|| "Decoded HEIC image has the wrong size".to_string())
+}
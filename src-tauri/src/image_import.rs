@@ -1,29 +1,139 @@
-use crate::AppRuntime;
+use crate::annotations::{Annotator, ImportAnnotations};
 use crate::app_dirs::PROJECT_DIRS;
-use crate::steam::{initialize_steam, open_steam_section};
+use crate::archive::{read_entries, split_entry_path};
+use crate::background::flatten;
+use crate::capture_time::{CaptureTimeSource, capture_time, capture_time_of_bytes};
+use crate::color_profile::{SourceProfile, needs_conversion, srgb_icc_profile, to_srgb};
+use crate::duplicates::{DuplicateIndex, DuplicateMatch, perceptual_hash};
+use crate::import_journal::{ImportJournal, JournalFileState};
+use crate::memory_budget::{MemoryBudget, MemoryPool, UNKNOWN_SIZE, estimated_size, float_size};
+use crate::metadata::{replace_jpeg_metadata, selected_exif};
+use crate::resample::{decode_jpeg_preview, resize_image, sharpen};
+use crate::screenshot_library::{Screenshot, ScreenshotHandle, ScreenshotLibrary};
+use crate::tone_map::{DEFAULT_WHITE_POINT, Primaries, to_sdr};
+use crate::userdata::file_sha256;
+use crate::vr::{VrLayout, VrScreenshotType};
 use atomic_float::AtomicF32;
 use image::codecs::jpeg::JpegEncoder;
-use image::imageops::{FilterType as ImageFilterType, resize};
-use image::{DynamicImage, ImageReader};
+use image::imageops::FilterType as ImageFilterType;
+use image::{
+    DynamicImage, GenericImageView, ImageDecoder, ImageEncoder, ImageFormat, ImageReader,
+    ImageResult, Limits, PixelWithColorType, metadata::Orientation,
+};
 use log::{error, info, warn};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::ffi::CString;
-use std::fs::{File, copy, create_dir, create_dir_all, remove_dir_all};
-use std::io::BufWriter;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs::{File, create_dir_all, read, remove_dir_all, write};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Seek};
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex, atomic::Ordering};
-use steamworks::sys::INVALID_SCREENSHOT_HANDLE;
-use steamworks::sys::SteamAPI_ISteamScreenshots_AddScreenshotToLibrary as add_screenshot_to_library;
-use steamworks::sys::SteamAPI_SteamScreenshots_v003 as get_steam_screenshots;
-use tauri::Emitter;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub use crate::background::Background;
+pub use crate::duplicates::{DEFAULT_DUPLICATE_THRESHOLD, DuplicatePolicy};
+pub use crate::memory_budget::DEFAULT_MEMORY_BUDGET_MB;
+pub use crate::metadata::MetadataPolicy;
+pub use crate::tone_map::{HdrTransfer, ToneMapOperator};
+pub use crate::vr::{StereoLayout, VrMode};
 
-const PROGRESS_EVENT: &str = "screenshotImportProgress";
 const THUMB_WIDTH: u32 = steamworks::sys::k_ScreenshotThumbWidth as u32;
 const MAX_SIDE: u32 = 16_000;
-const MAX_RESOLUTION: u32 = 26_210_175;
+pub(crate) const MAX_RESOLUTION: u32 = 26_210_175;
+/// Minimum width of the previews JPEGs that are copied as they are get decoded at, leaving the
+/// resize filter some detail to work with for the thumbnail.
+const PREVIEW_MIN_WIDTH: u32 = THUMB_WIDTH * 2;
+pub const DEFAULT_SHARPEN_RADIUS: f32 = 1.0;
+
+/// How often the library's callbacks are run while waiting for screenshots to be processed.
+const CALLBACK_INTERVAL: Duration = Duration::from_millis(100);
+/// How long Steam has to confirm that a screenshot was imported after it was added.
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of imports started by this process, used to give each one its own cache directory.
+static IMPORTS_STARTED: AtomicU32 = AtomicU32::new(0);
+
+/// File extensions of the image formats the importer can decode.
+pub const SUPPORTED_EXTENSIONS: &[&str] = &[
+    "png",
+    "jpg",
+    "jpeg",
+    "bmp",
+    "ico",
+    "tiff",
+    "tif",
+    "webp",
+    "avif",
+    "pnm",
+    "dds",
+    "tga",
+    "exr",
+    #[cfg(feature = "jxr")]
+    "jxr",
+    #[cfg(feature = "jxl")]
+    "jxl",
+    #[cfg(feature = "heic")]
+    "heic",
+    #[cfg(feature = "heic")]
+    "heif",
+];
+
+type Decoder = fn(&[u8]) -> Result<(DynamicImage, SourceProfile), String>;
+type DimensionsReader = fn(&[u8]) -> Result<(u32, u32), String>;
+
+/// Decoder for a format the `image` crate can't read. The size is read from the header first, as
+/// these decoders can't be given [`Limits`].
+#[derive(Clone, Copy)]
+struct ExtraDecoder {
+    dimensions: DimensionsReader,
+    decode: Decoder,
+}
+
+impl ExtraDecoder {
+    /// Decodes an image unless its header says it's too large for `budget`.
+    fn decode(
+        self,
+        bytes: &[u8],
+        file_name: &str,
+        budget: MemoryBudget,
+    ) -> Result<(DynamicImage, SourceProfile), String> {
+        let (width, height) = (self.dimensions)(bytes)
+            .map_err(|error| format!("Failed to decode {file_name}: {error}"))?;
+        budget.check_dimensions(file_name, width, height)?;
+        (self.decode)(bytes).map_err(|error| format!("Failed to decode {file_name}: {error}"))
+    }
+}
+
+/// Decoder for formats the `image` crate can't read, which are enabled with cargo features.
+fn extra_decoder(extension: &str) -> Option<ExtraDecoder> {
+    match extension.to_ascii_lowercase().as_str() {
+        #[cfg(feature = "jxr")]
+        "jxr" => Some(ExtraDecoder {
+            dimensions: crate::jxr::dimensions,
+            decode: crate::jxr::decode,
+        }),
+        #[cfg(feature = "jxl")]
+        "jxl" => Some(ExtraDecoder {
+            dimensions: crate::jxl::dimensions,
+            decode: crate::jxl::decode,
+        }),
+        #[cfg(feature = "heic")]
+        "heic" | "heif" => Some(ExtraDecoder {
+            dimensions: crate::heic::dimensions,
+            decode: crate::heic::decode,
+        }),
+        _ => None,
+    }
+}
 
-#[derive(Clone, Copy, Deserialize, specta::Type)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, specta::Type, clap::ValueEnum)]
 pub enum ResizeFilterType {
     Nearest,
     Triangle,
@@ -44,37 +154,392 @@ impl From<ResizeFilterType> for ImageFilterType {
     }
 }
 
-#[derive(Clone, Copy)]
-struct ImportOptions {
+/// Options that apply to every screenshot in an import.
+#[derive(Clone, Copy, Deserialize, Serialize, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportOptions {
     app_id: u32,
     jpeg_quality: u8,
-    filter_type: ImageFilterType,
+    filter_type: ResizeFilterType,
+    /// Resizes in linear light instead of on sRGB values
+    #[serde(default)]
+    linear_resize: bool,
+    /// Strength of the unsharp mask applied after resizing, 0 to not sharpen
+    #[serde(default)]
+    sharpen_amount: f32,
+    /// Blur radius of the unsharp mask in pixels
+    #[serde(default = "default_sharpen_radius")]
+    sharpen_radius: f32,
+    #[serde(default)]
+    tone_map: ToneMapOperator,
+    /// Exposure adjustment of HDR screenshots in stops
+    #[serde(default)]
+    exposure: f32,
+    /// Brightness of HDR screenshots, relative to SDR white, that's mapped to white
+    #[serde(default = "default_white_point")]
+    white_point: f32,
+    #[serde(default)]
+    hdr_transfer: HdrTransfer,
+    #[serde(default)]
+    background: Background,
+    /// Color of solid backgrounds, as RGB
+    #[serde(default)]
+    background_color: [u8; 3],
+    /// Embeds an sRGB ICC profile in re-encoded JPEGs
+    #[serde(default)]
+    embed_srgb_profile: bool,
+    #[serde(default)]
+    metadata: MetadataPolicy,
+    #[serde(default)]
+    capture_time: CaptureTimeSource,
+    #[serde(default)]
+    duplicates: DuplicatePolicy,
+    /// Maximum perceptual hash distance for a screenshot to count as a duplicate
+    #[serde(default = "default_duplicate_threshold")]
+    duplicate_threshold: u32,
+    /// Maximum number of screenshots processed at the same time, 0 for one per CPU core
+    #[serde(default)]
+    max_workers: u32,
+    /// Memory in MiB that the screenshots processed at the same time may take together
+    #[serde(default = "default_memory_budget_mb")]
+    memory_budget_mb: u32,
+    #[serde(default)]
+    vr_mode: VrMode,
+    #[serde(default)]
+    stereo_layout: StereoLayout,
+    #[serde(skip)]
+    open_library: bool,
+}
+
+impl ImportOptions {
+    #[must_use]
+    pub fn new(app_id: u32, jpeg_quality: u8, filter_type: ResizeFilterType) -> Self {
+        Self {
+            app_id,
+            jpeg_quality: jpeg_quality.clamp(1, 100),
+            filter_type,
+            linear_resize: false,
+            sharpen_amount: 0.0,
+            sharpen_radius: DEFAULT_SHARPEN_RADIUS,
+            tone_map: ToneMapOperator::default(),
+            exposure: 0.0,
+            white_point: DEFAULT_WHITE_POINT,
+            hdr_transfer: HdrTransfer::default(),
+            background: Background::default(),
+            background_color: [0; 3],
+            embed_srgb_profile: false,
+            metadata: MetadataPolicy::default(),
+            capture_time: CaptureTimeSource::default(),
+            duplicates: DuplicatePolicy::default(),
+            duplicate_threshold: DEFAULT_DUPLICATE_THRESHOLD,
+            max_workers: 0,
+            memory_budget_mb: DEFAULT_MEMORY_BUDGET_MB,
+            vr_mode: VrMode::default(),
+            stereo_layout: StereoLayout::default(),
+            open_library: false,
+        }
+    }
+
+    #[must_use]
+    pub fn app_id(&self) -> u32 {
+        self.app_id
+    }
+
+    /// Sets the game the screenshots are imported for.
+    #[must_use]
+    pub fn with_app_id(mut self, app_id: u32) -> Self {
+        self.app_id = app_id;
+        self
+    }
+
+    /// Resizes screenshots and thumbnails in linear light, which keeps fine high-contrast details
+    /// from getting darker.
+    #[must_use]
+    pub fn linear_resize(mut self, linear: bool) -> Self {
+        self.linear_resize = linear;
+        self
+    }
+
+    /// Sharpens resized screenshots and thumbnails with an unsharp mask of the given strength and
+    /// radius in pixels. An amount of 0 turns sharpening off.
+    #[must_use]
+    pub fn sharpen(mut self, amount: f32, radius: f32) -> Self {
+        self.sharpen_amount = amount;
+        self.sharpen_radius = radius;
+        self
+    }
+
+    /// Sets how HDR screenshots are converted to SDR.
+    #[must_use]
+    pub fn tone_map(mut self, operator: ToneMapOperator, exposure: f32, white_point: f32) -> Self {
+        self.tone_map = operator;
+        self.exposure = exposure;
+        self.white_point = white_point;
+        self
+    }
+
+    /// Sets how the values of 16-bit screenshots are encoded, unless their color profile marks
+    /// them as PQ or HLG.
+    #[must_use]
+    pub fn hdr_transfer(mut self, transfer: HdrTransfer) -> Self {
+        self.hdr_transfer = transfer;
+        self
+    }
+
+    /// Sets what transparent parts of screenshots are filled with, and the color of solid fills.
+    #[must_use]
+    pub fn background(mut self, background: Background, color: [u8; 3]) -> Self {
+        self.background = background;
+        self.background_color = color;
+        self
+    }
+
+    /// Embeds an sRGB ICC profile in the JPEGs that are re-encoded, so viewers that assume
+    /// another color space for untagged images still show the right colors.
+    #[must_use]
+    pub fn embed_srgb_profile(mut self, embed: bool) -> Self {
+        self.embed_srgb_profile = embed;
+        self
+    }
+
+    /// Sets which metadata of the original screenshots is kept in the imported JPEGs.
+    #[must_use]
+    pub fn metadata(mut self, metadata: MetadataPolicy) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Sets where the date shown in Steam for each screenshot comes from.
+    #[must_use]
+    pub fn capture_time(mut self, capture_time: CaptureTimeSource) -> Self {
+        self.capture_time = capture_time;
+        self
+    }
+
+    /// Sets how screenshots that are already in the Steam library are handled, and how similar
+    /// they have to be to count as duplicates.
+    #[must_use]
+    pub fn duplicates(mut self, duplicates: DuplicatePolicy, threshold: u32) -> Self {
+        self.duplicates = duplicates;
+        self.duplicate_threshold = threshold;
+        self
+    }
+
+    /// Sets how many screenshots are processed at the same time, 0 for one per CPU core, and how
+    /// much memory in MiB they may take together. Screenshots that need more than that are
+    /// processed on their own, and ones that can't even be decoded within it fail to import.
+    #[must_use]
+    pub fn parallelism(mut self, max_workers: u32, memory_budget_mb: u32) -> Self {
+        self.max_workers = max_workers;
+        self.memory_budget_mb = memory_budget_mb;
+        self
+    }
+
+    /// Imports VR screenshots of the given type, or detected from their aspect ratio, instead of
+    /// regular ones. Each is added with a preview cropped from it, e.g. the left eye of stereo
+    /// screenshots laid out as `stereo_layout`.
+    #[must_use]
+    pub fn vr(mut self, mode: VrMode, stereo_layout: StereoLayout) -> Self {
+        self.vr_mode = mode;
+        self.stereo_layout = stereo_layout;
+        self
+    }
+
+    fn memory_budget(&self) -> MemoryBudget {
+        MemoryBudget::new(self.memory_budget_mb, self.max_workers)
+    }
+
+    /// Opens the game's Steam screenshot library once at least one screenshot was imported.
+    #[must_use]
+    pub fn open_library(mut self, open_library: bool) -> Self {
+        self.open_library = open_library;
+        self
+    }
+}
+
+fn default_duplicate_threshold() -> u32 {
+    DEFAULT_DUPLICATE_THRESHOLD
+}
+
+fn default_memory_budget_mb() -> u32 {
+    DEFAULT_MEMORY_BUDGET_MB
+}
+
+fn default_white_point() -> f32 {
+    DEFAULT_WHITE_POINT
+}
+
+fn default_sharpen_radius() -> f32 {
+    DEFAULT_SHARPEN_RADIUS
+}
+
+/// Receives the overall progress of an import as a percentage.
+pub trait ImportProgress: Sync {
+    fn report(&self, progress: f32);
+}
+
+/// Stops an import between the steps of each screenshot once cancelled.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    imported: u32,
+    duplicates: Vec<ImportDuplicate>,
+    /// Screenshots that weren't imported because the import was cancelled
+    cancelled: Vec<String>,
 }
 
-#[derive(Serialize, specta::Type)]
+#[derive(Clone, Debug, Serialize, specta::Type)]
 #[serde(rename_all = "camelCase")]
 pub struct ImportError {
     summary: String,
     errors: Vec<ImportFailure>,
+    duplicates: Vec<ImportDuplicate>,
+    cancelled: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportFailure {
+    file_path: String,
+    message: String,
 }
 
-#[derive(Serialize, specta::Type)]
+/// A screenshot that matched one already in the Steam library.
+#[derive(Clone, Debug, Serialize, specta::Type)]
 #[serde(rename_all = "camelCase")]
-struct ImportFailure {
+pub struct ImportDuplicate {
     file_path: String,
     message: String,
+    skipped: bool,
+}
+
+impl ImportReport {
+    pub(crate) fn cancelled_before_start(file_paths: Vec<String>) -> Self {
+        Self {
+            imported: 0,
+            duplicates: Vec::new(),
+            cancelled: file_paths,
+        }
+    }
+
+    #[must_use]
+    pub fn imported(&self) -> u32 {
+        self.imported
+    }
+
+    #[must_use]
+    pub fn duplicates(&self) -> &[ImportDuplicate] {
+        &self.duplicates
+    }
+
+    #[must_use]
+    pub fn cancelled(&self) -> &[String] {
+        &self.cancelled
+    }
 }
 
 impl ImportError {
-    fn from_failures(total: usize, errors: Vec<ImportFailure>) -> Self {
+    fn from_failures(
+        total: usize,
+        errors: Vec<ImportFailure>,
+        duplicates: Vec<ImportDuplicate>,
+        cancelled: Vec<String>,
+    ) -> Self {
         let failed = errors.len();
-        let summary = if failed == total {
+        let mut summary = if failed == total {
             format!("All {total} screenshots failed to import.")
         } else {
             format!("{failed} of {total} screenshots failed to import.")
         };
+        if !cancelled.is_empty() {
+            summary = format!(
+                "{summary} The import was cancelled before {} screenshots were imported.",
+                cancelled.len()
+            );
+        }
+
+        Self {
+            summary,
+            errors,
+            duplicates,
+            cancelled,
+        }
+    }
+
+    #[must_use]
+    pub fn errors(&self) -> &[ImportFailure] {
+        &self.errors
+    }
+
+    #[must_use]
+    pub fn duplicates(&self) -> &[ImportDuplicate] {
+        &self.duplicates
+    }
+
+    #[must_use]
+    pub fn cancelled(&self) -> &[String] {
+        &self.cancelled
+    }
+}
+
+impl ImportFailure {
+    #[must_use]
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    #[must_use]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl ImportDuplicate {
+    #[must_use]
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    #[must_use]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
 
-        Self { summary, errors }
+    #[must_use]
+    pub fn skipped(&self) -> bool {
+        self.skipped
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.summary)
+    }
+}
+
+impl fmt::Display for ImportFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.file_path, self.message)
+    }
+}
+
+impl fmt::Display for ImportDuplicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.file_path, self.message)
     }
 }
 
@@ -83,27 +548,290 @@ impl From<String> for ImportError {
         Self {
             summary,
             errors: Vec::new(),
+            duplicates: Vec::new(),
+            cancelled: Vec::new(),
         }
     }
 }
 
-struct ImportContext {
-    window: tauri::Window<AppRuntime>,
+/// Why a screenshot wasn't imported.
+enum ScreenshotError {
+    Failed(String),
+    /// Skipped because it's already in the library
+    Duplicate(DuplicateMatch),
+    Cancelled,
+}
+
+impl From<String> for ScreenshotError {
+    fn from(message: String) -> Self {
+        Self::Failed(message)
+    }
+}
+
+/// What happened to a single screenshot of an import.
+enum ScreenshotOutcome {
+    Imported,
+    Duplicate(ImportDuplicate),
+    Failed(ImportFailure),
+    Cancelled(String),
+}
+
+/// Where the original file of a screenshot is read from.
+enum ScreenshotSource<'a> {
+    File(&'a Path),
+    /// Contents of an archive entry, with the time it was last modified at
+    Entry {
+        bytes: Vec<u8>,
+        modified: Option<i64>,
+    },
+}
+
+/// A decoded screenshot, turned the way it's meant to be shown.
+struct DecodedImage {
+    img: DynamicImage,
+    /// Size of the screenshot if `img` is only a preview decoded at a reduced scale
+    full_size: Option<(u32, u32)>,
+    /// Color profile the pixels are in
+    profile: SourceProfile,
+    /// Whether the pixels were rotated or flipped for the EXIF orientation
+    reoriented: bool,
+    exif: Option<Vec<u8>>,
+    xmp: Option<Vec<u8>>,
+}
+
+/// A screenshot that's ready to be written.
+struct LoadedImage {
+    img: DynamicImage,
+    /// Size of the screenshot if `img` is only a preview, in which case it's copied as it is
+    full_size: Option<(u32, u32)>,
+    /// Whether the pixels differ from the original file, so it can't be copied
+    modified: bool,
+    /// EXIF data for the imported JPEG
+    exif: Option<Vec<u8>>,
+}
+
+impl ScreenshotSource<'_> {
+    /// Decodes the screenshot, along with its color profile and metadata, unless it's too large
+    /// for `budget`. JPEGs that are copied as they are only get a preview decoded if
+    /// `allow_preview` is set.
+    fn decode(
+        &self,
+        file_name: &str,
+        extension: &str,
+        budget: MemoryBudget,
+        allow_preview: bool,
+    ) -> Result<DecodedImage, String> {
+        if let Some(decoder) = extra_decoder(extension) {
+            let bytes = self
+                .bytes()
+                .map_err(|error| format!("Failed to open {file_name}: {error}"))?;
+            let (img, profile) = decoder.decode(&bytes, file_name, budget)?;
+            // These decoders apply the orientation themselves
+            let exif = exif::Reader::new()
+                .read_from_container(&mut Cursor::new(bytes.as_ref()))
+                .ok()
+                .map(|exif| exif.buf().to_vec());
+            return Ok(DecodedImage {
+                img,
+                full_size: None,
+                profile,
+                reoriented: false,
+                exif,
+                xmp: None,
+            });
+        }
+
+        let decoded = match self {
+            Self::File(path) => {
+                let reader = ImageReader::open(path)
+                    .map_err(|error| format!("Failed to open {file_name}: {error}"))?;
+                decode_with_profile(reader, budget.limits(), allow_preview, || {
+                    File::open(path).map(BufReader::new)
+                })
+            }
+            Self::Entry { bytes, .. } => {
+                let mut reader = ImageReader::new(Cursor::new(bytes.as_slice()));
+                match ImageFormat::from_extension(extension) {
+                    Some(format) => reader.set_format(format),
+                    None => {
+                        reader = reader
+                            .with_guessed_format()
+                            .map_err(|error| format!("Failed to open {file_name}: {error}"))?;
+                    }
+                }
+                decode_with_profile(reader, budget.limits(), allow_preview, || {
+                    Ok(Cursor::new(bytes.as_slice()))
+                })
+            }
+        };
+        decoded.map_err(|error| {
+            budget
+                .limit_error(file_name, &error)
+                .unwrap_or_else(|| format!("Failed to decode {file_name}: {error}"))
+        })
+    }
+
+    /// Estimates how much memory importing the screenshot takes from its header, without
+    /// decoding it.
+    fn estimated_size(&self, extension: &str) -> u64 {
+        if let Some(decoder) = extra_decoder(extension) {
+            return self
+                .bytes()
+                .ok()
+                .and_then(|bytes| (decoder.dimensions)(&bytes).ok())
+                .map_or(UNKNOWN_SIZE, |(width, height)| float_size(width, height));
+        }
+        match self {
+            Self::File(path) => ImageReader::open(path).map_or(UNKNOWN_SIZE, estimated_size),
+            Self::Entry { bytes, .. } => {
+                let mut reader = ImageReader::new(Cursor::new(bytes.as_slice()));
+                match ImageFormat::from_extension(extension) {
+                    Some(format) => {
+                        reader.set_format(format);
+                        estimated_size(reader)
+                    }
+                    None => reader
+                        .with_guessed_format()
+                        .map_or(UNKNOWN_SIZE, estimated_size),
+                }
+            }
+        }
+    }
+
+    fn bytes(&self) -> io::Result<Cow<'_, [u8]>> {
+        match self {
+            Self::File(path) => read(path).map(Cow::Owned),
+            Self::Entry { bytes, .. } => Ok(Cow::Borrowed(bytes.as_slice())),
+        }
+    }
+
+    /// Copies a JPEG screenshot without re-encoding it, replacing its metadata with `exif`.
+    fn copy_jpeg_to(&self, path: &Path, exif: Option<&[u8]>) -> Result<(), String> {
+        let bytes = self.bytes().map_err(|error| error.to_string())?;
+        let jpeg = replace_jpeg_metadata(&bytes, exif)?;
+        write(path, jpeg).map_err(|error| error.to_string())
+    }
+
+    fn capture_time(&self, file_path: &str, source: CaptureTimeSource) -> Option<i64> {
+        match self {
+            Self::File(path) => capture_time(path, source),
+            Self::Entry { bytes, modified } => {
+                capture_time_of_bytes(file_path, bytes, *modified, source)
+            }
+        }
+    }
+}
+
+/// Decodes an image along with its color profile and metadata, and applies its EXIF
+/// orientation. Images whose header exceeds `limits` are rejected before they're decoded. PNGs
+/// are read again with `reopen` for the color chunks that the decoder doesn't expose. JPEGs that
+/// will be copied as they are, which is known from their headers alone, are read again to only
+/// decode a preview if `allow_preview` is set.
+fn decode_with_profile<R: BufRead + Seek, P: BufRead + Seek>(
+    mut reader: ImageReader<R>,
+    mut limits: Limits,
+    allow_preview: bool,
+    reopen: impl Fn() -> io::Result<P>,
+) -> ImageResult<DecodedImage> {
+    let format = reader.format();
+    reader.limits(limits.clone());
+    let mut decoder = reader.into_decoder()?;
+    // Not every decoder checks the memory limit before allocating the image
+    limits.reserve(decoder.total_bytes())?;
+    let (width, height) = decoder.dimensions();
+    let icc = decoder.icc_profile()?;
+    // Broken metadata shouldn't keep the screenshot from being imported
+    let exif = decoder.exif_metadata().ok().flatten();
+    let xmp = decoder.xmp_metadata().ok().flatten();
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+
+    let png = (format == Some(ImageFormat::Png))
+        .then(&reopen)
+        .and_then(Result::ok);
+    let profile = match png {
+        Some(png) => SourceProfile::of_png(png, icc),
+        None => icc.map_or(SourceProfile::Srgb, SourceProfile::Icc),
+    };
+
+    let copied_as_is = allow_preview
+        && format == Some(ImageFormat::Jpeg)
+        && orientation == Orientation::NoTransforms
+        && fits_steam(width, height)
+        && !needs_conversion(&profile);
+    let preview = if copied_as_is {
+        reopen()
+            .map_err(|error| error.to_string())
+            .and_then(|jpeg| decode_jpeg_preview(jpeg, PREVIEW_MIN_WIDTH))
+            .unwrap_or_else(|error| {
+                warn!("Failed to decode a JPEG preview, decoding it in full: {error}");
+                None
+            })
+    } else {
+        None
+    };
+    let (img, full_size) = if let Some(preview) = preview {
+        (preview, Some((width, height)))
+    } else {
+        let mut img = DynamicImage::from_decoder(decoder)?;
+        img.apply_orientation(orientation);
+        (img, None)
+    };
+
+    Ok(DecodedImage {
+        img,
+        full_size,
+        profile,
+        reoriented: orientation != Orientation::NoTransforms,
+        exif,
+        xmp,
+    })
+}
+
+struct ImportContext<'a> {
+    progress: &'a dyn ImportProgress,
+    cancellation: &'a CancellationToken,
+    journal: Option<&'a ImportJournal>,
+    library: &'a dyn ScreenshotLibrary,
+    duplicates: Option<DuplicateIndex>,
+    duplicate_policy: DuplicatePolicy,
+    annotator: Annotator,
     cache_dir: PathBuf,
-    client: Mutex<steamworks::Client>,
     screenshots_completed: AtomicF32,
     total_screenshots: usize,
 }
 
-#[tauri::command]
-#[specta::specta]
-pub async fn import_screenshots(
-    file_paths: Vec<String>,
-    app_id: u32,
-    jpeg_quality: u8,
-    filter_type: ResizeFilterType,
-    window: tauri::Window<AppRuntime>,
-) -> Result<(), ImportError> {
+impl ImportContext<'_> {
+    fn record(&self, file_path: &str, state: JournalFileState) {
+        if let Some(journal) = self.journal {
+            journal.record(file_path, state);
+        }
+    }
+}
+
+/// Imports `file_paths` into `library` under the configured `AppID`, captioned and tagged with
+/// `annotations`.
+///
+/// # Errors
+///
+/// Returns an [`ImportError`] if the annotations have an invalid ID, the screenshot cache could
+/// not be emptied or any of the screenshots failed to import. Screenshots that weren't imported
+/// yet when `cancellation` is cancelled are reported as cancelled rather than failed. The state
+/// of each screenshot is recorded in `journal`, if given, so the import can be resumed if it's
+/// interrupted.
+pub fn run_import(
+    file_paths: &[String],
+    options: ImportOptions,
+    annotations: &ImportAnnotations,
+    library: &dyn ScreenshotLibrary,
+    progress: &dyn ImportProgress,
+    cancellation: &CancellationToken,
+    journal: Option<&ImportJournal>,
+) -> Result<ImportReport, ImportError> {
+    let options = ImportOptions {
+        jpeg_quality: options.jpeg_quality.clamp(1, 100),
+        ..options
+    };
+    let app_id = options.app_id;
     info!(
         "Importing {} screenshots under AppID {}",
         file_paths.len(),
@@ -115,45 +843,64 @@ pub async fn import_screenshots(
         warn!("Got no screenshots to import");
         return Err("No screenshots to import".to_string().into());
     }
+    let annotator = annotations.parse()?;
 
-    let options = ImportOptions {
-        app_id,
-        jpeg_quality: jpeg_quality.clamp(1, 100),
-        filter_type: filter_type.into(),
+    let duplicates = match options.duplicates {
+        DuplicatePolicy::Import => None,
+        DuplicatePolicy::Skip | DuplicatePolicy::Report => {
+            DuplicateIndex::load(app_id, options.duplicate_threshold)
+                .inspect_err(|error| warn!("Not checking for duplicates: {error}"))
+                .ok()
+        }
     };
 
-    // Check if steam is running and initialize client
-    let client = initialize_steam(app_id)?;
-
-    let ctx = Arc::new(ImportContext {
-        window,
-        cache_dir: PROJECT_DIRS.cache_dir().to_path_buf(),
-        client: Mutex::new(client),
+    let ctx = ImportContext {
+        progress,
+        cancellation,
+        journal,
+        library,
+        duplicates,
+        duplicate_policy: options.duplicates,
+        annotator,
+        cache_dir: import_cache_dir(),
         screenshots_completed: AtomicF32::new(0.0),
         total_screenshots: num_of_files,
-    });
+    };
 
-    // Process screenshots in parallel
-    let import_errors: Vec<ImportFailure> = file_paths
-        .par_iter()
-        .enumerate()
-        .filter_map(|(file_index, file_path)| {
-            import_single_screenshot(file_path, file_index, &ctx, options).err()
-        })
-        .collect();
+    let outcomes = import_screenshots(file_paths, &ctx, options);
 
-    info!("Emptying cache");
-    let cleanup_result = remove_dir_all(&ctx.cache_dir)
-        .and_then(|()| create_dir_all(&ctx.cache_dir))
-        .map_err(|error| format!("Failed to empty screenshot cache: {error}"));
+    let mut import_errors = Vec::new();
+    let mut duplicates = Vec::new();
+    let mut cancelled = Vec::new();
+    for outcome in outcomes {
+        match outcome {
+            ScreenshotOutcome::Imported => {}
+            ScreenshotOutcome::Duplicate(duplicate) => duplicates.push(duplicate),
+            ScreenshotOutcome::Failed(import_error) => import_errors.push(import_error),
+            ScreenshotOutcome::Cancelled(file_path) => cancelled.push(file_path),
+        }
+    }
 
-    let succeeded = num_of_files - import_errors.len();
+    if !cancelled.is_empty() {
+        warn!(
+            "Import cancelled, {} of {num_of_files} screenshots were not imported",
+            cancelled.len()
+        );
+    }
 
-    let open_section_result = if succeeded > 0 {
+    let cleanup_result = remove_import_cache(&ctx.cache_dir);
+
+    let skipped = duplicates
+        .iter()
+        .filter(|duplicate| duplicate.skipped)
+        .count();
+    let succeeded = num_of_files - import_errors.len() - skipped - cancelled.len();
+
+    let open_section_result = if succeeded > 0 && options.open_library {
         info!(
             "Import of {succeeded} out of {num_of_files} images complete, opening steam screenshots window"
         );
-        open_steam_section(&format!("screenshots/{app_id}"))
+        library.open_section(&format!("screenshots/{app_id}"))
     } else {
         Ok(())
     };
@@ -173,47 +920,576 @@ pub async fn import_screenshots(
             );
         }
 
-        return Err(ImportError::from_failures(num_of_files, import_errors));
+        return Err(ImportError::from_failures(
+            num_of_files,
+            import_errors,
+            duplicates,
+            cancelled,
+        ));
     }
 
     cleanup_result?;
     open_section_result?;
 
+    Ok(ImportReport {
+        imported: u32::try_from(succeeded).unwrap_or(u32::MAX),
+        duplicates,
+        cancelled,
+    })
+}
+
+/// Gives every import its own cache directory, so concurrent imports, even from other processes,
+/// don't remove each other's files.
+fn import_cache_dir() -> PathBuf {
+    let import_number = IMPORTS_STARTED.fetch_add(1, Ordering::SeqCst);
+    PROJECT_DIRS
+        .cache_dir()
+        .join(format!("import-{}-{import_number}", process::id()))
+}
+
+fn remove_import_cache(cache_dir: &Path) -> Result<(), String> {
+    info!("Removing cache directory {}", cache_dir.display());
+    match remove_dir_all(cache_dir) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => {
+            Err(format!("Failed to empty screenshot cache: {error}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn import_screenshots(
+    file_paths: &[String],
+    ctx: &ImportContext,
+    options: ImportOptions,
+) -> Vec<ScreenshotOutcome> {
+    let (archive_entries, files): (Vec<_>, Vec<_>) = file_paths
+        .iter()
+        .enumerate()
+        .partition(|(_, file_path)| split_entry_path(file_path).is_some());
+
+    // Only the headers are read to estimate the memory each screenshot needs
+    let sizes: Vec<u64> = files
+        .par_iter()
+        .map(|&(_, file_path)| {
+            ScreenshotSource::File(Path::new(file_path)).estimated_size(extension_of(file_path))
+        })
+        .collect();
+
+    let (sender, receiver) = mpsc::channel();
+    let pool = MemoryPool::new(options.memory_budget());
+    thread::scope(|scope| {
+        let registration = scope.spawn(move || register_screenshots(&receiver, ctx));
+
+        // Waits for the workers before returning, which closes the channel once the last
+        // screenshot was sent
+        rayon::in_place_scope(|workers| {
+            let mut scheduler = ScreenshotScheduler::new(workers, &pool, ctx, options, sender);
+            for (&(file_index, file_path), size) in files.iter().zip(sizes) {
+                let source = ScreenshotSource::File(Path::new(file_path));
+                scheduler.push(file_index, file_path, source, size);
+            }
+            import_archive_entries(&archive_entries, &mut scheduler, ctx);
+        });
+
+        registration
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+fn extension_of(file_path: &str) -> &str {
+    Path::new(file_path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+}
+
+/// What the workers send the registration thread for each screenshot, numbered in the order
+/// the screenshots were scheduled in.
+enum Processed {
+    /// Written to the cache and ready to be added to the library
+    Ready(ProcessedScreenshot),
+    /// Failed, skipped or cancelled before it was ready
+    Finished(ScreenshotOutcome),
+}
+
+/// A screenshot and its thumbnail that were written to the cache.
+struct ProcessedScreenshot {
+    file_path: String,
+    /// File name of the original, for messages
+    name: String,
+    new_file_name: String,
+    path: PathBuf,
+    thumbnail_path: PathBuf,
+    width: u32,
+    height: u32,
+    capture_time: Option<i64>,
+    /// Type and path of the VR image, for VR screenshots whose `path` is their preview
+    vr: Option<(VrScreenshotType, PathBuf)>,
+    /// Content and perceptual hashes it's checked for duplicates with and added to the duplicate
+    /// index with once it's imported
+    hashes: Option<(Option<String>, u64)>,
+    /// Library screenshot it matched, if duplicates are reported rather than skipped
+    duplicate: Option<DuplicateMatch>,
+    progress_remaining: f32,
+}
+
+/// Starts processing screenshots in parallel, each as soon as it fits in the memory budget and
+/// number of workers of the import options along with the ones that are still being processed.
+/// Each of them is sent to the registration thread when it's done, along with the order it was
+/// scheduled in.
+struct ScreenshotScheduler<'s, 'scope> {
+    workers: &'s rayon::Scope<'scope>,
+    pool: &'scope MemoryPool,
+    ctx: &'scope ImportContext<'scope>,
+    options: ImportOptions,
+    sender: Sender<(usize, Processed)>,
+    /// Number of screenshots scheduled so far
+    scheduled: usize,
+}
+
+impl<'s, 'scope> ScreenshotScheduler<'s, 'scope> {
+    fn new(
+        workers: &'s rayon::Scope<'scope>,
+        pool: &'scope MemoryPool,
+        ctx: &'scope ImportContext<'scope>,
+        options: ImportOptions,
+        sender: Sender<(usize, Processed)>,
+    ) -> Self {
+        Self {
+            workers,
+            pool,
+            ctx,
+            options,
+            sender,
+            scheduled: 0,
+        }
+    }
+
+    /// Processes a screenshot that needs `size` bytes, once that fits in the budget.
+    fn push(
+        &mut self,
+        file_index: usize,
+        file_path: &'scope str,
+        source: ScreenshotSource<'scope>,
+        size: u64,
+    ) {
+        let reservation = self.pool.reserve(size);
+        let sequence = self.next_sequence();
+        let (ctx, options, sender) = (self.ctx, self.options, self.sender.clone());
+        self.workers.spawn(move |_| {
+            let processed = import_single_screenshot(file_path, source, file_index, ctx, options);
+            // The memory is free once the screenshot was written to the cache
+            drop(reservation);
+            let _ = sender.send((sequence, processed));
+        });
+    }
+
+    /// Reports a screenshot that won't be processed, in its place in the order.
+    fn finish(&mut self, outcome: ScreenshotOutcome) {
+        let sequence = self.next_sequence();
+        // The registration thread only stops once every sender is dropped
+        let _ = self.sender.send((sequence, Processed::Finished(outcome)));
+    }
+
+    fn next_sequence(&mut self) -> usize {
+        self.scheduled += 1;
+        self.scheduled - 1
+    }
+}
+
+/// Adds processed screenshots to the library from a single thread, in the order they were
+/// scheduled in whatever order they finish, and keeps running the library's callbacks while it
+/// waits for them. Once every screenshot was sent, it waits for the library to confirm the ones
+/// that were added. Returns the outcome of every screenshot.
+fn register_screenshots(
+    receiver: &Receiver<(usize, Processed)>,
+    ctx: &ImportContext,
+) -> Vec<ScreenshotOutcome> {
+    let mut pending = BTreeMap::new();
+    let mut next_sequence = 0;
+    let mut unconfirmed = HashMap::new();
+    let mut outcomes = Vec::new();
+    let mut receiving = true;
+
+    while receiving || !unconfirmed.is_empty() {
+        if receiving {
+            match receiver.recv_timeout(CALLBACK_INTERVAL) {
+                Ok((sequence, processed)) => {
+                    pending.insert(sequence, processed);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => receiving = false,
+            }
+        } else {
+            thread::sleep(CALLBACK_INTERVAL);
+        }
+
+        loop {
+            // Every screenshot is sent exactly once, but once they all were, any that are left
+            // are still registered in order
+            let processed = if receiving {
+                pending.remove(&next_sequence)
+            } else {
+                pending.pop_first().map(|(_, processed)| processed)
+            };
+            let Some(processed) = processed else {
+                break;
+            };
+            next_sequence += 1;
+            let mut screenshot = match processed {
+                Processed::Ready(screenshot) => screenshot,
+                Processed::Finished(outcome) => {
+                    outcomes.push(outcome);
+                    continue;
+                }
+            };
+            match register_screenshot(&mut screenshot, ctx) {
+                Ok(handle) if ctx.library.confirms_asynchronously() => {
+                    unconfirmed.insert(handle, (screenshot, Instant::now()));
+                }
+                result => outcomes.push(finish_screenshot(screenshot, result.map(|_| ()), ctx)),
+            }
+        }
+
+        if let Err(error) = ctx.library.run_callbacks() {
+            warn!("{error}");
+        }
+        outcomes.extend(confirm_screenshots(&mut unconfirmed, ctx));
+    }
+
+    outcomes
+}
+
+/// Finishes the screenshots the library confirmed since the last callbacks, and fails the ones
+/// it didn't confirm in time.
+fn confirm_screenshots(
+    unconfirmed: &mut HashMap<ScreenshotHandle, (ProcessedScreenshot, Instant)>,
+    ctx: &ImportContext,
+) -> Vec<ScreenshotOutcome> {
+    let mut outcomes = Vec::new();
+    for (handle, result) in ctx.library.take_confirmations() {
+        let Some((screenshot, _)) = unconfirmed.remove(&handle) else {
+            continue;
+        };
+        let result =
+            result.map_err(|error| format!("Failed to import {}: {error}", screenshot.name).into());
+        outcomes.push(finish_screenshot(screenshot, result, ctx));
+    }
+
+    let expired: Vec<ScreenshotHandle> = unconfirmed
+        .iter()
+        .filter(|(_, (_, added))| added.elapsed() >= CONFIRMATION_TIMEOUT)
+        .map(|(&handle, _)| handle)
+        .collect();
+    for handle in expired {
+        if let Some((screenshot, _)) = unconfirmed.remove(&handle) {
+            let message = format!(
+                "Steam didn't confirm that {} was imported within {} seconds",
+                screenshot.name,
+                CONFIRMATION_TIMEOUT.as_secs()
+            );
+            outcomes.push(finish_screenshot(screenshot, Err(message.into()), ctx));
+        }
+    }
+    outcomes
+}
+
+/// Adds a processed screenshot to the library, returning its handle.
+fn register_screenshot(
+    screenshot: &mut ProcessedScreenshot,
+    ctx: &ImportContext,
+) -> Result<ScreenshotHandle, ScreenshotError> {
+    check_cancelled(ctx)?;
+    check_duplicate(screenshot, ctx)?;
+
+    info!(
+        "Importing screenshot {} {}",
+        screenshot.path.display(),
+        screenshot.thumbnail_path.display()
+    );
+    let tags = ctx
+        .annotator
+        .tags(&screenshot.file_path, screenshot.capture_time);
+    let handle = ctx
+        .library
+        .add_screenshot(&Screenshot {
+            path: &screenshot.path,
+            thumbnail_path: &screenshot.thumbnail_path,
+            width: screenshot.width,
+            height: screenshot.height,
+            capture_time: screenshot.capture_time,
+            vr: screenshot
+                .vr
+                .as_ref()
+                .map(|(kind, vr_path)| (*kind, vr_path.as_path())),
+            tags: &tags,
+        })
+        .map_err(|error| format!("Failed to import {}: {error}", screenshot.name))?;
+    ctx.record(
+        &screenshot.file_path,
+        JournalFileState::Registered { handle },
+    );
+
+    if let Some(index) = &ctx.duplicates
+        && let Some((sha256, perceptual_hash)) = &screenshot.hashes
+    {
+        index.insert(
+            screenshot.new_file_name.clone(),
+            sha256.clone(),
+            *perceptual_hash,
+        );
+    }
+
+    Ok(handle)
+}
+
+/// Looks for a screenshot in the library, or added earlier in this import, that `screenshot`
+/// duplicates. This runs on the registration thread, right before the screenshot would be added,
+/// so the same screenshots are caught whatever order they're processed in.
+fn check_duplicate(
+    screenshot: &mut ProcessedScreenshot,
+    ctx: &ImportContext,
+) -> Result<(), ScreenshotError> {
+    let (Some(index), Some((sha256, perceptual_hash))) = (&ctx.duplicates, &screenshot.hashes)
+    else {
+        return Ok(());
+    };
+    let Some(duplicate) = index.find(sha256.as_deref(), *perceptual_hash) else {
+        return Ok(());
+    };
+
+    if ctx.duplicate_policy == DuplicatePolicy::Skip {
+        info!(
+            "Skipping {}, it looks like {}",
+            screenshot.name, duplicate.file_name
+        );
+        return Err(ScreenshotError::Duplicate(duplicate));
+    }
+    screenshot.duplicate = Some(duplicate);
     Ok(())
 }
 
+/// Reports the outcome of a screenshot that was imported, or failed to be, once it was added to
+/// the library.
+fn finish_screenshot(
+    screenshot: ProcessedScreenshot,
+    result: Result<(), ScreenshotError>,
+    ctx: &ImportContext,
+) -> ScreenshotOutcome {
+    if result.is_ok() {
+        info!("Import of {} complete", screenshot.name);
+    }
+    if screenshot.progress_remaining > f32::EPSILON {
+        update_progress(
+            ctx.progress,
+            &ctx.screenshots_completed,
+            ctx.total_screenshots,
+            screenshot.progress_remaining,
+        );
+    }
+
+    screenshot_outcome(
+        &screenshot.file_path,
+        result.map(|()| screenshot.duplicate),
+        ctx,
+    )
+}
+
+/// Imports screenshots from archives, reading each archive only once and processing its entries
+/// in parallel as they fit in the memory budget, so that only a few of them are held in memory at
+/// a time.
+fn import_archive_entries<'scope>(
+    entries: &[(usize, &'scope String)],
+    scheduler: &mut ScreenshotScheduler<'_, 'scope>,
+    ctx: &ImportContext,
+) {
+    let mut archives: BTreeMap<&str, HashMap<&str, (usize, &str)>> = BTreeMap::new();
+    for &(file_index, file_path) in entries {
+        if let Some((archive, name)) = split_entry_path(file_path) {
+            archives
+                .entry(archive)
+                .or_default()
+                .insert(name, (file_index, file_path));
+        }
+    }
+
+    // No entry can be decoded from more bytes than the whole budget
+    let max_size = scheduler.pool.budget().bytes();
+    for (archive, wanted) in archives {
+        info!("Reading {} screenshots from {archive}", wanted.len());
+        let mut found = HashSet::new();
+
+        let result = read_entries(
+            Path::new(archive),
+            max_size,
+            |name| wanted.contains_key(name),
+            |name, modified, bytes| {
+                // Archives can contain the same path more than once, only the first is imported
+                let Some(&(file_index, file_path)) = wanted.get(name) else {
+                    return ControlFlow::Continue(());
+                };
+                if !found.insert(name.to_string()) {
+                    return ControlFlow::Continue(());
+                }
+                let bytes = match bytes {
+                    Ok(bytes) => bytes,
+                    Err(error) => {
+                        let error = ScreenshotError::Failed(error);
+                        scheduler.finish(screenshot_outcome(file_path, Err(error), ctx));
+                        update_progress(
+                            ctx.progress,
+                            &ctx.screenshots_completed,
+                            ctx.total_screenshots,
+                            1.0,
+                        );
+                        return ControlFlow::Continue(());
+                    }
+                };
+                let source = ScreenshotSource::Entry { bytes, modified };
+                let size = source.estimated_size(extension_of(file_path));
+                scheduler.push(file_index, file_path, source, size);
+
+                if ctx.cancellation.is_cancelled() {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            },
+        );
+
+        // Entries that couldn't be read, in a stable order
+        let mut missing: Vec<_> = wanted
+            .iter()
+            .filter(|(name, _)| !found.contains(**name))
+            .map(|(name, &(file_index, file_path))| (file_index, *name, file_path))
+            .collect();
+        missing.sort_unstable();
+        for (_, name, file_path) in missing {
+            let error = if ctx.cancellation.is_cancelled() {
+                ScreenshotError::Cancelled
+            } else {
+                match &result {
+                    Ok(()) => ScreenshotError::Failed(format!("{name} is not in {archive}")),
+                    Err(error) => ScreenshotError::Failed(error.clone()),
+                }
+            };
+            scheduler.finish(screenshot_outcome(file_path, Err(error), ctx));
+            update_progress(
+                ctx.progress,
+                &ctx.screenshots_completed,
+                ctx.total_screenshots,
+                1.0,
+            );
+        }
+    }
+}
+
 fn import_single_screenshot(
     file_path: &str,
+    source: ScreenshotSource,
     file_index: usize,
     ctx: &ImportContext,
     options: ImportOptions,
-) -> Result<(), ImportFailure> {
+) -> Processed {
     let mut progress_remaining = 1.0;
-    let result =
-        process_single_screenshot(file_path, file_index, ctx, options, &mut progress_remaining);
+    let result = process_single_screenshot(
+        file_path,
+        &source,
+        file_index,
+        ctx,
+        options,
+        &mut progress_remaining,
+    );
+    drop(source);
 
-    if result.is_err() && progress_remaining > 0.0 {
-        update_progress(
-            &ctx.window,
-            &ctx.screenshots_completed,
-            ctx.total_screenshots,
-            progress_remaining,
-        );
+    match result {
+        Ok(screenshot) => Processed::Ready(screenshot),
+        Err(error) => {
+            // Failed and skipped screenshots still count towards the overall progress
+            if progress_remaining > f32::EPSILON {
+                update_progress(
+                    ctx.progress,
+                    &ctx.screenshots_completed,
+                    ctx.total_screenshots,
+                    progress_remaining,
+                );
+            }
+            Processed::Finished(screenshot_outcome(file_path, Err(error), ctx))
+        }
     }
+}
+
+fn screenshot_outcome(
+    file_path: &str,
+    result: Result<Option<DuplicateMatch>, ScreenshotError>,
+    ctx: &ImportContext,
+) -> ScreenshotOutcome {
+    let (duplicate, skipped) = match result {
+        Ok(None) => return ScreenshotOutcome::Imported,
+        Ok(Some(duplicate)) => (duplicate, false),
+        Err(ScreenshotError::Duplicate(duplicate)) => (duplicate, true),
+        Err(ScreenshotError::Cancelled) => {
+            return ScreenshotOutcome::Cancelled(file_path.to_string());
+        }
+        Err(ScreenshotError::Failed(message)) => {
+            ctx.record(
+                file_path,
+                JournalFileState::Failed {
+                    message: message.clone(),
+                },
+            );
+            return ScreenshotOutcome::Failed(ImportFailure {
+                file_path: file_path.to_string(),
+                message,
+            });
+        }
+    };
 
-    result.map_err(|message| ImportFailure {
+    if skipped {
+        ctx.record(file_path, JournalFileState::Skipped);
+    }
+    let message = if duplicate.distance == 0 {
+        format!("Identical to {} in the Steam library", duplicate.file_name)
+    } else {
+        format!(
+            "Looks like {} in the Steam library (distance {})",
+            duplicate.file_name, duplicate.distance
+        )
+    };
+    let message = if skipped {
+        format!("Skipped duplicate. {message}")
+    } else {
+        format!("Imported duplicate. {message}")
+    };
+
+    ScreenshotOutcome::Duplicate(ImportDuplicate {
         file_path: file_path.to_string(),
         message,
+        skipped,
     })
 }
 
+fn check_cancelled(ctx: &ImportContext) -> Result<(), ScreenshotError> {
+    if ctx.cancellation.is_cancelled() {
+        Err(ScreenshotError::Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// Decodes, converts and writes a screenshot and its thumbnail to the cache, ready to be added
+/// to the library.
 fn process_single_screenshot(
     file_path: &str,
+    source: &ScreenshotSource,
     file_index: usize,
     ctx: &ImportContext,
     options: ImportOptions,
     progress_remaining: &mut f32,
-) -> Result<(), String> {
+) -> Result<ProcessedScreenshot, ScreenshotError> {
+    check_cancelled(ctx)?;
+
     let img_path = Path::new(file_path);
     let img_name = img_path
         .file_stem()
@@ -231,107 +1507,253 @@ fn process_single_screenshot(
 
     // Load original image
     info!("Loading image: {}", img_path.display());
-    let img = ImageReader::open(file_path)
-        .map_err(|error| format!("Failed to open {img_name}.{extension}: {error}"))?
-        .decode()
-        .map_err(|error| format!("Failed to decode {img_name}.{extension}: {error}"))?;
+    let loaded = load_image(
+        source,
+        &format!("{img_name}.{extension}"),
+        extension,
+        options,
+    )?;
+
+    check_cancelled(ctx)?;
 
     // Convert to jpg or downscale if needed
     let file_cache_dir = ctx.cache_dir.join(file_index.to_string());
-    create_dir(&file_cache_dir)
+    create_dir_all(&file_cache_dir)
         .map_err(|error| format!("Failed to create screenshot cache: {error}"))?;
     let new_img_path = file_cache_dir.join(&new_file_name);
 
-    let (img, was_resized) = resize_for_steam(img, img_name, extension, options);
-    let is_jpeg = extension.eq_ignore_ascii_case("jpg") || extension.eq_ignore_ascii_case("jpeg");
+    let (width, height) = loaded.img.dimensions();
+    let vr = options
+        .vr_mode
+        .layout(options.stereo_layout, width, height)
+        .map(|layout| {
+            let vr_img_path = file_cache_dir.join(format!("{img_name}_{}_vr.jpg", options.app_id));
+            (layout, vr_img_path)
+        });
+    let loaded = match &vr {
+        Some((layout, vr_img_path)) => write_vr_image(
+            source,
+            loaded,
+            *layout,
+            img_name,
+            extension,
+            vr_img_path,
+            options,
+        )?,
+        None => loaded,
+    };
 
-    if is_jpeg && !was_resized {
-        info!("Copying image {img_name}.{extension}");
-        copy(img_path, &new_img_path)
-            .map_err(|error| format!("Failed to copy {}: {error}", new_img_path.display()))?;
-    } else {
-        info!(
-            "Encoding image {img_name}.{extension} as jpg with {:?} q{}",
-            options.filter_type, options.jpeg_quality
-        );
-        let file = File::create(&new_img_path)
-            .map_err(|error| format!("Failed to create {}: {error}", new_img_path.display()))?;
-        let writer = BufWriter::new(file);
-        let mut encoder = JpegEncoder::new_with_quality(writer, options.jpeg_quality);
-        encoder
-            .encode_image(&img)
-            .map_err(|error| format!("Failed to encode {img_name}.{extension}: {error}"))?;
-    }
+    let (img, (width, height)) =
+        write_screenshot(source, loaded, img_name, extension, &new_img_path, options)?;
 
     report_step_progress(ctx, progress_remaining, 0.3);
 
+    ctx.record(file_path, JournalFileState::Encoded);
+
+    // The screenshot as it's added to the library is hashed, like the ones already in it
+    let hashes = ctx
+        .duplicates
+        .as_ref()
+        .map(|_| (file_sha256(&new_img_path).ok(), perceptual_hash(&img)));
+
+    check_cancelled(ctx)?;
+
     // Create thumbnail image
     info!(
         "Resizing image {img_name}.{extension} for thumbnail with {:?} q{}",
         options.filter_type, options.jpeg_quality
     );
     let thumb_img_path = file_cache_dir.join(&new_thumbnail_name);
-
-    let thumb_height =
-        (u64::from(THUMB_WIDTH) * u64::from(img.height()) / u64::from(img.width())).max(1);
-    let thumb_height = u32::try_from(thumb_height)
-        .map_err(|error| format!("Invalid thumbnail height: {error}"))?;
-    let thumb_img = resize(&img, THUMB_WIDTH, thumb_height, options.filter_type);
-    let file = File::create(&thumb_img_path)
-        .map_err(|error| format!("Failed to create {}: {error}", thumb_img_path.display()))?;
-    let writer = BufWriter::new(&file);
-    let mut encoder = JpegEncoder::new_with_quality(writer, options.jpeg_quality);
-    encoder.encode_image(&thumb_img).map_err(|error| {
+    create_thumbnail(&img, &thumb_img_path, options).map_err(|error| {
         format!("Failed to create thumbnail for {img_name}.{extension}: {error}")
     })?;
 
     report_step_progress(ctx, progress_remaining, 0.4);
 
-    // Import screenshot
+    Ok(ProcessedScreenshot {
+        file_path: file_path.to_string(),
+        name: format!("{img_name}.{extension}"),
+        new_file_name,
+        path: new_img_path,
+        thumbnail_path: thumb_img_path,
+        width,
+        height,
+        capture_time: source.capture_time(file_path, options.capture_time),
+        vr: vr.map(|(layout, vr_img_path)| (layout.kind, vr_img_path)),
+        hashes,
+        duplicate: None,
+        progress_remaining: *progress_remaining,
+    })
+}
+
+/// Decodes a screenshot, converts it to sRGB and to 8-bit SDR if it's HDR, fills in any
+/// transparency and picks the metadata that's kept.
+fn load_image(
+    source: &ScreenshotSource,
+    file_name: &str,
+    extension: &str,
+    options: ImportOptions,
+) -> Result<LoadedImage, String> {
+    let DecodedImage {
+        img,
+        full_size,
+        profile,
+        reoriented,
+        exif,
+        xmp,
+    } = source.decode(
+        file_name,
+        extension,
+        options.memory_budget(),
+        // VR screenshots are cropped to their preview, which needs every pixel
+        options.vr_mode == VrMode::Off,
+    )?;
+    if reoriented {
+        info!("Rotated {file_name} to its EXIF orientation");
+    }
+
+    let (img, colors_converted) = match to_srgb(&img, &profile) {
+        Ok(Some(converted)) => (converted, true),
+        Ok(None) => (img, false),
+        Err(error) => {
+            warn!("Keeping the colors of {file_name} as they are: {error}");
+            (img, false)
+        }
+    };
+    // The profile of HDR screenshots says how they're encoded, the option is for those without
+    let (transfer, primaries) = profile
+        .hdr_signal()
+        .unwrap_or((options.hdr_transfer, Primaries::Bt709));
+    let img = to_sdr(
+        img,
+        options.tone_map,
+        options.exposure,
+        options.white_point,
+        transfer,
+        primaries,
+    );
+
+    let exif = match options.metadata {
+        MetadataPolicy::KeepSelected => selected_exif(exif.as_deref(), xmp.as_deref())
+            .unwrap_or_else(|error| {
+                warn!("Leaving out the metadata of {file_name}: {error}");
+                None
+            }),
+        MetadataPolicy::StripAll => None,
+    };
+
+    Ok(LoadedImage {
+        img: flatten(img, options.background, options.background_color),
+        full_size,
+        modified: reoriented || colors_converted,
+        exif,
+    })
+}
+
+/// Writes the VR image of a VR screenshot like a regular screenshot, and returns the preview
+/// that's shown outside VR to be written as the screenshot.
+fn write_vr_image(
+    source: &ScreenshotSource,
+    loaded: LoadedImage,
+    layout: VrLayout,
+    img_name: &str,
+    extension: &str,
+    vr_img_path: &Path,
+    options: ImportOptions,
+) -> Result<LoadedImage, String> {
     info!(
-        "Importing screenshot {} {}",
-        new_img_path.display(),
-        thumb_img_path.display()
+        "Importing {img_name}.{extension} as a {:?} VR screenshot",
+        layout.kind
     );
-    unsafe {
-        let screenshots = get_steam_screenshots();
-        let screenshot_path = CString::new(new_img_path.to_string_lossy().as_bytes())
-            .map_err(|error| format!("Invalid screenshot path: {error}"))?;
-        let thumbnail_path = CString::new(thumb_img_path.to_string_lossy().as_bytes())
-            .map_err(|error| format!("Invalid thumbnail path: {error}"))?;
-        let width = img
-            .width()
-            .try_into()
-            .map_err(|error| format!("Invalid screenshot width: {error}"))?;
-        let height = img
-            .height()
-            .try_into()
-            .map_err(|error| format!("Invalid screenshot height: {error}"))?;
-
-        let screenshot_handle = add_screenshot_to_library(
-            screenshots,
-            screenshot_path.as_ptr(),
-            thumbnail_path.as_ptr(),
-            width,
-            height,
-        );
+    let preview = LoadedImage {
+        img: layout.preview(&loaded.img),
+        full_size: None,
+        modified: true,
+        exif: loaded.exif.clone(),
+    };
+    write_screenshot(source, loaded, img_name, extension, vr_img_path, options)?;
+    Ok(preview)
+}
 
-        if screenshot_handle == INVALID_SCREENSHOT_HANDLE {
-            return Err(format!(
-                "Steam failed to import {img_name}.{extension} into its screenshot library"
-            ));
-        }
+/// Writes the screenshot that's added to the library, downscaled if it's too large. JPEGs that
+/// fit and whose pixels didn't change are copied with only their metadata replaced. Returns the
+/// image to make the thumbnail from, along with the size of the written screenshot.
+fn write_screenshot(
+    source: &ScreenshotSource,
+    loaded: LoadedImage,
+    img_name: &str,
+    extension: &str,
+    new_img_path: &Path,
+    options: ImportOptions,
+) -> Result<(DynamicImage, (u32, u32)), String> {
+    let LoadedImage {
+        img,
+        full_size,
+        modified,
+        exif,
+    } = loaded;
+    let (img, was_resized) = match full_size {
+        Some(_) => (img, false),
+        None => resize_for_steam(img, img_name, extension, options)?,
+    };
+    let is_jpeg = extension.eq_ignore_ascii_case("jpg") || extension.eq_ignore_ascii_case("jpeg");
 
-        ctx.client
-            .lock()
-            .map_err(|error| format!("Failed to access Steam client: {error}"))?
-            .run_callbacks();
+    // Previews are only decoded for JPEGs that are copied, whatever their extension
+    if full_size.is_some() || (is_jpeg && !was_resized && !modified) {
+        info!("Copying image {img_name}.{extension}");
+        source
+            .copy_jpeg_to(new_img_path, exif.as_deref())
+            .map_err(|error| format!("Failed to copy {}: {error}", new_img_path.display()))?;
+    } else {
+        info!(
+            "Encoding image {img_name}.{extension} as jpg with {:?} q{}",
+            options.filter_type, options.jpeg_quality
+        );
+        encode_jpeg(&img, new_img_path, options, exif)
+            .map_err(|error| format!("Failed to encode {img_name}.{extension}: {error}"))?;
     }
-    info!("Import of {img_name}.{extension} complete");
 
-    report_step_progress(ctx, progress_remaining, 0.3);
+    let size = full_size.unwrap_or_else(|| img.dimensions());
+    Ok((img, size))
+}
 
-    Ok(())
+fn create_thumbnail(
+    img: &DynamicImage,
+    thumb_img_path: &Path,
+    options: ImportOptions,
+) -> Result<(), String> {
+    let thumb_height =
+        (u64::from(THUMB_WIDTH) * u64::from(img.height()) / u64::from(img.width())).max(1);
+    let thumb_height = u32::try_from(thumb_height)
+        .map_err(|error| format!("Invalid thumbnail height: {error}"))?;
+    let thumb_img = resize(img, THUMB_WIDTH, thumb_height, options)?;
+    encode_jpeg(&thumb_img, thumb_img_path, options, None)
+}
+
+fn encode_jpeg<I: GenericImageView>(
+    img: &I,
+    path: &Path,
+    options: ImportOptions,
+    exif: Option<Vec<u8>>,
+) -> Result<(), String>
+where
+    I::Pixel: PixelWithColorType,
+{
+    let file = File::create(path)
+        .map_err(|error| format!("Failed to create {}: {error}", path.display()))?;
+    let writer = BufWriter::new(file);
+    let mut encoder = JpegEncoder::new_with_quality(writer, options.jpeg_quality);
+    if options.embed_srgb_profile {
+        encoder
+            .set_icc_profile(srgb_icc_profile()?)
+            .map_err(|error| error.to_string())?;
+    }
+    if let Some(exif) = exif {
+        encoder
+            .set_exif_metadata(exif)
+            .map_err(|error| error.to_string())?;
+    }
+    encoder.encode_image(img).map_err(|error| error.to_string())
 }
 
 fn resize_for_steam(
@@ -339,12 +1761,9 @@ fn resize_for_steam(
     img_name: &str,
     extension: &str,
     options: ImportOptions,
-) -> (DynamicImage, bool) {
-    if img.width() <= MAX_SIDE
-        && img.height() <= MAX_SIDE
-        && img.width() * img.height() <= MAX_RESOLUTION
-    {
-        return (img, false);
+) -> Result<(DynamicImage, bool), String> {
+    if fits_steam(img.width(), img.height()) {
+        return Ok((img, false));
     }
 
     warn!(
@@ -353,10 +1772,39 @@ fn resize_for_steam(
     );
 
     let (new_width, new_height) = downscaled_dimensions(img.width(), img.height());
-    let img = img.resize_exact(new_width, new_height, options.filter_type);
+    let img = resize(&img, new_width, new_height, options)
+        .map_err(|error| format!("Failed to downscale {img_name}.{extension}: {error}"))?;
 
     info!("{img_name}.{extension} new size: {new_width}x{new_height}");
-    (img, true)
+    Ok((img, true))
+}
+
+/// Whether Steam accepts a screenshot of this size without downscaling it.
+fn fits_steam(width: u32, height: u32) -> bool {
+    width <= MAX_SIDE
+        && height <= MAX_SIDE
+        && u64::from(width) * u64::from(height) <= u64::from(MAX_RESOLUTION)
+}
+
+/// Resizes a screenshot the way the import options say, and sharpens it afterwards.
+fn resize(
+    img: &DynamicImage,
+    width: u32,
+    height: u32,
+    options: ImportOptions,
+) -> Result<DynamicImage, String> {
+    let resized = resize_image(
+        img,
+        width,
+        height,
+        options.filter_type,
+        options.linear_resize,
+    )?;
+    Ok(sharpen(
+        resized,
+        options.sharpen_amount,
+        options.sharpen_radius,
+    ))
 }
 
 fn downscaled_dimensions(width: u32, height: u32) -> (u32, u32) {
@@ -390,7 +1838,7 @@ fn downscaled_dimensions(width: u32, height: u32) -> (u32, u32) {
 
 fn report_step_progress(ctx: &ImportContext, progress_remaining: &mut f32, step_progress: f32) {
     update_progress(
-        &ctx.window,
+        ctx.progress,
         &ctx.screenshots_completed,
         ctx.total_screenshots,
         step_progress,
@@ -399,7 +1847,7 @@ fn report_step_progress(ctx: &ImportContext, progress_remaining: &mut f32, step_
 }
 
 fn update_progress(
-    window: &tauri::Window<AppRuntime>,
+    progress_sink: &dyn ImportProgress,
     screenshots_completed: &AtomicF32,
     total_screenshots: usize,
     step_progress: f32,
@@ -407,7 +1855,133 @@ fn update_progress(
     let completed = screenshots_completed.fetch_add(step_progress, Ordering::SeqCst);
     #[allow(clippy::cast_precision_loss)]
     let progress = ((completed + step_progress) / total_screenshots as f32) * 100.0;
-    if let Err(error) = window.emit(PROGRESS_EVENT, progress) {
-        error!("Failed to emit screenshot import progress: {error}");
+    progress_sink.report(progress);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screenshot_library::RecordingLibrary;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+    use tempfile::TempDir;
+
+    struct NoProgress;
+
+    impl ImportProgress for NoProgress {
+        fn report(&self, _progress: f32) {}
+    }
+
+    /// Writes a PNG of `width` by `height` pixels, with transparency if `alpha` is set.
+    fn write_png(dir: &TempDir, name: &str, width: u32, height: u32, alpha: bool) -> String {
+        let path = dir.path().join(name);
+        if alpha {
+            RgbaImage::from_pixel(width, height, Rgba([200, 100, 50, 128]))
+                .save(&path)
+                .unwrap();
+        } else {
+            RgbImage::from_pixel(width, height, Rgb([200, 100, 50]))
+                .save(&path)
+                .unwrap();
+        }
+        path.to_string_lossy().into_owned()
+    }
+
+    fn import(
+        file_paths: &[String],
+        library: &RecordingLibrary,
+        cancellation: &CancellationToken,
+    ) -> Result<ImportReport, ImportError> {
+        run_import(
+            file_paths,
+            ImportOptions::new(620, 90, ResizeFilterType::Lanczos3),
+            &ImportAnnotations::default(),
+            library,
+            &NoProgress,
+            cancellation,
+            None,
+        )
+    }
+
+    #[test]
+    fn imports_screenshots_into_recording_library_in_order() {
+        let dir = TempDir::new().unwrap();
+        let file_paths = vec![
+            write_png(&dir, "wide.png", 640, 360, false),
+            write_png(&dir, "transparent.png", 300, 400, true),
+        ];
+        let library = RecordingLibrary::default();
+
+        let report = import(&file_paths, &library, &CancellationToken::default()).unwrap();
+
+        assert_eq!(report.imported(), 2);
+        assert!(report.duplicates().is_empty());
+        assert!(report.cancelled().is_empty());
+        let recorded = serde_json::to_value(library.screenshots()).unwrap();
+        let sizes: Vec<_> = recorded
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|screenshot| (&screenshot["width"], &screenshot["height"]))
+            .map(|(width, height)| (width.as_u64().unwrap(), height.as_u64().unwrap()))
+            .collect();
+        assert_eq!(sizes, [(640, 360), (300, 400)]);
+    }
+
+    #[test]
+    fn reports_undecodable_screenshots_as_failed() {
+        let dir = TempDir::new().unwrap();
+        let broken = dir.path().join("broken.png");
+        write(&broken, b"not a png").unwrap();
+        let file_paths = vec![
+            write_png(&dir, "fine.png", 64, 64, false),
+            broken.to_string_lossy().into_owned(),
+        ];
+        let library = RecordingLibrary::default();
+
+        let error = import(&file_paths, &library, &CancellationToken::default()).unwrap_err();
+
+        assert_eq!(error.errors.len(), 1);
+        assert_eq!(error.errors[0].file_path, file_paths[1]);
+        assert_eq!(library.screenshots().len(), 1);
+    }
+
+    #[test]
+    fn reports_screenshots_as_cancelled_once_cancelled() {
+        let dir = TempDir::new().unwrap();
+        let file_paths = vec![write_png(&dir, "cancelled.png", 64, 64, false)];
+        let library = RecordingLibrary::default();
+        let cancellation = CancellationToken::default();
+        cancellation.cancel();
+
+        let report = import(&file_paths, &library, &cancellation).unwrap();
+
+        assert_eq!(report.imported(), 0);
+        assert_eq!(report.cancelled(), file_paths.as_slice());
+        assert!(library.screenshots().is_empty());
+    }
+
+    #[test]
+    fn extra_formats_larger_than_the_budget_are_not_decoded() {
+        let bomb = ExtraDecoder {
+            // A header claiming a decompression bomb
+            dimensions: |_| Ok((60_000, 60_000)),
+            decode: |_| panic!("the image was decoded"),
+        };
+        let error = bomb
+            .decode(b"", "bomb.jxl", MemoryBudget::new(4096, 1))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "bomb.jxl needs more memory to decode than the memory budget of 4096 MiB"
+        );
+
+        let small = ExtraDecoder {
+            dimensions: |_| Ok((2, 2)),
+            decode: |_| Ok((DynamicImage::new_rgb8(2, 2), SourceProfile::Srgb)),
+        };
+        let (img, _) = small
+            .decode(b"", "small.jxl", MemoryBudget::new(4096, 1))
+            .unwrap();
+        assert_eq!(img.dimensions(), (2, 2));
     }
 }
@@ -0,0 +1,600 @@
+use crate::AppRuntime;
+use crate::annotations::ImportAnnotations;
+use crate::file_selection::{FileFilter, expand_paths};
+use crate::image_import::{
+    CancellationToken, ImportError, ImportOptions, ImportProgress, ImportReport, run_import,
+};
+use crate::import_journal::{self, ImportJournal, UnfinishedImport};
+use crate::screenshot_library::LibraryBackend;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tauri::{AppHandle, Emitter};
+
+const JOB_UPDATED_EVENT: &str = "importJobUpdated";
+
+pub type ImportJobId = u32;
+
+/// Called with the result of a job once it finished.
+pub type JobCallback = Box<dyn FnOnce(&Result<ImportReport, ImportError>) + Send>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, specta::Type)]
+pub enum ImportJobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Status of an import job, emitted to the frontend whenever it changes.
+#[derive(Clone, Serialize, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportJobInfo {
+    id: ImportJobId,
+    app_id: u32,
+    file_count: u32,
+    status: ImportJobStatus,
+    /// Percentage of the job's screenshots that have been processed
+    progress: f32,
+}
+
+struct ImportJob {
+    info: ImportJobInfo,
+    file_paths: Vec<String>,
+    options: ImportOptions,
+    annotations: ImportAnnotations,
+    library: Option<LibraryBackend>,
+    cancellation: CancellationToken,
+    journal_id: Option<String>,
+    journal: Option<ImportJournal>,
+    on_finish: Option<JobCallback>,
+    result: Option<Result<ImportReport, ImportError>>,
+}
+
+impl ImportJob {
+    fn queued(
+        id: ImportJobId,
+        file_paths: Vec<String>,
+        options: ImportOptions,
+        annotations: ImportAnnotations,
+        library: Option<LibraryBackend>,
+        journal: Option<ImportJournal>,
+        on_finish: Option<JobCallback>,
+    ) -> Self {
+        Self {
+            info: ImportJobInfo {
+                id,
+                app_id: options.app_id(),
+                file_count: u32::try_from(file_paths.len()).unwrap_or(u32::MAX),
+                status: ImportJobStatus::Queued,
+                progress: 0.0,
+            },
+            file_paths,
+            options,
+            annotations,
+            library,
+            cancellation: CancellationToken::default(),
+            journal_id: journal.as_ref().map(|journal| journal.id().to_string()),
+            journal,
+            on_finish,
+            result: None,
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        matches!(
+            self.info.status,
+            ImportJobStatus::Queued | ImportJobStatus::Running
+        )
+    }
+}
+
+/// Everything the worker needs to run a job, taken from the queue when it starts.
+struct StartedJob {
+    file_paths: Vec<String>,
+    options: ImportOptions,
+    annotations: ImportAnnotations,
+    library: Option<LibraryBackend>,
+    cancellation: CancellationToken,
+    journal: Option<ImportJournal>,
+}
+
+struct JobTable {
+    app: AppHandle<AppRuntime>,
+    jobs: Mutex<BTreeMap<ImportJobId, ImportJob>>,
+}
+
+impl JobTable {
+    fn update<T>(
+        &self,
+        id: ImportJobId,
+        update: impl FnOnce(&mut ImportJob) -> T,
+    ) -> Result<T, String> {
+        let mut jobs = self
+            .jobs
+            .lock()
+            .map_err(|error| format!("Failed to access import jobs: {error}"))?;
+        let job = jobs
+            .get_mut(&id)
+            .ok_or_else(|| format!("Unknown import job {id}"))?;
+        let result = update(job);
+        let info = job.info.clone();
+        drop(jobs);
+
+        if let Err(error) = self.app.emit(JOB_UPDATED_EVENT, info) {
+            error!("Failed to emit import job update: {error}");
+        }
+        Ok(result)
+    }
+
+    fn start(&self, id: ImportJobId) -> Option<StartedJob> {
+        self.update(id, |job| {
+            if job.info.status != ImportJobStatus::Queued {
+                return None;
+            }
+            job.info.status = ImportJobStatus::Running;
+
+            Some(StartedJob {
+                file_paths: std::mem::take(&mut job.file_paths),
+                options: job.options,
+                annotations: std::mem::take(&mut job.annotations),
+                library: job.library,
+                cancellation: job.cancellation.clone(),
+                journal: job.journal.take(),
+            })
+        })
+        .inspect_err(|error| error!("{error}"))
+        .ok()
+        .flatten()
+    }
+
+    fn finish(&self, id: ImportJobId, result: Result<ImportReport, ImportError>) {
+        let status = match &result {
+            Ok(report) if !report.cancelled().is_empty() => ImportJobStatus::Cancelled,
+            Ok(_) => ImportJobStatus::Completed,
+            Err(_) => ImportJobStatus::Failed,
+        };
+
+        let finished = result.clone();
+        match self.update(id, |job| {
+            job.info.status = status;
+            job.info.progress = 100.0;
+            job.result = Some(result);
+            job.on_finish.take()
+        }) {
+            Ok(Some(on_finish)) => on_finish(&finished),
+            Ok(None) => {}
+            Err(error) => error!("{error}"),
+        }
+    }
+}
+
+/// Reports the progress of a running import on its job.
+struct JobProgress<'a> {
+    table: &'a JobTable,
+    id: ImportJobId,
+}
+
+impl ImportProgress for JobProgress<'_> {
+    fn report(&self, progress: f32) {
+        if let Err(error) = self.table.update(self.id, |job| {
+            job.info.progress = progress.min(100.0);
+        }) {
+            error!("{error}");
+        }
+    }
+}
+
+/// Queue of screenshot imports. Jobs run one at a time on a dedicated thread so the async runtime
+/// stays free for other commands, and each import gets its own cache directory.
+pub struct ImportJobs {
+    table: Arc<JobTable>,
+    queue: Sender<ImportJobId>,
+    next_id: AtomicU32,
+}
+
+impl ImportJobs {
+    pub fn new(app: AppHandle<AppRuntime>) -> Self {
+        let table = Arc::new(JobTable {
+            app,
+            jobs: Mutex::new(BTreeMap::new()),
+        });
+        let (queue, receiver) = channel();
+
+        let worker_table = Arc::clone(&table);
+        thread::Builder::new()
+            .name("import-jobs".to_string())
+            .spawn(move || run_worker(&worker_table, &receiver))
+            .expect("failed to spawn import job thread");
+
+        Self {
+            table,
+            queue,
+            next_id: AtomicU32::new(1),
+        }
+    }
+
+    /// Queues a new, journaled import, calling `on_finish` with its result once it's done.
+    pub fn start(
+        &self,
+        file_paths: Vec<String>,
+        options: ImportOptions,
+        annotations: ImportAnnotations,
+        library: Option<LibraryBackend>,
+        on_finish: Option<JobCallback>,
+    ) -> Result<ImportJobId, String> {
+        let journal = ImportJournal::create(&file_paths, options, &annotations, library)
+            .inspect_err(|error| warn!("Importing without a journal: {error}"))
+            .ok();
+        self.enqueue(
+            file_paths,
+            options,
+            annotations,
+            library,
+            journal,
+            on_finish,
+        )
+    }
+
+    fn enqueue(
+        &self,
+        file_paths: Vec<String>,
+        options: ImportOptions,
+        annotations: ImportAnnotations,
+        library: Option<LibraryBackend>,
+        journal: Option<ImportJournal>,
+        on_finish: Option<JobCallback>,
+    ) -> Result<ImportJobId, String> {
+        let mut jobs = self
+            .table
+            .jobs
+            .lock()
+            .map_err(|error| format!("Failed to access import jobs: {error}"))?;
+        self.queue_job(&mut jobs, |id| {
+            ImportJob::queued(
+                id,
+                file_paths,
+                options,
+                annotations,
+                library,
+                journal,
+                on_finish,
+            )
+        })
+    }
+
+    /// Queues the screenshots of an unfinished import that weren't added to the library yet.
+    fn resume(&self, journal_id: &str) -> Result<ImportJobId, String> {
+        // Holding the lock until the job is queued keeps the same import from being resumed twice
+        let mut jobs = self
+            .table
+            .jobs
+            .lock()
+            .map_err(|error| format!("Failed to access import jobs: {error}"))?;
+        if jobs
+            .values()
+            .any(|job| job.is_active() && job.journal_id.as_deref() == Some(journal_id))
+        {
+            return Err("This import is already running".to_string());
+        }
+
+        let (journal, contents) = ImportJournal::open(journal_id)?;
+        let file_paths = contents.remaining_files();
+        if file_paths.is_empty() {
+            journal.remove();
+            return Err("Every screenshot of this import was already imported".to_string());
+        }
+
+        info!(
+            "Resuming import {journal_id} with {} of {} screenshots left",
+            file_paths.len(),
+            contents.files.len()
+        );
+        self.queue_job(&mut jobs, |id| {
+            ImportJob::queued(
+                id,
+                file_paths,
+                contents.options.open_library(true),
+                contents.annotations,
+                contents.library,
+                Some(journal),
+                None,
+            )
+        })
+    }
+
+    fn queue_job(
+        &self,
+        jobs: &mut BTreeMap<ImportJobId, ImportJob>,
+        job: impl FnOnce(ImportJobId) -> ImportJob,
+    ) -> Result<ImportJobId, String> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        jobs.insert(id, job(id));
+
+        self.queue
+            .send(id)
+            .map_err(|error| format!("Failed to queue import job {id}: {error}"))?;
+        info!("Queued import job {id}");
+        Ok(id)
+    }
+
+    fn cancel(&self, id: ImportJobId) -> Result<(), String> {
+        let cancelled_before_start = self.table.update(id, |job| {
+            job.cancellation.cancel();
+
+            // Queued jobs never start, so they're finished here rather than by the worker
+            if job.info.status != ImportJobStatus::Queued {
+                return None;
+            }
+            if let Some(journal) = job.journal.take() {
+                journal.remove();
+            }
+            let result = Ok(ImportReport::cancelled_before_start(std::mem::take(
+                &mut job.file_paths,
+            )));
+            job.info.status = ImportJobStatus::Cancelled;
+            job.result = Some(result.clone());
+            job.on_finish.take().map(|on_finish| (on_finish, result))
+        })?;
+
+        info!("Cancelling import job {id}");
+        if let Some((on_finish, result)) = cancelled_before_start {
+            on_finish(&result);
+        }
+        Ok(())
+    }
+
+    fn jobs(&self) -> Result<Vec<ImportJobInfo>, String> {
+        Ok(self
+            .table
+            .jobs
+            .lock()
+            .map_err(|error| format!("Failed to access import jobs: {error}"))?
+            .values()
+            .map(|job| job.info.clone())
+            .collect())
+    }
+
+    fn job(&self, id: ImportJobId) -> Result<ImportJobInfo, String> {
+        self.table
+            .jobs
+            .lock()
+            .map_err(|error| format!("Failed to access import jobs: {error}"))?
+            .get(&id)
+            .map(|job| job.info.clone())
+            .ok_or_else(|| format!("Unknown import job {id}"))
+    }
+
+    /// IDs of the journals of jobs that haven't finished yet.
+    fn active_journals(&self) -> Result<Vec<String>, String> {
+        Ok(self
+            .table
+            .jobs
+            .lock()
+            .map_err(|error| format!("Failed to access import jobs: {error}"))?
+            .values()
+            .filter(|job| job.is_active())
+            .filter_map(|job| job.journal_id.clone())
+            .collect())
+    }
+
+    fn result(&self, id: ImportJobId) -> Result<ImportReport, ImportError> {
+        let jobs = self
+            .table
+            .jobs
+            .lock()
+            .map_err(|error| format!("Failed to access import jobs: {error}"))?;
+        let job = jobs
+            .get(&id)
+            .ok_or_else(|| format!("Unknown import job {id}"))?;
+
+        job.result
+            .clone()
+            .unwrap_or_else(|| Err(format!("Import job {id} hasn't finished yet").into()))
+    }
+}
+
+fn run_worker(table: &JobTable, queue: &Receiver<ImportJobId>) {
+    for id in queue {
+        let Some(job) = table.start(id) else {
+            continue;
+        };
+
+        info!("Starting import job {id}");
+        let progress = JobProgress { table, id };
+        let result = job
+            .library
+            .unwrap_or_else(LibraryBackend::from_env)
+            .connect(job.options.app_id())
+            .map_err(ImportError::from)
+            .and_then(|library| {
+                run_import(
+                    &job.file_paths,
+                    job.options,
+                    &job.annotations,
+                    library.as_ref(),
+                    &progress,
+                    &job.cancellation,
+                    job.journal.as_ref(),
+                )
+            });
+
+        info!("Import job {id} finished");
+        if is_complete(&result)
+            && let Some(journal) = &job.journal
+        {
+            journal.remove();
+        }
+        table.finish(id, result);
+    }
+}
+
+/// Whether every screenshot of an import was dealt with, so its journal isn't needed anymore. The
+/// journal of failed and cancelled imports is kept so the rest of the screenshots can be
+/// resumed, e.g. after Steam was closed part way through.
+fn is_complete(result: &Result<ImportReport, ImportError>) -> bool {
+    matches!(result, Ok(report) if report.cancelled().is_empty())
+}
+
+/// Queues an import of the given screenshots and the ones in the given folders that pass
+/// `filter`, captioned and tagged with `annotations`, and returns its job ID.
+///
+/// # Errors
+///
+/// Returns an error if the filter or an ID in the annotations is invalid, no screenshots were
+/// found, or the import job could not be queued.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn import_screenshots(
+    paths: Vec<String>,
+    options: ImportOptions,
+    annotations: Option<ImportAnnotations>,
+    library: Option<LibraryBackend>,
+    filter: Option<FileFilter>,
+    jobs: tauri::State<'_, ImportJobs>,
+) -> Result<ImportJobId, String> {
+    let annotations = annotations.unwrap_or_default();
+    annotations.parse()?;
+
+    // Walking folders and reading capture dates can take a while, so it's kept off the runtime
+    let filter = filter.unwrap_or_default();
+    let file_paths = tauri::async_runtime::spawn_blocking(move || expand_paths(&paths, &filter))
+        .await
+        .map_err(|error| format!("Failed to search for screenshots: {error}"))??;
+    if file_paths.is_empty() {
+        return Err("No screenshots found in the selected folders".to_string());
+    }
+
+    jobs.start(
+        file_paths,
+        options.open_library(true),
+        annotations,
+        library,
+        None,
+    )
+}
+
+/// Cancels an import job. Screenshots that were already added to the library stay imported.
+///
+/// # Errors
+///
+/// Returns an error if there's no job with the given ID.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::needless_pass_by_value)]
+pub fn cancel_import(
+    job_id: ImportJobId,
+    jobs: tauri::State<'_, ImportJobs>,
+) -> Result<(), String> {
+    jobs.cancel(job_id)
+}
+
+/// Lists all import jobs started since the app was launched.
+///
+/// # Errors
+///
+/// Returns an error if the job list could not be accessed.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::needless_pass_by_value)]
+pub fn get_import_jobs(jobs: tauri::State<'_, ImportJobs>) -> Result<Vec<ImportJobInfo>, String> {
+    jobs.jobs()
+}
+
+/// Gets the status and progress of an import job.
+///
+/// # Errors
+///
+/// Returns an error if there's no job with the given ID.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::needless_pass_by_value)]
+pub fn get_import_job(
+    job_id: ImportJobId,
+    jobs: tauri::State<'_, ImportJobs>,
+) -> Result<ImportJobInfo, String> {
+    jobs.job(job_id)
+}
+
+/// Gets the per-file results of a finished import job.
+///
+/// # Errors
+///
+/// Returns the job's [`ImportError`] if it failed, or an error if it doesn't exist or hasn't
+/// finished yet.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::needless_pass_by_value)]
+pub fn get_import_job_result(
+    job_id: ImportJobId,
+    jobs: tauri::State<'_, ImportJobs>,
+) -> Result<ImportReport, ImportError> {
+    jobs.result(job_id)
+}
+
+/// Lists imports that were interrupted, e.g. by a crash, and can be resumed.
+///
+/// # Errors
+///
+/// Returns an error if the import journals could not be read.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::needless_pass_by_value)]
+pub fn get_unfinished_imports(
+    jobs: tauri::State<'_, ImportJobs>,
+) -> Result<Vec<UnfinishedImport>, String> {
+    import_journal::unfinished_imports(&jobs.active_journals()?)
+}
+
+/// Queues the screenshots of an unfinished import that weren't added to the library yet.
+///
+/// # Errors
+///
+/// Returns an error if the import's journal could not be read, or it has nothing left to import.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::needless_pass_by_value)]
+pub fn resume_import(
+    journal_id: String,
+    jobs: tauri::State<'_, ImportJobs>,
+) -> Result<ImportJobId, String> {
+    jobs.resume(&journal_id)
+}
+
+/// Deletes the journal of an unfinished import so it's no longer offered to be resumed.
+///
+/// # Errors
+///
+/// Returns an error if the import is running or its journal could not be removed.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::needless_pass_by_value)]
+pub fn discard_import(
+    journal_id: String,
+    jobs: tauri::State<'_, ImportJobs>,
+) -> Result<(), String> {
+    if jobs.active_journals()?.contains(&journal_id) {
+        return Err("This import is still running".to_string());
+    }
+    import_journal::discard(&journal_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journals_of_cancelled_imports_are_kept() {
+        let cancelled = ImportReport::cancelled_before_start(vec!["shot.png".to_string()]);
+        assert!(!is_complete(&Ok(cancelled)));
+        assert!(is_complete(&Ok(ImportReport::cancelled_before_start(
+            Vec::new()
+        ))));
+    }
+}
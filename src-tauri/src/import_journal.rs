@@ -0,0 +1,338 @@
+use crate::annotations::ImportAnnotations;
+use crate::app_dirs::PROJECT_DIRS;
+use crate::image_import::ImportOptions;
+use crate::screenshot_library::{LibraryBackend, ScreenshotHandle};
+use chrono::{Local, SecondsFormat};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions, create_dir_all, read_dir, read_to_string, remove_file};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const JOURNAL_DIR: &str = "journals";
+const JOURNAL_EXTENSION: &str = "jsonl";
+
+/// Number of journals created by this process, used to keep their IDs unique.
+static JOURNALS_CREATED: AtomicU32 = AtomicU32::new(0);
+
+/// How far a screenshot of a journaled import got.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum JournalFileState {
+    Pending,
+    /// Converted into the cache, but not confirmed to be registered with the library
+    Encoded,
+    Registered {
+        handle: ScreenshotHandle,
+    },
+    /// Not imported because it was a duplicate
+    Skipped,
+    Failed {
+        message: String,
+    },
+}
+
+impl JournalFileState {
+    fn is_done(&self) -> bool {
+        matches!(self, Self::Registered { .. } | Self::Skipped)
+    }
+}
+
+/// A line of a journal. The first one describes the import and every following one records a
+/// screenshot's new state, so that a crash can at most lose the line being written.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum JournalRecord {
+    #[serde(rename_all = "camelCase")]
+    Started {
+        started: String,
+        options: ImportOptions,
+        #[serde(default)]
+        annotations: ImportAnnotations,
+        library: Option<LibraryBackend>,
+        file_paths: Vec<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    File {
+        file_path: String,
+        state: JournalFileState,
+    },
+}
+
+/// The state of an import read back from its journal.
+pub struct JournalContents {
+    pub started: String,
+    pub options: ImportOptions,
+    pub annotations: ImportAnnotations,
+    pub library: Option<LibraryBackend>,
+    pub files: Vec<(String, JournalFileState)>,
+}
+
+impl JournalContents {
+    /// Screenshots that still have to be imported, including ones that failed.
+    #[must_use]
+    pub fn remaining_files(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .filter(|(_, state)| !state.is_done())
+            .map(|(file_path, _)| file_path.clone())
+            .collect()
+    }
+}
+
+/// An import that was interrupted before it finished, e.g. because the app crashed.
+#[derive(Clone, Serialize, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct UnfinishedImport {
+    id: String,
+    app_id: u32,
+    /// When the import was first started, as an RFC 3339 timestamp
+    started: String,
+    file_count: u32,
+    registered: u32,
+    failed: u32,
+}
+
+/// Append-only record of the state of every screenshot in an import, kept in the data directory
+/// until the import finishes so an interrupted one can be resumed.
+pub struct ImportJournal {
+    id: String,
+    file: Mutex<File>,
+}
+
+impl ImportJournal {
+    /// Creates a journal for a new import with all of `file_paths` pending.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal file could not be created.
+    pub fn create(
+        file_paths: &[String],
+        options: ImportOptions,
+        annotations: &ImportAnnotations,
+        library: Option<LibraryBackend>,
+    ) -> Result<Self, String> {
+        let dir = journal_dir();
+        create_dir_all(&dir)
+            .map_err(|error| format!("Failed to create {}: {error}", dir.display()))?;
+
+        let now = Local::now();
+        let id = format!(
+            "{}-{}-{}",
+            now.format("%Y%m%d%H%M%S"),
+            process::id(),
+            JOURNALS_CREATED.fetch_add(1, Ordering::SeqCst)
+        );
+        let path = journal_path(&id)?;
+        let file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&path)
+            .map_err(|error| format!("Failed to create {}: {error}", path.display()))?;
+
+        let journal = Self {
+            id,
+            file: Mutex::new(file),
+        };
+        journal.append(&JournalRecord::Started {
+            started: now.to_rfc3339_opts(SecondsFormat::Secs, false),
+            options,
+            annotations: annotations.clone(),
+            library,
+            file_paths: file_paths.to_vec(),
+        })?;
+
+        info!("Journaling import to {}", path.display());
+        Ok(journal)
+    }
+
+    /// Opens an existing journal to resume its import, appending to it from then on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal doesn't exist or could not be read.
+    pub fn open(id: &str) -> Result<(Self, JournalContents), String> {
+        let contents = read_journal(id)?;
+        let path = journal_path(id)?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .map_err(|error| format!("Failed to open {}: {error}", path.display()))?;
+
+        Ok((
+            Self {
+                id: id.to_string(),
+                file: Mutex::new(file),
+            },
+            contents,
+        ))
+    }
+
+    #[must_use]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Records the new state of a screenshot. Failing to do so only makes resuming less precise,
+    /// so it doesn't fail the import.
+    pub fn record(&self, file_path: &str, state: JournalFileState) {
+        let record = JournalRecord::File {
+            file_path: file_path.to_string(),
+            state,
+        };
+        if let Err(error) = self.append(&record) {
+            warn!("Failed to update import journal {}: {error}", self.id);
+        }
+    }
+
+    /// Deletes the journal of an import that finished.
+    pub fn remove(&self) {
+        discard(&self.id).unwrap_or_else(|error| warn!("{error}"));
+    }
+
+    fn append(&self, record: &JournalRecord) -> Result<(), String> {
+        let mut line = serde_json::to_string(record)
+            .map_err(|error| format!("Failed to serialize journal record: {error}"))?;
+        line.push('\n');
+
+        self.file
+            .lock()
+            .map_err(|error| format!("Failed to access import journal: {error}"))?
+            .write_all(line.as_bytes())
+            .map_err(|error| format!("Failed to write import journal: {error}"))
+    }
+}
+
+/// Lists the imports with a journal left behind, except for the ones in `active`.
+///
+/// # Errors
+///
+/// Returns an error if the journal directory could not be read.
+pub fn unfinished_imports(active: &[String]) -> Result<Vec<UnfinishedImport>, String> {
+    let dir = journal_dir();
+    let entries = match read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(format!("Failed to read {}: {error}", dir.display())),
+    };
+
+    let mut imports = Vec::new();
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some(JOURNAL_EXTENSION) {
+            continue;
+        }
+        let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if active.iter().any(|active_id| active_id == id) {
+            continue;
+        }
+
+        match read_journal(id) {
+            Ok(contents) => imports.push(summarize(id, &contents)),
+            Err(error) => warn!("Ignoring import journal {id}: {error}"),
+        }
+    }
+
+    imports.sort_unstable_by(|a, b| a.started.cmp(&b.started));
+    Ok(imports)
+}
+
+/// Deletes the journal of an import that won't be resumed.
+///
+/// # Errors
+///
+/// Returns an error if the journal could not be removed.
+pub fn discard(id: &str) -> Result<(), String> {
+    let path = journal_path(id)?;
+    match remove_file(&path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => {
+            Err(format!("Failed to remove {}: {error}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn summarize(id: &str, contents: &JournalContents) -> UnfinishedImport {
+    let count = |matches: fn(&JournalFileState) -> bool| {
+        let count = contents
+            .files
+            .iter()
+            .filter(|(_, state)| matches(state))
+            .count();
+        u32::try_from(count).unwrap_or(u32::MAX)
+    };
+
+    UnfinishedImport {
+        id: id.to_string(),
+        app_id: contents.options.app_id(),
+        started: contents.started.clone(),
+        file_count: count(|_| true),
+        registered: count(JournalFileState::is_done),
+        failed: count(|state| matches!(state, JournalFileState::Failed { .. })),
+    }
+}
+
+fn read_journal(id: &str) -> Result<JournalContents, String> {
+    let path = journal_path(id)?;
+    let text = read_to_string(&path)
+        .map_err(|error| format!("Failed to read {}: {error}", path.display()))?;
+    let mut lines = text.lines();
+
+    let Some(Ok(JournalRecord::Started {
+        started,
+        options,
+        annotations,
+        library,
+        file_paths,
+    })) = lines.next().map(serde_json::from_str)
+    else {
+        return Err(format!("{} has no valid header", path.display()));
+    };
+
+    let mut states: HashMap<String, JournalFileState> = HashMap::new();
+    for line in lines {
+        // The last line is incomplete if the app crashed while writing it
+        match serde_json::from_str(line) {
+            Ok(JournalRecord::File { file_path, state }) => {
+                states.insert(file_path, state);
+            }
+            Ok(JournalRecord::Started { .. }) => {}
+            Err(error) => warn!("Skipping invalid line in {}: {error}", path.display()),
+        }
+    }
+
+    let files = file_paths
+        .into_iter()
+        .map(|file_path| {
+            let state = states
+                .remove(&file_path)
+                .unwrap_or(JournalFileState::Pending);
+            (file_path, state)
+        })
+        .collect();
+
+    Ok(JournalContents {
+        started,
+        options,
+        annotations,
+        library,
+        files,
+    })
+}
+
+fn journal_dir() -> PathBuf {
+    PROJECT_DIRS.data_dir().join(JOURNAL_DIR)
+}
+
+/// Journal IDs come from the frontend, so make sure they can't point outside the journal directory.
+fn journal_path(id: &str) -> Result<PathBuf, String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(format!("Invalid import journal ID: {id}"));
+    }
+    Ok(journal_dir().join(format!("{id}.{JOURNAL_EXTENSION}")))
+}
@@ -0,0 +1,69 @@
+use crate::color_profile::SourceProfile;
+use image::{DynamicImage, Rgba32FImage};
+use jxl_oxide::{JxlImage, PixelFormat};
+use std::io::Cursor;
+
+/// Reads the size of a JPEG XL image from its header, as it's shown once its orientation is
+/// applied.
+pub fn dimensions(bytes: &[u8]) -> Result<(u32, u32), String> {
+    let image = JxlImage::builder()
+        .read(Cursor::new(bytes))
+        .map_err(|error| error.to_string())?;
+    Ok((image.width(), image.height()))
+}
+
+/// Decodes a JPEG XL image, along with the ICC profile of the rendered pixels. The orientation in
+/// the image header is applied while rendering.
+pub fn decode(bytes: &[u8]) -> Result<(DynamicImage, SourceProfile), String> {
+    let image = JxlImage::builder()
+        .read(Cursor::new(bytes))
+        .map_err(|error| error.to_string())?;
+    let (color_channels, has_alpha) = match image.pixel_format() {
+        PixelFormat::Gray => (1, false),
+        PixelFormat::Graya => (1, true),
+        PixelFormat::Rgb => (3, false),
+        PixelFormat::Rgba => (3, true),
+        PixelFormat::Cmyk | PixelFormat::Cmyka => {
+            return Err("CMYK JPEG XL images are not supported".to_string());
+        }
+    };
+
+    let render = image.render_frame(0).map_err(|error| error.to_string())?;
+    let frame = render.image_all_channels();
+    let width = u32::try_from(frame.width()).map_err(|error| error.to_string())?;
+    let height = u32::try_from(frame.height()).map_err(|error| error.to_string())?;
+
+    // Extra channels other than alpha, like depth maps, are dropped
+    let pixels = frame
+        .buf()
+        .chunks_exact(frame.channels())
+        .flat_map(|pixel| {
+            let alpha = if has_alpha {
+                pixel[color_channels]
+            } else {
+                1.0
+            };
+            if color_channels == 1 {
+                [pixel[0], pixel[0], pixel[0], alpha]
+            } else {
+                [pixel[0], pixel[1], pixel[2], alpha]
+            }
+        })
+        .collect();
+    let rendered = DynamicImage::ImageRgba32F(
+        Rgba32FImage::from_raw(width, height, pixels)
+            .ok_or("Rendered JPEG XL image has the wrong size")?,
+    );
+
+    // The rendered samples are encoded with the transfer curve of the output profile, so they're
+    // stored as integers to not be tone mapped as linear light
+    let high_bit_depth = image.image_header().metadata.bit_depth.bits_per_sample() > 8;
+    let img = match (high_bit_depth, has_alpha) {
+        (false, false) => DynamicImage::ImageRgb8(rendered.to_rgb8()),
+        (false, true) => DynamicImage::ImageRgba8(rendered.to_rgba8()),
+        (true, false) => DynamicImage::ImageRgb16(rendered.to_rgb16()),
+        (true, true) => DynamicImage::ImageRgba16(rendered.to_rgba16()),
+    };
+
+    Ok((img, SourceProfile::Icc(image.rendered_icc())))
+}
@@ -0,0 +1,93 @@
+use crate::color_profile::SourceProfile;
+use crate::tone_map::REFERENCE_WHITE;
+use half::f16;
+use image::{DynamicImage, ImageBuffer, Rgb, Rgb32FImage, RgbImage, Rgba, RgbaImage};
+use jpegxr::{ImageDecode, PixelFormat, Rect};
+use std::io::Cursor;
+
+/// Brightness of 1.0 in scRGB, in nits.
+const SCRGB_WHITE: f32 = 80.0;
+
+/// Reads the size of a JPEG XR image from its header.
+pub fn dimensions(bytes: &[u8]) -> Result<(u32, u32), String> {
+    let decoder =
+        ImageDecode::with_reader(Cursor::new(bytes.to_vec())).map_err(|error| error.to_string())?;
+    let (width, height) = decoder.get_size().map_err(|error| error.to_string())?;
+    Ok((
+        u32::try_from(width).map_err(|error| error.to_string())?,
+        u32::try_from(height).map_err(|error| error.to_string())?,
+    ))
+}
+
+/// Decodes a JPEG XR image, like the HDR screenshots taken by the Xbox Game Bar. HDR images are
+/// scRGB, which is scaled so that 1.0 is SDR white like in other linear float images.
+pub fn decode(bytes: &[u8]) -> Result<(DynamicImage, SourceProfile), String> {
+    let mut decoder =
+        ImageDecode::with_reader(Cursor::new(bytes.to_vec())).map_err(|error| error.to_string())?;
+    let format = decoder
+        .get_pixel_format()
+        .map_err(|error| error.to_string())?;
+    let (width, height) = decoder.get_size().map_err(|error| error.to_string())?;
+
+    let bytes_per_pixel = match format {
+        PixelFormat::PixelFormat24bppBGR => 3,
+        PixelFormat::PixelFormat32bppBGRA => 4,
+        PixelFormat::PixelFormat64bppRGBAHalf => 8,
+        PixelFormat::PixelFormat128bppRGBAFloat => 16,
+        _ => return Err("Unsupported JPEG XR pixel format".to_string()),
+    };
+    let pixel_width = u32::try_from(width).map_err(|error| error.to_string())?;
+    let pixel_height = u32::try_from(height).map_err(|error| error.to_string())?;
+    let stride = pixel_width as usize * bytes_per_pixel;
+
+    let mut buffer = vec![0; stride * pixel_height as usize];
+    decoder
+        .copy(&Rect::new(0, 0, width, height), &mut buffer, stride)
+        .map_err(|error| error.to_string())?;
+
+    let img = match format {
+        PixelFormat::PixelFormat24bppBGR => {
+            DynamicImage::ImageRgb8(RgbImage::from_fn(pixel_width, pixel_height, |x, y| {
+                let pixel = &buffer[pixel_offset(x, y, stride, 3)..];
+                Rgb([pixel[2], pixel[1], pixel[0]])
+            }))
+        }
+        PixelFormat::PixelFormat32bppBGRA => {
+            DynamicImage::ImageRgba8(RgbaImage::from_fn(pixel_width, pixel_height, |x, y| {
+                let pixel = &buffer[pixel_offset(x, y, stride, 4)..];
+                Rgba([pixel[2], pixel[1], pixel[0], pixel[3]])
+            }))
+        }
+        // Screenshots are opaque, so the alpha channel of HDR captures is dropped
+        PixelFormat::PixelFormat64bppRGBAHalf => {
+            float_image(pixel_width, pixel_height, |x, y, c| {
+                let offset = pixel_offset(x, y, stride, 8) + c * 2;
+                f16::from_le_bytes([buffer[offset], buffer[offset + 1]]).to_f32()
+            })
+        }
+        _ => float_image(pixel_width, pixel_height, |x, y, c| {
+            let offset = pixel_offset(x, y, stride, 16) + c * 4;
+            f32::from_le_bytes([
+                buffer[offset],
+                buffer[offset + 1],
+                buffer[offset + 2],
+                buffer[offset + 3],
+            ])
+        }),
+    };
+
+    Ok((img, SourceProfile::Srgb))
+}
+
+fn pixel_offset(x: u32, y: u32, stride: usize, bytes_per_pixel: usize) -> usize {
+    y as usize * stride + x as usize * bytes_per_pixel
+}
+
+/// Builds an RGB float image from a function returning each scRGB channel of each pixel.
+fn float_image(width: u32, height: u32, channel: impl Fn(u32, u32, usize) -> f32) -> DynamicImage {
+    let scale = SCRGB_WHITE / REFERENCE_WHITE;
+    let img: Rgb32FImage = ImageBuffer::from_fn(width, height, |x, y| {
+        Rgb([0, 1, 2].map(|c| channel(x, y, c) * scale))
+    });
+    DynamicImage::ImageRgb32F(img)
+}
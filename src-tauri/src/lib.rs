@@ -0,0 +1,117 @@
+pub mod annotations;
+mod app_dirs;
+pub mod archive;
+pub mod background;
+pub mod capture_time;
+mod color_profile;
+mod duplicates;
+mod file_picker;
+pub mod file_selection;
+#[cfg(feature = "heic")]
+mod heic;
+mod image_fetch;
+pub mod image_import;
+mod import_jobs;
+pub mod import_journal;
+#[cfg(feature = "jxl")]
+mod jxl;
+#[cfg(feature = "jxr")]
+mod jxr;
+mod memory_budget;
+mod metadata;
+pub mod resample;
+pub mod screenshot_library;
+mod steam;
+pub mod steam_helper;
+mod steam_locate;
+pub mod tone_map;
+mod userdata;
+mod vdf;
+pub mod vr;
+mod watch_folders;
+
+#[cfg(target_os = "linux")]
+pub type AppRuntime = tauri::Cef;
+#[cfg(not(target_os = "linux"))]
+pub type AppRuntime = tauri::Wry;
+
+use app_dirs::PROJECT_DIRS;
+use import_jobs::ImportJobs;
+use log::{info, warn};
+#[cfg(debug_assertions)]
+use specta_typescript::Typescript;
+use std::fs::create_dir_all;
+use std::thread;
+use tauri::Manager;
+use tauri_specta::{Builder, ErrorHandlingMode, collect_commands};
+use watch_folders::WatchService;
+
+#[cfg(debug_assertions)]
+const TYPESCRIPT_BINDINGS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../src/bindings.ts");
+
+/// Starts the Tauri application.
+///
+/// # Panics
+///
+/// Panics if the cache directory cannot be created or the Tauri application fails to start.
+pub fn run() {
+    let cache_dir = PROJECT_DIRS.cache_dir();
+    info!("Creating cache directory: {}", cache_dir.display());
+    create_dir_all(cache_dir).unwrap();
+
+    // Capture times of screenshots imported through Steamworks can only be restored while Steam
+    // is closed, so try again on every launch
+    thread::spawn(|| {
+        if steam::is_running() {
+            return;
+        }
+        match userdata::apply_creation_fixups() {
+            Ok(0) => {}
+            Ok(applied) => info!("Restored the capture time of {applied} screenshots"),
+            Err(error) => warn!("Failed to restore screenshot capture times: {error}"),
+        }
+    });
+
+    let command_builder = Builder::<AppRuntime>::new()
+        .commands(collect_commands![
+            steam_locate::get_games,
+            steam_locate::get_recent_steam_user,
+            import_jobs::import_screenshots,
+            import_jobs::cancel_import,
+            import_jobs::get_import_jobs,
+            import_jobs::get_import_job,
+            import_jobs::get_import_job_result,
+            import_jobs::get_unfinished_imports,
+            import_jobs::resume_import,
+            import_jobs::discard_import,
+            watch_folders::get_watch_rules,
+            watch_folders::add_watch_rule,
+            watch_folders::update_watch_rule,
+            watch_folders::remove_watch_rule,
+            watch_folders::get_watch_activity,
+            file_picker::pick_screenshot_files,
+            file_picker::pick_screenshot_folders,
+            file_selection::preview_import,
+            file_picker::pick_folder
+        ])
+        .error_handling(ErrorHandlingMode::Throw);
+
+    #[cfg(debug_assertions)]
+    command_builder
+        .export(Typescript::default(), TYPESCRIPT_BINDINGS_PATH)
+        .expect("failed to export TypeScript bindings");
+
+    let builder = tauri::Builder::<AppRuntime>::default();
+
+    builder
+        .plugin(tauri_plugin_opener::init())
+        .invoke_handler(command_builder.invoke_handler())
+        .setup(move |app| {
+            command_builder.mount_events(app);
+            app.manage(ImportJobs::new(app.handle().clone()));
+            app.manage(WatchService::new(app.handle().clone()));
+            Ok(())
+        })
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
@@ -0,0 +1,294 @@
+use crate::image_import::MAX_RESOLUTION;
+use image::error::LimitErrorKind;
+use image::{ImageDecoder, ImageError, ImageReader, Limits};
+use std::io::{BufRead, Seek};
+use std::sync::{Condvar, Mutex, PoisonError};
+
+pub const DEFAULT_MEMORY_BUDGET_MB: u32 = 4096;
+
+/// Largest width or height of a screenshot that's decoded at all. Anything larger is far beyond
+/// what Steam accepts and most likely a decompression bomb.
+const MAX_DECODED_SIDE: u32 = 65_535;
+/// Bytes per pixel of the copies made while processing a decoded screenshot: an 8-bit RGB copy,
+/// the 16-bit linear light copy that's resized and the resized result.
+const WORKING_BYTES_PER_PIXEL: u64 = 3 + 6 + 3;
+/// Bytes per pixel of screenshots decoded by the decoders behind cargo features, which decode to
+/// as much as 32-bit float RGBA.
+const FLOAT_BYTES_PER_PIXEL: u64 = 16;
+/// Memory assumed for screenshots whose header can't be read up front: the largest screenshot
+/// Steam accepts, decoded to 32-bit float RGBA.
+pub const UNKNOWN_SIZE: u64 =
+    MAX_RESOLUTION as u64 * (FLOAT_BYTES_PER_PIXEL + WORKING_BYTES_PER_PIXEL);
+
+/// Limits how many screenshots are decoded and processed at the same time, by how much memory
+/// they're estimated to need and by the number of workers.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryBudget {
+    bytes: u64,
+    max_workers: usize,
+}
+
+impl MemoryBudget {
+    /// A budget of `megabytes` MiB shared by at most `max_workers` screenshots, or one per CPU
+    /// core if it's 0.
+    #[must_use]
+    pub fn new(megabytes: u32, max_workers: u32) -> Self {
+        let max_workers = match usize::try_from(max_workers) {
+            Ok(0) | Err(_) => rayon::current_num_threads(),
+            Ok(max_workers) => max_workers,
+        };
+        Self {
+            bytes: u64::from(megabytes.max(1)) * 1024 * 1024,
+            max_workers,
+        }
+    }
+
+    /// The memory the budget allows, in bytes.
+    #[must_use]
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Whether a screenshot needing `size` bytes can be processed along with the `count`
+    /// screenshots of a batch, which need `reserved` bytes together. Screenshots that need more
+    /// than the whole budget are only processed on their own.
+    #[must_use]
+    pub fn fits(&self, count: usize, reserved: u64, size: u64) -> bool {
+        count == 0 || (count < self.max_workers && reserved.saturating_add(size) <= self.bytes)
+    }
+
+    /// Decoder limits that reject screenshots which are too large to decode within the budget,
+    /// before their pixels are allocated.
+    #[must_use]
+    pub fn limits(&self) -> Limits {
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(MAX_DECODED_SIDE);
+        limits.max_image_height = Some(MAX_DECODED_SIDE);
+        limits.max_alloc = Some(self.bytes);
+        limits
+    }
+
+    /// Rejects a screenshot of `width` by `height` pixels that's too large to decode within the
+    /// budget, for decoders that [`Self::limits`] can't be passed to.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of why `file_name` won't be decoded.
+    pub fn check_dimensions(&self, file_name: &str, width: u32, height: u32) -> Result<(), String> {
+        let mut limits = self.limits();
+        limits
+            .check_dimensions(width, height)
+            .and_then(|()| {
+                limits.reserve(u64::from(width) * u64::from(height) * FLOAT_BYTES_PER_PIXEL)
+            })
+            .map_err(|error| {
+                self.limit_error(file_name, &error)
+                    .unwrap_or_else(|| error.to_string())
+            })
+    }
+
+    /// Describes why the decoder refused to decode `file_name` because of [`Self::limits`], or
+    /// `None` for any other error.
+    #[must_use]
+    pub fn limit_error(&self, file_name: &str, error: &ImageError) -> Option<String> {
+        let ImageError::Limits(limit_error) = error else {
+            return None;
+        };
+        match limit_error.kind() {
+            LimitErrorKind::DimensionError => Some(format!(
+                "{file_name} is larger than {MAX_DECODED_SIDE}x{MAX_DECODED_SIDE} pixels, so it \
+                 wasn't decoded"
+            )),
+            LimitErrorKind::InsufficientMemory => Some(format!(
+                "{file_name} needs more memory to decode than the memory budget of {} MiB",
+                self.bytes / 1024 / 1024
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Shares a [`MemoryBudget`] between the screenshots that are processed at the same time. Each
+/// of them reserves the memory it needs until it's done, so the next one can start as soon as
+/// there's room for it.
+pub struct MemoryPool {
+    budget: MemoryBudget,
+    /// Number of screenshots being processed and the memory they need together
+    used: Mutex<(usize, u64)>,
+    released: Condvar,
+}
+
+impl MemoryPool {
+    #[must_use]
+    pub fn new(budget: MemoryBudget) -> Self {
+        Self {
+            budget,
+            used: Mutex::new((0, 0)),
+            released: Condvar::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn budget(&self) -> MemoryBudget {
+        self.budget
+    }
+
+    /// Waits until a screenshot needing `size` bytes fits along with the screenshots that are
+    /// being processed, and reserves the memory for it until the reservation is dropped.
+    pub fn reserve(&self, size: u64) -> Reservation<'_> {
+        // A worker that panicked still released its reservation, so the counts are intact
+        let mut used = self.used.lock().unwrap_or_else(PoisonError::into_inner);
+        while !self.budget.fits(used.0, used.1, size) {
+            used = self
+                .released
+                .wait(used)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        used.0 += 1;
+        used.1 = used.1.saturating_add(size);
+        Reservation { pool: self, size }
+    }
+}
+
+/// Memory reserved in a [`MemoryPool`] for a screenshot, which is released when it's dropped.
+pub struct Reservation<'a> {
+    pool: &'a MemoryPool,
+    size: u64,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let mut used = self
+            .pool
+            .used
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        used.0 -= 1;
+        used.1 = used.1.saturating_sub(self.size);
+        self.pool.released.notify_all();
+    }
+}
+
+/// Estimates how much memory decoding and processing a screenshot of `width` by `height` pixels
+/// takes with the decoders behind cargo features.
+#[must_use]
+pub fn float_size(width: u32, height: u32) -> u64 {
+    (u64::from(width) * u64::from(height))
+        .saturating_mul(FLOAT_BYTES_PER_PIXEL + WORKING_BYTES_PER_PIXEL)
+}
+
+/// Estimates how much memory decoding and processing an image takes from its header.
+#[must_use]
+pub fn estimated_size<R: BufRead + Seek>(mut reader: ImageReader<R>) -> u64 {
+    // Only the header is read, so the image can't be too large yet
+    reader.no_limits();
+    let Ok(decoder) = reader.into_decoder() else {
+        return UNKNOWN_SIZE;
+    };
+    let (width, height) = decoder.dimensions();
+    let pixels = u64::from(width) * u64::from(height);
+    decoder
+        .total_bytes()
+        .saturating_add(pixels.saturating_mul(WORKING_BYTES_PER_PIXEL))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, RgbImage};
+    use std::io::Cursor;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        RgbImage::new(width, height)
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    fn reader(bytes: &[u8]) -> ImageReader<Cursor<&[u8]>> {
+        ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()
+            .unwrap()
+    }
+
+    #[test]
+    fn batches_fit_in_the_budget() {
+        let budget = MemoryBudget::new(100, 4);
+        assert_eq!(budget.bytes(), 100 * MIB);
+        assert!(budget.fits(1, 60 * MIB, 40 * MIB));
+        assert!(!budget.fits(1, 60 * MIB, 41 * MIB));
+    }
+
+    #[test]
+    fn batches_are_limited_to_the_number_of_workers() {
+        let budget = MemoryBudget::new(100, 2);
+        assert!(budget.fits(1, MIB, MIB));
+        assert!(!budget.fits(2, MIB, MIB));
+    }
+
+    #[test]
+    fn screenshots_larger_than_the_budget_are_processed_on_their_own() {
+        let budget = MemoryBudget::new(100, 4);
+        assert!(budget.fits(0, 0, 500 * MIB));
+        assert!(!budget.fits(1, MIB, 500 * MIB));
+    }
+
+    #[test]
+    fn reservations_wait_until_memory_is_released() {
+        let pool = MemoryPool::new(MemoryBudget::new(100, 4));
+        let first = pool.reserve(60 * MIB);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let _second = pool.reserve(60 * MIB);
+                sender.send(()).unwrap();
+            });
+            // The second screenshot doesn't fit along with the first
+            let timeout = std::time::Duration::from_millis(100);
+            assert!(receiver.recv_timeout(timeout).is_err());
+            drop(first);
+            assert!(receiver.recv_timeout(timeout * 50).is_ok());
+        });
+        // Everything was released, so the whole budget is available again
+        drop(pool.reserve(100 * MIB));
+    }
+
+    #[test]
+    fn estimates_sizes_from_the_header() {
+        // The decoded 8-bit RGB image and the copies made while processing it
+        assert_eq!(estimated_size(reader(&png(100, 50))), 5000 * (3 + 12));
+        assert_eq!(estimated_size(reader(b"not an image")), UNKNOWN_SIZE);
+    }
+
+    #[test]
+    fn checks_dimensions_against_the_budget() {
+        let budget = MemoryBudget::new(64, 1);
+        assert!(budget.check_dimensions("small.jxr", 1920, 1080).is_ok());
+        assert_eq!(
+            budget
+                .check_dimensions("large.jxr", 4096, 4096)
+                .unwrap_err(),
+            "large.jxr needs more memory to decode than the memory budget of 64 MiB"
+        );
+        assert_eq!(
+            budget.check_dimensions("wide.jxr", 70_000, 1).unwrap_err(),
+            "wide.jxr is larger than 65535x65535 pixels, so it wasn't decoded"
+        );
+    }
+
+    #[test]
+    fn refuses_to_decode_screenshots_larger_than_the_budget() {
+        let budget = MemoryBudget::new(1, 1);
+        let png = png(1024, 1024);
+        let mut reader = reader(&png);
+        reader.limits(budget.limits());
+        let error = reader.decode().unwrap_err();
+        assert_eq!(
+            budget.limit_error("big.png", &error).unwrap(),
+            "big.png needs more memory to decode than the memory budget of 1 MiB"
+        );
+    }
+}
@@ -0,0 +1,460 @@
+use chrono::{DateTime, NaiveDateTime};
+use exif::experimental::Writer;
+use exif::{Field, In, Tag, Value};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+/// EXIF fields that are carried over: when the screenshot was taken, what took it and what it
+/// shows.
+const KEPT_TAGS: &[Tag] = &[
+    Tag::DateTime,
+    Tag::OffsetTime,
+    Tag::DateTimeOriginal,
+    Tag::OffsetTimeOriginal,
+    Tag::SubSecTimeOriginal,
+    Tag::DateTimeDigitized,
+    Tag::OffsetTimeDigitized,
+    Tag::Software,
+    Tag::ImageDescription,
+];
+
+/// XMP properties that hold the capture date, in order of preference.
+const XMP_DATE_PROPERTIES: &[&str] = &[
+    "exif:DateTimeOriginal",
+    "photoshop:DateCreated",
+    "xmp:CreateDate",
+];
+
+const EXIF_DATE_FORMAT: &str = "%Y:%m:%d %H:%M:%S";
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const XMP_HEADERS: [&[u8]; 2] = [
+    b"http://ns.adobe.com/xap/1.0/\0",
+    b"http://ns.adobe.com/xmp/extension/\0",
+];
+
+const JPEG_SOI: [u8; 2] = [0xFF, 0xD8];
+const JPEG_APP0: u8 = 0xE0;
+const JPEG_APP1: u8 = 0xE1;
+const JPEG_APP13: u8 = 0xED;
+const JPEG_COM: u8 = 0xFE;
+const JPEG_SOS: u8 = 0xDA;
+
+/// Which metadata of the original screenshots ends up in the imported JPEGs.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Deserialize,
+    Serialize,
+    specta::Type,
+    clap::ValueEnum,
+)]
+pub enum MetadataPolicy {
+    /// Keep the capture date, the software or game that took the screenshot and its description
+    #[default]
+    KeepSelected,
+    /// Remove all metadata, including locations and camera serial numbers
+    StripAll,
+}
+
+/// Builds EXIF data with only the fields in [`KEPT_TAGS`] of `exif`, filling in the ones it
+/// doesn't have from `xmp`. Returns `None` if there's nothing to keep.
+///
+/// # Errors
+///
+/// Returns an error if the kept fields could not be encoded.
+pub fn selected_exif(exif: Option<&[u8]>, xmp: Option<&[u8]>) -> Result<Option<Vec<u8>>, String> {
+    let mut fields: Vec<Field> = exif
+        .and_then(|exif| exif::Reader::new().read_raw(exif.to_vec()).ok())
+        .map(|exif| {
+            exif.fields()
+                .filter(|field| field.ifd_num == In::PRIMARY && KEPT_TAGS.contains(&field.tag))
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if let Some(xmp) = xmp.and_then(|xmp| std::str::from_utf8(xmp).ok()) {
+        let has = |tag: Tag| fields.iter().any(|field| field.tag == tag);
+        let mut from_xmp = Vec::new();
+        if !has(Tag::DateTimeOriginal)
+            && let Some((date_time, offset)) = XMP_DATE_PROPERTIES
+                .iter()
+                .find_map(|name| xmp_property(xmp, name))
+                .and_then(|date| exif_date_time(&date))
+        {
+            from_xmp.push(ascii_field(Tag::DateTimeOriginal, date_time));
+            if let Some(offset) = offset {
+                from_xmp.push(ascii_field(Tag::OffsetTimeOriginal, offset));
+            }
+        }
+        if !has(Tag::Software)
+            && let Some(tool) = xmp_property(xmp, "xmp:CreatorTool")
+        {
+            from_xmp.push(ascii_field(Tag::Software, tool));
+        }
+        if !has(Tag::ImageDescription)
+            && let Some(description) = xmp_property(xmp, "dc:description")
+        {
+            from_xmp.push(ascii_field(Tag::ImageDescription, description));
+        }
+        fields.extend(from_xmp);
+    }
+
+    if fields.is_empty() {
+        return Ok(None);
+    }
+    let mut writer = Writer::new();
+    for field in &fields {
+        writer.push_field(field);
+    }
+    let mut buffer = Cursor::new(Vec::new());
+    writer
+        .write(&mut buffer, false)
+        .map_err(|error| format!("Failed to write EXIF metadata: {error}"))?;
+    Ok(Some(buffer.into_inner()))
+}
+
+fn ascii_field(tag: Tag, value: String) -> Field {
+    Field {
+        tag,
+        ifd_num: In::PRIMARY,
+        value: Value::Ascii(vec![value.into_bytes()]),
+    }
+}
+
+/// Converts an XMP date to an EXIF date and time, with its UTC offset if it has one.
+fn exif_date_time(date: &str) -> Option<(String, Option<String>)> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(date) {
+        return Some((
+            time.format(EXIF_DATE_FORMAT).to_string(),
+            Some(time.format("%:z").to_string()),
+        ));
+    }
+
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+        .map(|time| (time.format(EXIF_DATE_FORMAT).to_string(), None))
+}
+
+/// Reads a simple XMP property, written either as an attribute or as an element. For arrays and
+/// language alternatives, like `dc:description`, the first item is returned.
+fn xmp_property(xmp: &str, name: &str) -> Option<String> {
+    let value = xmp_attribute(xmp, name).or_else(|| xmp_element(xmp, name))?;
+    let value = unescape_xml(value.trim());
+    (!value.is_empty()).then_some(value)
+}
+
+fn xmp_attribute<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    xmp.match_indices(name).find_map(|(start, _)| {
+        // Attributes are separated by whitespace, element names start with <
+        if !xmp[..start].ends_with(char::is_whitespace) {
+            return None;
+        }
+        let value = xmp[start + name.len()..]
+            .trim_start()
+            .strip_prefix('=')?
+            .trim_start();
+        let quote = value
+            .chars()
+            .next()
+            .filter(|&quote| quote == '"' || quote == '\'')?;
+        let value = &value[1..];
+        Some(&value[..value.find(quote)?])
+    })
+}
+
+fn xmp_element<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{name}");
+    let content_start = xmp.match_indices(&open).find_map(|(start, _)| {
+        let rest = &xmp[start + open.len()..];
+        // Skip elements whose name only starts with `name`, and empty elements
+        if !rest.starts_with(['>', ' ', '\t', '\r', '\n']) {
+            return None;
+        }
+        let end = rest.find('>')?;
+        (!rest[..end].ends_with('/')).then_some(start + open.len() + end + 1)
+    })?;
+    let content = &xmp[content_start..];
+    let content = &content[..content.find(&format!("</{name}>"))?];
+
+    match content.find("<rdf:li") {
+        Some(item_start) => {
+            let item = &content[item_start..];
+            let item = &item[item.find('>')? + 1..];
+            Some(&item[..item.find("</rdf:li>")?])
+        }
+        None => Some(content),
+    }
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Replaces the metadata of a JPEG with `exif` without re-encoding it. EXIF, XMP and IPTC
+/// segments and comments are removed, while the color profile and everything else is kept.
+///
+/// # Errors
+///
+/// Returns an error if the JPEG's segments are malformed, or `exif` is too large for a segment.
+pub fn replace_jpeg_metadata(jpeg: &[u8], exif: Option<&[u8]>) -> Result<Vec<u8>, String> {
+    let invalid = || "Invalid JPEG segment".to_string();
+    if !jpeg.starts_with(&JPEG_SOI) {
+        return Err("Not a JPEG file".to_string());
+    }
+
+    let mut output = Vec::with_capacity(jpeg.len());
+    output.extend_from_slice(&JPEG_SOI);
+    let mut exif = exif;
+    let mut position = JPEG_SOI.len();
+    loop {
+        let Some(&[0xFF, marker]) = jpeg.get(position..position + 2) else {
+            return Err(invalid());
+        };
+
+        // EXIF data goes right after the JFIF header, if there is one
+        if marker != JPEG_APP0
+            && let Some(exif) = exif.take()
+        {
+            write_exif_segment(&mut output, exif)?;
+        }
+        // The compressed image data follows the start of scan
+        if marker == JPEG_SOS {
+            output.extend_from_slice(&jpeg[position..]);
+            return Ok(output);
+        }
+
+        let length = jpeg
+            .get(position + 2..position + 4)
+            .map(|length| usize::from(u16::from_be_bytes([length[0], length[1]])))
+            .ok_or_else(invalid)?;
+        let segment = jpeg
+            .get(position..position + 2 + length)
+            .ok_or_else(invalid)?;
+        if !is_metadata_segment(marker, segment.get(4..).unwrap_or_default()) {
+            output.extend_from_slice(segment);
+        }
+        position += segment.len();
+    }
+}
+
+fn is_metadata_segment(marker: u8, data: &[u8]) -> bool {
+    match marker {
+        JPEG_APP1 => {
+            data.starts_with(EXIF_HEADER)
+                || XMP_HEADERS.iter().any(|header| data.starts_with(header))
+        }
+        JPEG_APP13 | JPEG_COM => true,
+        _ => false,
+    }
+}
+
+fn write_exif_segment(output: &mut Vec<u8>, exif: &[u8]) -> Result<(), String> {
+    let length = u16::try_from(2 + EXIF_HEADER.len() + exif.len())
+        .map_err(|_| "EXIF metadata is too large for a JPEG segment".to_string())?;
+    output.extend_from_slice(&[0xFF, JPEG_APP1]);
+    output.extend_from_slice(&length.to_be_bytes());
+    output.extend_from_slice(EXIF_HEADER);
+    output.extend_from_slice(exif);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JPEG_APP2: u8 = 0xE2;
+    const ICC_PROFILE: &[u8] = b"ICC_PROFILE\0\x01\x01profile";
+    const SCAN: &[u8] = &[0xFF, JPEG_SOS, 0, 2, 0x12, 0x34, 0xFF, 0xD9];
+
+    fn segment(marker: u8, data: &[u8]) -> Vec<u8> {
+        let length = u16::try_from(data.len() + 2).unwrap();
+        [&[0xFF, marker][..], &length.to_be_bytes(), data].concat()
+    }
+
+    fn write_exif(fields: &[(Tag, &str)]) -> Vec<u8> {
+        let fields: Vec<_> = fields
+            .iter()
+            .map(|&(tag, value)| ascii_field(tag, value.to_string()))
+            .collect();
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut buffer = Cursor::new(Vec::new());
+        writer.write(&mut buffer, false).unwrap();
+        buffer.into_inner()
+    }
+
+    fn exif_fields(jpeg: &[u8]) -> Option<Vec<(Tag, String)>> {
+        let exif = exif::Reader::new()
+            .read_from_container(&mut Cursor::new(jpeg))
+            .ok()?;
+        Some(
+            exif.fields()
+                .map(|field| (field.tag, field.display_value().to_string()))
+                .collect(),
+        )
+    }
+
+    /// A JPEG with a JFIF header, every kind of metadata and a color profile.
+    fn jpeg_with_metadata() -> Vec<u8> {
+        let original_exif = write_exif(&[(Tag::Make, "Camera"), (Tag::Software, "Game")]);
+        [
+            &JPEG_SOI[..],
+            &segment(JPEG_APP0, b"JFIF\0\x01\x02\0\0\x01\0\x01\0\0"),
+            &segment(JPEG_APP1, &[EXIF_HEADER, &original_exif].concat()),
+            &segment(JPEG_APP1, &[XMP_HEADERS[0], b"<x:xmpmeta/>"].concat()),
+            &segment(JPEG_APP2, ICC_PROFILE),
+            &segment(JPEG_APP13, b"Photoshop 3.0\0"),
+            &segment(JPEG_COM, b"comment"),
+            SCAN,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn replaces_metadata_and_keeps_the_color_profile() {
+        let exif = write_exif(&[(Tag::Software, "Imported")]);
+        let jpeg = replace_jpeg_metadata(&jpeg_with_metadata(), Some(&exif)).unwrap();
+
+        let expected = [
+            &JPEG_SOI[..],
+            &segment(JPEG_APP0, b"JFIF\0\x01\x02\0\0\x01\0\x01\0\0"),
+            &segment(JPEG_APP1, &[EXIF_HEADER, &exif].concat()),
+            &segment(JPEG_APP2, ICC_PROFILE),
+            SCAN,
+        ]
+        .concat();
+        assert_eq!(jpeg, expected);
+        assert_eq!(
+            exif_fields(&jpeg),
+            Some(vec![(Tag::Software, "\"Imported\"".to_string())])
+        );
+    }
+
+    #[test]
+    fn places_exif_first_without_a_jfif_header() {
+        let exif = write_exif(&[(Tag::Software, "Imported")]);
+        let original = [&JPEG_SOI[..], &segment(JPEG_APP2, ICC_PROFILE), SCAN].concat();
+        let jpeg = replace_jpeg_metadata(&original, Some(&exif)).unwrap();
+
+        let exif_segment = segment(JPEG_APP1, &[EXIF_HEADER, &exif].concat());
+        assert!(jpeg[JPEG_SOI.len()..].starts_with(&exif_segment));
+    }
+
+    #[test]
+    fn stripping_all_metadata_leaves_no_exif() {
+        // `MetadataPolicy::StripAll` replaces the metadata with nothing
+        let jpeg = replace_jpeg_metadata(&jpeg_with_metadata(), None).unwrap();
+        assert!(exif_fields(&jpeg_with_metadata()).is_some());
+        assert_eq!(exif_fields(&jpeg), None);
+        assert!(
+            !jpeg
+                .windows(EXIF_HEADER.len())
+                .any(|bytes| bytes == EXIF_HEADER)
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_segments() {
+        let mut jpeg = jpeg_with_metadata();
+        // Cut off in the middle of the EXIF segment
+        jpeg.truncate(30);
+        assert_eq!(
+            replace_jpeg_metadata(&jpeg, None),
+            Err("Invalid JPEG segment".to_string())
+        );
+        assert_eq!(
+            replace_jpeg_metadata(b"GIF89a", None),
+            Err("Not a JPEG file".to_string())
+        );
+    }
+
+    #[test]
+    fn keeps_selected_exif_fields() {
+        let original = write_exif(&[
+            (Tag::Make, "Camera"),
+            (Tag::DateTimeOriginal, "2024:05:06 07:08:09"),
+            (Tag::Software, "Game"),
+        ]);
+        let exif = selected_exif(Some(&original), None).unwrap().unwrap();
+        let jpeg = replace_jpeg_metadata(&[&JPEG_SOI[..], SCAN].concat(), Some(&exif)).unwrap();
+        assert_eq!(
+            exif_fields(&jpeg),
+            Some(vec![
+                (Tag::Software, "\"Game\"".to_string()),
+                (Tag::DateTimeOriginal, "2024-05-06 07:08:09".to_string()),
+            ])
+        );
+
+        assert_eq!(
+            selected_exif(Some(&write_exif(&[(Tag::Make, "Camera")])), None),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn fills_in_missing_exif_fields_from_xmp() {
+        let xmp = r#"<x:xmpmeta><rdf:RDF><rdf:Description
+            xmp:CreateDate="2024-05-06T07:08:09+02:00"
+            xmp:CreatorTool='Game &amp; Engine'>
+            <dc:description><rdf:Alt><rdf:li xml:lang="x-default">A view</rdf:li></rdf:Alt></dc:description>
+        </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        let original = write_exif(&[(Tag::Software, "Game")]);
+
+        let exif = selected_exif(Some(&original), Some(xmp.as_bytes()))
+            .unwrap()
+            .unwrap();
+        let jpeg = replace_jpeg_metadata(&[&JPEG_SOI[..], SCAN].concat(), Some(&exif)).unwrap();
+        assert_eq!(
+            exif_fields(&jpeg),
+            Some(vec![
+                (Tag::ImageDescription, "\"A view\"".to_string()),
+                (Tag::Software, "\"Game\"".to_string()),
+                (Tag::DateTimeOriginal, "2024-05-06 07:08:09".to_string()),
+                (Tag::OffsetTimeOriginal, "\"+02:00\"".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn reads_xmp_attributes_and_elements() {
+        let xmp = r#"<rdf:Description xmp:CreatorTool="Tool" xmp:CreateDateTime='ignored'
+            photoshop:DateCreated = '2024-05-06T07:08'>
+            <xmp:CreateDate>2024-05-06T07:08:09</xmp:CreateDate>
+            <dc:title/>
+            <dc:description>
+                <rdf:Alt><rdf:li xml:lang="x-default">First</rdf:li><rdf:li>Second</rdf:li></rdf:Alt>
+            </dc:description>
+        </rdf:Description>"#;
+
+        assert_eq!(xmp_attribute(xmp, "xmp:CreatorTool"), Some("Tool"));
+        assert_eq!(
+            xmp_attribute(xmp, "photoshop:DateCreated"),
+            Some("2024-05-06T07:08")
+        );
+        assert_eq!(xmp_attribute(xmp, "xmp:CreateDate"), None);
+        assert_eq!(
+            xmp_element(xmp, "xmp:CreateDate"),
+            Some("2024-05-06T07:08:09")
+        );
+        assert_eq!(xmp_element(xmp, "dc:title"), None);
+        assert_eq!(xmp_element(xmp, "dc:description"), Some("First"));
+        assert_eq!(
+            xmp_property(xmp, "xmp:CreateDate")
+                .as_deref()
+                .and_then(exif_date_time),
+            Some(("2024:05:06 07:08:09".to_string(), None))
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use crate::image_import::ResizeFilterType;
+use crate::tone_map::{linear_to_srgb, srgb_to_linear};
+use fast_image_resize::images::{Image, ImageRef};
+use fast_image_resize::{FilterType, PixelType, ResizeAlg, ResizeOptions, Resizer};
+use image::imageops::fast_blur;
+use image::{DynamicImage, GrayImage, RgbImage};
+use jpeg_decoder::PixelFormat;
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+use std::io::Read;
+use std::sync::LazyLock;
+
+/// Scratch buffers larger than this, like the linear copy of an 8K screenshot, are freed after
+/// use instead of being kept around for the next screenshot.
+const MAX_KEPT_SCRATCH: usize = 64 * 1024 * 1024;
+
+/// 8-bit sRGB values to 16-bit linear light, which keeps enough precision in the shadows.
+static TO_LINEAR: LazyLock<Vec<u16>> = LazyLock::new(|| {
+    (0..=u8::MAX)
+        .map(|value| to_u16(srgb_to_linear(f32::from(value) / f32::from(u8::MAX))))
+        .collect()
+});
+/// 16-bit linear light to 8-bit sRGB values.
+static TO_SRGB: LazyLock<Vec<u8>> = LazyLock::new(|| {
+    (0..=u16::MAX)
+        .into_par_iter()
+        .map(|value| to_u8(linear_to_srgb(f32::from(value) / f32::from(u16::MAX))))
+        .collect()
+});
+
+thread_local! {
+    // Each worker thread keeps its resizer and scratch buffer, so their memory is reused from one
+    // screenshot to the next
+    static RESIZER: RefCell<Resizer> = RefCell::new(Resizer::new());
+    static SCRATCH: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+impl From<ResizeFilterType> for ResizeAlg {
+    fn from(filter_type: ResizeFilterType) -> Self {
+        match filter_type {
+            ResizeFilterType::Nearest => Self::Nearest,
+            ResizeFilterType::Triangle => Self::Convolution(FilterType::Bilinear),
+            ResizeFilterType::CatmullRom => Self::Convolution(FilterType::CatmullRom),
+            ResizeFilterType::Gaussian => Self::Convolution(FilterType::Gaussian),
+            ResizeFilterType::Lanczos3 => Self::Convolution(FilterType::Lanczos3),
+        }
+    }
+}
+
+/// Resizes a screenshot to exactly `width` by `height` with a SIMD resampler. With
+/// `linear_light`, pixels are blended as light rather than as sRGB values, so fine bright details
+/// like text don't get darker when downscaling. Screenshots are 8-bit and opaque by the time
+/// they're resized, so the result is always 8-bit RGB.
+///
+/// # Errors
+///
+/// Returns an error if either size is 0.
+pub fn resize_image(
+    img: &DynamicImage,
+    width: u32,
+    height: u32,
+    filter: ResizeFilterType,
+    linear_light: bool,
+) -> Result<DynamicImage, String> {
+    let rgb = match img {
+        DynamicImage::ImageRgb8(rgb) => Cow::Borrowed(rgb),
+        _ => Cow::Owned(img.to_rgb8()),
+    };
+    let options = ResizeOptions::new().resize_alg(filter.into());
+
+    if !linear_light {
+        let src = ImageRef::new(rgb.width(), rgb.height(), rgb.as_raw(), PixelType::U8x3)
+            .map_err(resize_error)?;
+        let mut dst = Image::new(width, height, PixelType::U8x3);
+        RESIZER
+            .with_borrow_mut(|resizer| resizer.resize(&src, &mut dst, &options))
+            .map_err(resize_error)?;
+        return RgbImage::from_raw(width, height, dst.into_vec())
+            .map(DynamicImage::ImageRgb8)
+            .ok_or_else(|| "Resized image has the wrong size".to_string());
+    }
+
+    let mut dst = Image::new(width, height, PixelType::U16x3);
+    SCRATCH.with_borrow_mut(|scratch| {
+        scratch.clear();
+        scratch.resize(rgb.len() * 2, 0);
+        scratch
+            .par_chunks_exact_mut(2)
+            .zip(rgb.par_iter())
+            .for_each(|(linear, &sample)| {
+                linear.copy_from_slice(&TO_LINEAR[usize::from(sample)].to_ne_bytes());
+            });
+
+        let resized = ImageRef::new(rgb.width(), rgb.height(), scratch, PixelType::U16x3)
+            .map_err(resize_error)
+            .and_then(|src| {
+                RESIZER
+                    .with_borrow_mut(|resizer| resizer.resize(&src, &mut dst, &options))
+                    .map_err(resize_error)
+            });
+        if scratch.capacity() > MAX_KEPT_SCRATCH {
+            *scratch = Vec::new();
+        }
+        resized
+    })?;
+
+    let samples = dst
+        .buffer()
+        .par_chunks_exact(2)
+        .map(|linear| TO_SRGB[usize::from(u16::from_ne_bytes([linear[0], linear[1]]))])
+        .collect();
+    RgbImage::from_raw(width, height, samples)
+        .map(DynamicImage::ImageRgb8)
+        .ok_or_else(|| "Resized image has the wrong size".to_string())
+}
+
+/// Sharpens a resized screenshot with an unsharp mask, adding `amount` times the difference to
+/// a copy blurred with `radius`. An amount of 0 leaves it as it is.
+#[must_use]
+pub fn sharpen(img: DynamicImage, amount: f32, radius: f32) -> DynamicImage {
+    if amount <= 0.0 || radius <= 0.0 {
+        return img;
+    }
+
+    let mut sharpened = img.into_rgb8();
+    let blurred = fast_blur(&sharpened, radius);
+    sharpened
+        .par_iter_mut()
+        .zip(blurred.par_iter())
+        .for_each(|(sample, &blurred)| {
+            let difference = f32::from(*sample) - f32::from(blurred);
+            *sample = to_u8((f32::from(*sample) + amount * difference) / 255.0);
+        });
+    DynamicImage::ImageRgb8(sharpened)
+}
+
+/// Decodes a JPEG at the smallest scale the DCT allows that's still at least `min_width` wide,
+/// which skips most of the work of decoding it in full. Returns `None` for JPEGs that can't be
+/// decoded this way, like CMYK ones.
+///
+/// # Errors
+///
+/// Returns an error if the JPEG could not be decoded.
+pub fn decode_jpeg_preview(
+    reader: impl Read,
+    min_width: u32,
+) -> Result<Option<DynamicImage>, String> {
+    let mut decoder = jpeg_decoder::Decoder::new(reader);
+    decoder.read_info().map_err(|error| error.to_string())?;
+    let info = decoder.info().ok_or("JPEG has no frame header")?;
+    if info.width == 0 || info.height == 0 {
+        return Ok(None);
+    }
+
+    let requested_width = u16::try_from(min_width).unwrap_or(u16::MAX).min(info.width);
+    let requested_height =
+        u32::from(info.height) * u32::from(requested_width) / u32::from(info.width);
+    let requested_height = u16::try_from(requested_height.max(1)).unwrap_or(info.height);
+    let (width, height) = decoder
+        .scale(requested_width, requested_height)
+        .map_err(|error| error.to_string())?;
+    let pixels = decoder.decode().map_err(|error| error.to_string())?;
+
+    let (width, height) = (u32::from(width), u32::from(height));
+    let img = match info.pixel_format {
+        PixelFormat::RGB24 => {
+            RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8)
+        }
+        PixelFormat::L8 => GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        PixelFormat::L16 | PixelFormat::CMYK32 => return Ok(None),
+    };
+    img.map(Some)
+        .ok_or_else(|| "Decoded JPEG has the wrong size".to_string())
+}
+
+fn resize_error(error: impl fmt::Display) -> String {
+    format!("Failed to resize: {error}")
+}
+
+fn to_u16(value: f32) -> u16 {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let value = (value.clamp(0.0, 1.0) * f32::from(u16::MAX)).round() as u16;
+    value
+}
+
+fn to_u8(value: f32) -> u8 {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let value = (value.clamp(0.0, 1.0) * f32::from(u8::MAX)).round() as u8;
+    value
+}
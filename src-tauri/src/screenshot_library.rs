@@ -0,0 +1,406 @@
+use crate::steam::{initialize_steam, open_steam_section};
+use crate::steam_helper::SteamHelperLibrary;
+use crate::userdata::{UserdataLibrary, queue_creation_fixup};
+use crate::vr::VrScreenshotType;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{CString, c_void};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use steamworks::sys::SteamAPI_ISteamScreenshots_AddScreenshotToLibrary as add_screenshot_to_library;
+use steamworks::sys::SteamAPI_ISteamScreenshots_SetLocation as set_location;
+use steamworks::sys::SteamAPI_ISteamScreenshots_TagPublishedFile as tag_published_file;
+use steamworks::sys::SteamAPI_ISteamScreenshots_TagUser as tag_user;
+use steamworks::sys::SteamAPI_SteamScreenshots_v003 as get_steam_screenshots;
+use steamworks::sys::{
+    EResult, EVRScreenshotType, INVALID_SCREENSHOT_HANDLE, ScreenshotReady_t,
+    ScreenshotReady_t_k_iCallback,
+    SteamAPI_ISteamScreenshots_AddVRScreenshotToLibrary as add_vr_screenshot_to_library,
+};
+use steamworks::{Callback, CallbackHandle};
+
+const BACKEND_ENV_VAR: &str = "SSI_SCREENSHOT_LIBRARY";
+
+pub type ScreenshotHandle = steamworks::sys::ScreenshotHandle;
+
+/// A processed screenshot that is ready to be added to a library.
+pub struct Screenshot<'a> {
+    pub path: &'a Path,
+    pub thumbnail_path: &'a Path,
+    pub width: u32,
+    pub height: u32,
+    /// Unix timestamp the screenshot should be dated at, or `None` to date it at import time
+    pub capture_time: Option<i64>,
+    /// Type and path of the VR image, for VR screenshots whose `path` is their preview
+    pub vr: Option<(VrScreenshotType, &'a Path)>,
+    pub tags: &'a ScreenshotTags,
+}
+
+/// Caption and tags added to a screenshot once it's registered.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScreenshotTags {
+    /// Shown as the screenshot's caption
+    pub location: Option<String>,
+    /// 64-bit `SteamID`s of the users in the screenshot
+    pub tagged_users: Vec<u64>,
+    /// IDs of the Workshop items in the screenshot
+    pub workshop_items: Vec<u64>,
+}
+
+/// A screenshot library that processed screenshots can be registered with. Imports add their
+/// screenshots and run callbacks from a single thread.
+pub trait ScreenshotLibrary: Send + Sync {
+    /// Registers a screenshot and its thumbnail with its tags, returning the handle of the new
+    /// entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message describing why the screenshot could not be added.
+    fn add_screenshot(&self, screenshot: &Screenshot<'_>) -> Result<ScreenshotHandle, String>;
+
+    /// Gives the library a chance to process pending events after screenshots were added.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message if the library could not be accessed.
+    fn run_callbacks(&self) -> Result<(), String>;
+
+    /// Whether added screenshots are only imported once the library confirms it from a callback,
+    /// which [`Self::take_confirmations`] returns. Otherwise they're imported once they're added.
+    fn confirms_asynchronously(&self) -> bool {
+        false
+    }
+
+    /// Takes the results the library reported for added screenshots since the last call, when
+    /// callbacks were run.
+    fn take_confirmations(&self) -> Vec<(ScreenshotHandle, Result<(), String>)> {
+        Vec::new()
+    }
+
+    /// Shows the given section of the library to the user.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message if the section could not be opened.
+    fn open_section(&self, section: &str) -> Result<(), String>;
+}
+
+#[derive(Clone, Copy, Default, Debug, Deserialize, Serialize, specta::Type, clap::ValueEnum)]
+pub enum LibraryBackend {
+    /// Registers screenshots with Steam through Steamworks, from a helper process
+    #[default]
+    Steamworks,
+    /// Writes screenshots straight into the Steam userdata folder while Steam is closed
+    Userdata,
+    /// Records the screenshots that would have been registered without touching Steam
+    Recording,
+}
+
+impl LibraryBackend {
+    /// Reads the backend from the `SSI_SCREENSHOT_LIBRARY` environment variable, defaulting to
+    /// Steamworks.
+    #[must_use]
+    pub fn from_env() -> Self {
+        match env::var(BACKEND_ENV_VAR) {
+            Ok(value) if value.eq_ignore_ascii_case("userdata") => Self::Userdata,
+            Ok(value) if value.eq_ignore_ascii_case("recording") => Self::Recording,
+            _ => Self::default(),
+        }
+    }
+
+    /// Creates a library for `app_id` using this backend.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message if the backend could not be initialized, e.g. when Steam is not running.
+    pub fn connect(self, app_id: u32) -> Result<Box<dyn ScreenshotLibrary>, String> {
+        info!("Using {self:?} screenshot library for AppID {app_id}");
+        Ok(match self {
+            Self::Steamworks => Box::new(SteamHelperLibrary::spawn(app_id)?),
+            Self::Userdata => Box::new(UserdataLibrary::new(app_id)?),
+            Self::Recording => Box::new(RecordingLibrary::default()),
+        })
+    }
+}
+
+/// Registers screenshots through Steamworks in this process, which binds it to one `AppID`. The
+/// importer uses it from a [`SteamHelperLibrary`] helper process.
+pub struct SteamworksLibrary {
+    app_id: u32,
+    client: Mutex<steamworks::Client>,
+    /// Results of the `ScreenshotReady_t` callbacks that weren't taken yet
+    confirmations: Arc<Mutex<Vec<(ScreenshotHandle, EResult)>>>,
+    /// Tags of added screenshots, applied once Steam confirms their handle
+    pending_tags: Arc<Mutex<HashMap<ScreenshotHandle, ScreenshotTags>>>,
+    /// Keeps the callback registered for as long as the library exists
+    _screenshot_ready: CallbackHandle,
+}
+
+/// Sent by Steam once a screenshot was written to the library, or failed to be.
+struct ScreenshotReady(ScreenshotReady_t);
+
+unsafe impl Callback for ScreenshotReady {
+    const ID: i32 = ScreenshotReady_t_k_iCallback;
+
+    unsafe fn from_raw(raw: *mut c_void) -> Self {
+        Self(unsafe { *raw.cast::<ScreenshotReady_t>() })
+    }
+}
+
+impl SteamworksLibrary {
+    /// Initializes Steamworks for `app_id`, starting Steam if it isn't running.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message if Steam could not be started or initialized.
+    pub fn new(app_id: u32) -> Result<Self, String> {
+        let client = initialize_steam(app_id)?;
+        let confirmations = Arc::new(Mutex::new(Vec::new()));
+        let pending_tags = Arc::new(Mutex::new(HashMap::new()));
+        let received = Arc::clone(&confirmations);
+        let tags = Arc::clone(&pending_tags);
+        let screenshot_ready = client.register_callback(move |ready: ScreenshotReady| {
+            let (handle, result) = (ready.0.m_hLocal, ready.0.m_eResult);
+            let tags = tags.lock().ok().and_then(|mut tags| tags.remove(&handle));
+            if let Some(tags) = tags
+                && result == EResult::k_EResultOK
+            {
+                tag_screenshot(handle, &tags);
+            }
+            if let Ok(mut received) = received.lock() {
+                received.push((handle, result));
+            }
+        });
+
+        Ok(Self {
+            app_id,
+            client: Mutex::new(client),
+            confirmations,
+            pending_tags,
+            _screenshot_ready: screenshot_ready,
+        })
+    }
+}
+
+impl ScreenshotLibrary for SteamworksLibrary {
+    fn add_screenshot(&self, screenshot: &Screenshot<'_>) -> Result<ScreenshotHandle, String> {
+        let screenshot_path = CString::new(screenshot.path.to_string_lossy().as_bytes())
+            .map_err(|error| format!("Invalid screenshot path: {error}"))?;
+        let thumbnail_path = CString::new(screenshot.thumbnail_path.to_string_lossy().as_bytes())
+            .map_err(|error| format!("Invalid thumbnail path: {error}"))?;
+        let width = screenshot
+            .width
+            .try_into()
+            .map_err(|error| format!("Invalid screenshot width: {error}"))?;
+        let height = screenshot
+            .height
+            .try_into()
+            .map_err(|error| format!("Invalid screenshot height: {error}"))?;
+
+        let screenshot_handle = if let Some((kind, vr_path)) = screenshot.vr {
+            // Steam makes the thumbnail of VR screenshots itself
+            let vr_path = CString::new(vr_path.to_string_lossy().as_bytes())
+                .map_err(|error| format!("Invalid VR screenshot path: {error}"))?;
+            unsafe {
+                add_vr_screenshot_to_library(
+                    get_steam_screenshots(),
+                    vr_screenshot_type(kind),
+                    screenshot_path.as_ptr(),
+                    vr_path.as_ptr(),
+                )
+            }
+        } else {
+            unsafe {
+                add_screenshot_to_library(
+                    get_steam_screenshots(),
+                    screenshot_path.as_ptr(),
+                    thumbnail_path.as_ptr(),
+                    width,
+                    height,
+                )
+            }
+        };
+
+        if screenshot_handle == INVALID_SCREENSHOT_HANDLE {
+            return Err("Steam failed to import the screenshot into its library".to_string());
+        }
+        // Steam only accepts tags once the screenshot is written, which the callback reports
+        self.pending_tags
+            .lock()
+            .map_err(|error| format!("Failed to access screenshot tags: {error}"))?
+            .insert(screenshot_handle, screenshot.tags.clone());
+
+        // Steam dates screenshots at import time and can only be corrected once it's closed
+        if let Some(capture_time) = screenshot.capture_time
+            && let Err(error) = queue_creation_fixup(self.app_id, screenshot.path, capture_time)
+        {
+            warn!(
+                "Failed to queue capture time for {}: {error}",
+                screenshot.path.display()
+            );
+        }
+
+        Ok(screenshot_handle)
+    }
+
+    fn run_callbacks(&self) -> Result<(), String> {
+        self.client
+            .lock()
+            .map_err(|error| format!("Failed to access Steam client: {error}"))?
+            .run_callbacks();
+        Ok(())
+    }
+
+    fn confirms_asynchronously(&self) -> bool {
+        true
+    }
+
+    fn take_confirmations(&self) -> Vec<(ScreenshotHandle, Result<(), String>)> {
+        let Ok(mut confirmations) = self.confirmations.lock() else {
+            return Vec::new();
+        };
+        confirmations
+            .drain(..)
+            .map(|(handle, result)| {
+                let result = match result {
+                    EResult::k_EResultOK => Ok(()),
+                    EResult::k_EResultIOFailure => {
+                        Err("Steam failed to write the screenshot to its library".to_string())
+                    }
+                    result => Err(format!(
+                        "Steam failed to import the screenshot into its library ({result:?})"
+                    )),
+                };
+                (handle, result)
+            })
+            .collect()
+    }
+
+    fn open_section(&self, section: &str) -> Result<(), String> {
+        open_steam_section(section)
+    }
+}
+
+fn vr_screenshot_type(kind: VrScreenshotType) -> EVRScreenshotType {
+    match kind {
+        VrScreenshotType::Mono => EVRScreenshotType::k_EVRScreenshotType_Mono,
+        VrScreenshotType::Stereo => EVRScreenshotType::k_EVRScreenshotType_Stereo,
+        VrScreenshotType::MonoCubemap => EVRScreenshotType::k_EVRScreenshotType_MonoCubemap,
+        VrScreenshotType::MonoPanorama => EVRScreenshotType::k_EVRScreenshotType_MonoPanorama,
+        VrScreenshotType::StereoPanorama => EVRScreenshotType::k_EVRScreenshotType_StereoPanorama,
+    }
+}
+
+/// Adds the caption and tags to a screenshot Steam confirmed it added. The screenshot is imported
+/// either way, so tags Steam refuses are only logged.
+fn tag_screenshot(handle: ScreenshotHandle, tags: &ScreenshotTags) {
+    if let Some(location) = &tags.location {
+        match CString::new(location.as_str()) {
+            Ok(location) => {
+                if !unsafe { set_location(get_steam_screenshots(), handle, location.as_ptr()) } {
+                    warn!("Steam failed to set the caption of screenshot {handle}");
+                }
+            }
+            Err(error) => warn!("Invalid screenshot caption: {error}"),
+        }
+    }
+    for &steam_id in &tags.tagged_users {
+        if !unsafe { tag_user(get_steam_screenshots(), handle, steam_id) } {
+            warn!("Steam failed to tag user {steam_id} in screenshot {handle}");
+        }
+    }
+    for &item_id in &tags.workshop_items {
+        if !unsafe { tag_published_file(get_steam_screenshots(), handle, item_id) } {
+            warn!("Steam failed to tag Workshop item {item_id} in screenshot {handle}");
+        }
+    }
+}
+
+#[derive(Clone, Serialize, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedScreenshot {
+    handle: ScreenshotHandle,
+    screenshot_path: PathBuf,
+    thumbnail_path: PathBuf,
+    width: u32,
+    height: u32,
+    capture_time: Option<i64>,
+    vr_type: Option<VrScreenshotType>,
+    vr_path: Option<PathBuf>,
+    location: Option<String>,
+    /// `SteamID`s of the tagged users, as strings because they don't fit in a JavaScript number
+    tagged_users: Vec<String>,
+    workshop_items: Vec<String>,
+}
+
+/// An in-memory library that records every screenshot it is given instead of importing it.
+#[derive(Default)]
+pub struct RecordingLibrary {
+    screenshots: Mutex<Vec<RecordedScreenshot>>,
+    last_handle: AtomicU32,
+}
+
+impl RecordingLibrary {
+    /// Returns the screenshots recorded so far, in the order they were added.
+    #[must_use]
+    pub fn screenshots(&self) -> Vec<RecordedScreenshot> {
+        self.screenshots
+            .lock()
+            .map(|screenshots| screenshots.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl ScreenshotLibrary for RecordingLibrary {
+    fn add_screenshot(&self, screenshot: &Screenshot<'_>) -> Result<ScreenshotHandle, String> {
+        let handle = self.last_handle.fetch_add(1, Ordering::SeqCst) + 1;
+        info!(
+            "Recording screenshot {} ({}x{}) as handle {handle}",
+            screenshot.path.display(),
+            screenshot.width,
+            screenshot.height
+        );
+
+        self.screenshots
+            .lock()
+            .map_err(|error| format!("Failed to access recorded screenshots: {error}"))?
+            .push(RecordedScreenshot {
+                handle,
+                screenshot_path: screenshot.path.to_path_buf(),
+                thumbnail_path: screenshot.thumbnail_path.to_path_buf(),
+                width: screenshot.width,
+                height: screenshot.height,
+                capture_time: screenshot.capture_time,
+                vr_type: screenshot.vr.map(|(kind, _)| kind),
+                vr_path: screenshot.vr.map(|(_, vr_path)| vr_path.to_path_buf()),
+                location: screenshot.tags.location.clone(),
+                tagged_users: screenshot
+                    .tags
+                    .tagged_users
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect(),
+                workshop_items: screenshot
+                    .tags
+                    .workshop_items
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect(),
+            });
+
+        Ok(handle)
+    }
+
+    fn run_callbacks(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn open_section(&self, section: &str) -> Result<(), String> {
+        info!("Not opening Steam section {section} for recording library");
+        Ok(())
+    }
+}
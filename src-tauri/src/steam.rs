@@ -1,3 +1,5 @@
+#[cfg(not(target_os = "windows"))]
+use crate::vdf;
 use log::info;
 use std::process::Command;
 use std::thread;
@@ -28,8 +30,50 @@ pub fn open_steam_section(section: &str) -> Result<(), String> {
     Ok(())
 }
 
+pub fn is_running() -> bool {
+    unsafe { is_steam_running() }
+}
+
+/// Returns the `AppID` of the game that's currently running through Steam, if any.
+pub fn running_app_id() -> Option<u32> {
+    read_running_app_id()
+        .inspect_err(|error| info!("Couldn't find the running game: {error}"))
+        .ok()
+        .filter(|&app_id| app_id != 0)
+}
+
+#[cfg(target_os = "windows")]
+fn read_running_app_id() -> Result<u32, String> {
+    use winreg::RegKey;
+    use winreg::enums::HKEY_CURRENT_USER;
+
+    RegKey::predef(HKEY_CURRENT_USER)
+        .open_subkey("Software\\Valve\\Steam")
+        .and_then(|key| key.get_value::<u32, _>("RunningAppID"))
+        .map_err(|error| format!("Failed to read the Steam registry key: {error}"))
+}
+
+/// Steam keeps a copy of the Windows registry keys it uses in `~/.steam/registry.vdf`.
+#[cfg(not(target_os = "windows"))]
+fn read_running_app_id() -> Result<u32, String> {
+    let home = directories::BaseDirs::new().ok_or("Failed to find the home directory")?;
+    let path = home.home_dir().join(".steam").join("registry.vdf");
+    let registry = vdf::load(&path)?;
+
+    let steam = ["Registry", "HKCU", "Software", "Valve", "Steam"]
+        .iter()
+        .try_fold(&registry, |object, key| object.get_object(key))
+        .ok_or_else(|| format!("{} has no Steam registry key", path.display()))?;
+
+    steam
+        .get_str("RunningAppID")
+        .ok_or_else(|| format!("{} has no RunningAppID", path.display()))?
+        .parse()
+        .map_err(|error| format!("Invalid RunningAppID: {error}"))
+}
+
 pub fn initialize_steam(app_id: u32) -> Result<Client, String> {
-    if unsafe { is_steam_running() } {
+    if is_running() {
         Client::init_app(app_id).map_err(|_| "Failed to initialize steamworks!\nMake sure steam is open and you own the game you're attempting to import for.".to_string())
     } else {
         open_steam_section("main")?;
@@ -0,0 +1,360 @@
+use crate::screenshot_library::{
+    Screenshot, ScreenshotHandle, ScreenshotLibrary, ScreenshotTags, SteamworksLibrary,
+};
+use crate::steam::open_steam_section;
+use crate::vr::VrScreenshotType;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use simple_logger::SimpleLogger;
+use std::collections::HashSet;
+use std::env;
+use std::io::{BufRead, BufReader, Write, stdin, stdout};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, ChildStdout, Command, ExitCode, Stdio};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Argument that starts the importer as a Steam helper for the `AppID` that follows it.
+const HELPER_ARG: &str = "--steam-helper";
+/// How long the helper gets to shut Steamworks down once it's told to exit, before it's killed.
+const EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A request the importer sends to its Steam helper, one JSON object per line on stdin.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum Request {
+    #[serde(rename_all = "camelCase")]
+    Add {
+        path: PathBuf,
+        thumbnail_path: PathBuf,
+        width: u32,
+        height: u32,
+        capture_time: Option<i64>,
+        vr: Option<(VrScreenshotType, PathBuf)>,
+        tags: ScreenshotTags,
+    },
+    RunCallbacks,
+}
+
+/// A reply of the Steam helper, one JSON object per line on stdout. Any other lines it prints,
+/// like its log and Steam's own output, are passed on to the importer's log.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum Response {
+    /// Sent once when the helper starts, with the error if Steamworks couldn't be initialized
+    Started(Result<(), String>),
+    Added(Result<ScreenshotHandle, String>),
+    Confirmations(Vec<(ScreenshotHandle, Result<(), String>)>),
+}
+
+/// Returns the `AppID` to run the Steam helper for if the importer was started as one.
+#[must_use]
+pub fn requested_app_id() -> Option<u32> {
+    let mut args = env::args_os().skip(1);
+    if args.next()? != HELPER_ARG {
+        return None;
+    }
+    args.next()?.to_str()?.parse().ok()
+}
+
+/// Runs the Steam helper for `app_id`, which initializes Steamworks and registers the screenshots
+/// the importer sends it until its stdin is closed.
+#[must_use]
+pub fn run(app_id: u32) -> ExitCode {
+    // The importer may have its own logger already, but never in the same process
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Info)
+        .init();
+
+    let library = match SteamworksLibrary::new(app_id) {
+        Ok(library) => library,
+        Err(error) => {
+            let _ = respond(&Response::Started(Err(error)));
+            return ExitCode::FAILURE;
+        }
+    };
+    if respond(&Response::Started(Ok(()))).is_err() {
+        return ExitCode::FAILURE;
+    }
+
+    for line in stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let request = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(error) => {
+                error!("Invalid request from the importer: {error}");
+                return ExitCode::FAILURE;
+            }
+        };
+
+        let response = match request {
+            Request::Add {
+                path,
+                thumbnail_path,
+                width,
+                height,
+                capture_time,
+                vr,
+                tags,
+            } => Response::Added(
+                library.add_screenshot(&Screenshot {
+                    path: &path,
+                    thumbnail_path: &thumbnail_path,
+                    width,
+                    height,
+                    capture_time,
+                    vr: vr
+                        .as_ref()
+                        .map(|(kind, vr_path)| (*kind, vr_path.as_path())),
+                    tags: &tags,
+                }),
+            ),
+            Request::RunCallbacks => {
+                if let Err(error) = library.run_callbacks() {
+                    warn!("{error}");
+                }
+                Response::Confirmations(library.take_confirmations())
+            }
+        };
+        if respond(&response).is_err() {
+            return ExitCode::FAILURE;
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn respond(response: &Response) -> Result<(), String> {
+    // Holding the lock keeps log lines from ending up in the middle of the response
+    let mut stdout = stdout().lock();
+    serde_json::to_writer(&mut stdout, response)
+        .map_err(|error| error.to_string())
+        .and_then(|()| writeln!(stdout).map_err(|error| error.to_string()))
+        .and_then(|()| stdout.flush().map_err(|error| error.to_string()))
+        .inspect_err(|error| error!("Failed to reply to the importer: {error}"))
+}
+
+/// Registers screenshots with Steam through a helper process that's started for one `AppID`.
+/// Steamworks binds a process to the `AppID` it's initialized for, and the helper also keeps
+/// crashes in the Steam API from taking the importer down with them.
+pub struct SteamHelperLibrary {
+    helper: Mutex<Helper>,
+}
+
+struct Helper {
+    process: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    /// Handles of added screenshots the helper hasn't confirmed yet
+    unconfirmed: HashSet<ScreenshotHandle>,
+    confirmations: Vec<(ScreenshotHandle, Result<(), String>)>,
+    /// Why the helper can't be used anymore, once it exited
+    exit_error: Option<String>,
+}
+
+impl SteamHelperLibrary {
+    /// Starts a Steam helper for `app_id` and waits until it has initialized Steamworks, which
+    /// starts Steam if it isn't running.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message if the helper could not be started or failed to initialize Steamworks.
+    pub fn spawn(app_id: u32) -> Result<Self, String> {
+        let executable = env::current_exe()
+            .map_err(|error| format!("Failed to find the importer executable: {error}"))?;
+        let mut process = Command::new(executable)
+            .arg(HELPER_ARG)
+            .arg(app_id.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|error| format!("Failed to start the Steam helper: {error}"))?;
+        info!("Started Steam helper {} for AppID {app_id}", process.id());
+
+        let mut helper = Helper {
+            stdin: process.stdin.take(),
+            stdout: BufReader::new(process.stdout.take().ok_or("Steam helper has no stdout")?),
+            process,
+            unconfirmed: HashSet::new(),
+            confirmations: Vec::new(),
+            exit_error: None,
+        };
+        match helper.receive()? {
+            Response::Started(result) => result?,
+            _ => return Err("Unexpected reply from the Steam helper".to_string()),
+        }
+
+        Ok(Self {
+            helper: Mutex::new(helper),
+        })
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Helper>, String> {
+        self.helper
+            .lock()
+            .map_err(|error| format!("Failed to access the Steam helper: {error}"))
+    }
+}
+
+impl Helper {
+    fn request(&mut self, request: &Request) -> Result<Response, String> {
+        if let Some(error) = &self.exit_error {
+            return Err(error.clone());
+        }
+        let stdin = self.stdin.as_mut().ok_or("Steam helper has no stdin")?;
+        let mut line = serde_json::to_string(request)
+            .map_err(|error| format!("Failed to encode the Steam helper request: {error}"))?;
+        line.push('\n');
+        if let Err(error) = stdin
+            .write_all(line.as_bytes())
+            .and_then(|()| stdin.flush())
+        {
+            warn!("Failed to send a request to the Steam helper: {error}");
+            return Err(self.exited());
+        }
+        self.receive()
+    }
+
+    /// Reads the next reply, passing the other lines the helper prints on to the log.
+    fn receive(&mut self) -> Result<Response, String> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match self.stdout.read_line(&mut line) {
+                Ok(0) | Err(_) => return Err(self.exited()),
+                Ok(_) => {}
+            }
+            match serde_json::from_str(&line) {
+                Ok(response) => return Ok(response),
+                Err(_) => info!("Steam helper: {}", line.trim_end()),
+            }
+        }
+    }
+
+    /// Records that the helper exited, failing the screenshots it hasn't confirmed.
+    fn exited(&mut self) -> String {
+        let error = match self.process.wait() {
+            Ok(status) => format!("The Steam helper exited unexpectedly ({status})"),
+            Err(error) => format!("The Steam helper stopped responding: {error}"),
+        };
+        self.confirmations.extend(
+            self.unconfirmed
+                .drain()
+                .map(|handle| (handle, Err(error.clone()))),
+        );
+        self.exit_error = Some(error.clone());
+        error
+    }
+}
+
+impl ScreenshotLibrary for SteamHelperLibrary {
+    fn add_screenshot(&self, screenshot: &Screenshot<'_>) -> Result<ScreenshotHandle, String> {
+        let mut helper = self.lock()?;
+        let request = Request::Add {
+            path: screenshot.path.to_path_buf(),
+            thumbnail_path: screenshot.thumbnail_path.to_path_buf(),
+            width: screenshot.width,
+            height: screenshot.height,
+            capture_time: screenshot.capture_time,
+            vr: screenshot
+                .vr
+                .map(|(kind, vr_path)| (kind, vr_path.to_path_buf())),
+            tags: screenshot.tags.clone(),
+        };
+        match helper.request(&request)? {
+            Response::Added(result) => {
+                let handle = result?;
+                helper.unconfirmed.insert(handle);
+                Ok(handle)
+            }
+            _ => Err("Unexpected reply from the Steam helper".to_string()),
+        }
+    }
+
+    fn run_callbacks(&self) -> Result<(), String> {
+        let mut helper = self.lock()?;
+        match helper.request(&Request::RunCallbacks)? {
+            Response::Confirmations(confirmations) => {
+                for (handle, _) in &confirmations {
+                    helper.unconfirmed.remove(handle);
+                }
+                helper.confirmations.extend(confirmations);
+                Ok(())
+            }
+            _ => Err("Unexpected reply from the Steam helper".to_string()),
+        }
+    }
+
+    fn confirms_asynchronously(&self) -> bool {
+        true
+    }
+
+    fn take_confirmations(&self) -> Vec<(ScreenshotHandle, Result<(), String>)> {
+        self.lock()
+            .map(|mut helper| helper.confirmations.drain(..).collect())
+            .unwrap_or_default()
+    }
+
+    fn open_section(&self, section: &str) -> Result<(), String> {
+        open_steam_section(section)
+    }
+}
+
+impl Drop for Helper {
+    fn drop(&mut self) {
+        // Closing stdin tells the helper to shut Steamworks down and exit
+        drop(self.stdin.take());
+        if self.exit_error.is_none() {
+            wait_or_kill(&mut self.process, EXIT_TIMEOUT);
+        }
+    }
+}
+
+/// Waits up to `timeout` for a helper to exit, and kills it if it doesn't, so a helper that's
+/// stuck in Steamworks can't hang the import.
+fn wait_or_kill(process: &mut Child, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match process.try_wait() {
+            Ok(Some(_)) => return,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+            Ok(None) => break,
+            Err(error) => {
+                warn!("Failed to wait for the Steam helper to exit: {error}");
+                return;
+            }
+        }
+    }
+
+    warn!(
+        "The Steam helper didn't exit within {} seconds, killing it",
+        timeout.as_secs()
+    );
+    if let Err(error) = process.kill().and_then(|()| process.wait().map(|_| ())) {
+        warn!("Failed to kill the Steam helper: {error}");
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn helpers_that_exit_are_waited_for() {
+        let mut process = Command::new("true").spawn().unwrap();
+        wait_or_kill(&mut process, Duration::from_secs(10));
+        assert!(process.try_wait().unwrap().is_some());
+    }
+
+    #[test]
+    fn stuck_helpers_are_killed() {
+        let mut process = Command::new("sleep").arg("60").spawn().unwrap();
+        let start = Instant::now();
+        wait_or_kill(&mut process, Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(process.try_wait().unwrap().is_some());
+    }
+}
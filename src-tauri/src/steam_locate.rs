@@ -1,10 +1,10 @@
 use crate::image_fetch;
+use crate::vdf::{self, Value};
 use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD};
 use log::info;
 use serde::Serialize;
 use std::fs::read;
 use std::path::{Path, PathBuf};
-use steamy_vdf as vdf;
 use walkdir::WalkDir;
 
 const LIB_CACHE_PATH: &str = "appcache/librarycache/";
@@ -107,6 +107,58 @@ fn get_local_games() -> Result<Vec<LocalGame>, String> {
     Ok(games)
 }
 
+/// Finds the installed game called `name`, ignoring case, spaces and punctuation, since capture
+/// tools often sanitize game names when they use them as folder names.
+pub fn find_app_id_by_name(name: &str) -> Option<u32> {
+    let normalize = |name: &str| -> String {
+        name.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    let name = normalize(name);
+    if name.is_empty() {
+        return None;
+    }
+
+    let steam_dir = steamlocate::locate().ok()?;
+    let libraries = steam_dir.libraries().ok()?;
+    libraries
+        .filter_map(Result::ok)
+        .flat_map(|library| library.apps().filter_map(Result::ok).collect::<Vec<_>>())
+        .find(|app| {
+            app.name
+                .as_deref()
+                .is_some_and(|app_name| normalize(app_name) == name)
+        })
+        .map(|app| app.app_id)
+}
+
+/// Returns the 32-bit account ID of the user that most recently logged in to Steam.
+pub fn most_recent_account_id() -> Result<u32, String> {
+    let steam_dir = steamlocate::locate().map_err(|_| "Failed to locate Steam installation")?;
+    let vdf_path: PathBuf = steam_dir.path().join("config").join("loginusers.vdf");
+
+    let loginusers = vdf::load(&vdf_path)?;
+    let users = loginusers
+        .get_object("users")
+        .ok_or("Failed to get local Steam users")?;
+
+    let (steam_id, _) = users
+        .iter()
+        .find(|(_, user)| is_set(user, "MostRecent"))
+        .ok_or("No Steam user has logged in on this computer")?;
+
+    let steam_id: u64 = steam_id
+        .parse()
+        .map_err(|error| format!("Invalid SteamID {steam_id}: {error}"))?;
+
+    // The account ID is stored in the lower 32 bits of a SteamID64
+    #[allow(clippy::cast_possible_truncation)]
+    let account_id = steam_id as u32;
+    Ok(account_id)
+}
+
 #[tauri::command]
 #[specta::specta]
 pub fn get_recent_steam_user() -> Result<String, String> {
@@ -114,29 +166,30 @@ pub fn get_recent_steam_user() -> Result<String, String> {
     let steam_path = steam_dir.path();
     let vdf_path: PathBuf = steam_path.join("config").join("loginusers.vdf");
 
-    let loginusers = vdf::load(&vdf_path).map_err(|error| error.to_string())?;
+    let loginusers = vdf::load(&vdf_path)?;
     let users = loginusers
-        .lookup("users")
-        .ok_or("Failed to get local Steam users")?
-        .as_table()
-        .ok_or("Failed to convert local Steam users to table")?;
-
-    for user in users.values() {
-        if !user
-            .lookup("AutoLogin")
-            .is_some_and(|entry| entry.to::<bool>().unwrap_or(false))
-        {
+        .get_object("users")
+        .ok_or("Failed to get local Steam users")?;
+
+    for (_, user) in users.iter() {
+        if !is_set(user, "AutoLogin") {
             continue;
         }
 
+        let Value::Object(user) = user else {
+            continue;
+        };
         let steam_user = user
-            .lookup("PersonaName")
-            .ok_or("Failed to get Steam username")?
-            .as_str()
-            .ok_or("Failed to convert Steam username to string")?;
+            .get_str("PersonaName")
+            .ok_or("Failed to get Steam username")?;
 
         return Ok(steam_user.to_owned());
     }
 
     Ok(String::new())
 }
+
+/// Whether the flag `key` of a user in `loginusers.vdf` is set to 1.
+fn is_set(user: &Value, key: &str) -> bool {
+    matches!(user, Value::Object(user) if user.get_str(key) == Some("1"))
+}
@@ -0,0 +1,314 @@
+use image::DynamicImage;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_WHITE_POINT: f32 = 4.0;
+
+/// Brightness of SDR white in HDR screenshots, in nits, as recommended by ITU-R BT.2408.
+pub(crate) const REFERENCE_WHITE: f32 = 203.0;
+const PQ_MAX_LUMINANCE: f32 = 10_000.0;
+/// Peak brightness of the display HLG screenshots are shown on, in nits, and the system gamma
+/// that goes with it.
+const HLG_DISPLAY_LUMINANCE: f32 = 1000.0;
+const HLG_SYSTEM_GAMMA: f32 = 1.2;
+
+/// How HDR screenshots are mapped to the range of a JPEG.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Deserialize,
+    Serialize,
+    specta::Type,
+    clap::ValueEnum,
+)]
+pub enum ToneMapOperator {
+    /// Cut off everything brighter than SDR white
+    Clip,
+    /// Extended Reinhard, which compresses highlights evenly up to the white point
+    Reinhard,
+    /// Approximation of the ACES filmic curve, with more contrast than Reinhard
+    #[default]
+    AcesFilmic,
+}
+
+/// How the values of 16-bit integer screenshots are encoded, for those whose color profile doesn't
+/// say. Float screenshots, e.g. from EXR files, are always linear.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Deserialize,
+    Serialize,
+    specta::Type,
+    clap::ValueEnum,
+)]
+pub enum HdrTransfer {
+    /// Regular SDR screenshots that only have to be reduced to 8 bits
+    #[default]
+    Srgb,
+    /// Linear light, where 1.0 is SDR white
+    Linear,
+    /// SMPTE ST 2084 (PQ), used by HDR10 captures
+    Pq,
+    /// ARIB STD-B67 (HLG), used by HDR broadcasts and some captures
+    Hlg,
+}
+
+/// The primaries of the RGB values of an HDR screenshot, which are converted to the BT.709
+/// primaries of sRGB along with tone mapping.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Primaries {
+    /// The primaries of sRGB
+    #[default]
+    Bt709,
+    /// The wide gamut of HDR10 and HLG
+    Bt2020,
+    DisplayP3,
+}
+
+impl Primaries {
+    /// Converts linear RGB with these primaries to BT.709. Colors outside of it become negative.
+    fn to_bt709(self, [red, green, blue]: [f32; 3]) -> [f32; 3] {
+        let matrix = match self {
+            Self::Bt709 => return [red, green, blue],
+            Self::Bt2020 => [
+                [1.660_491, -0.587_641, -0.072_85],
+                [-0.124_55, 1.132_9, -0.008_349],
+                [-0.018_151, -0.100_579, 1.118_73],
+            ],
+            Self::DisplayP3 => [
+                [1.224_94, -0.224_94, 0.0],
+                [-0.042_057, 1.042_057, 0.0],
+                [-0.019_638, -0.078_636, 1.098_274],
+            ],
+        };
+        matrix.map(|row| row[0] * red + row[1] * green + row[2] * blue)
+    }
+
+    /// The luminance of linear RGB with these primaries.
+    fn luminance(self, [red, green, blue]: [f32; 3]) -> f32 {
+        let [red_weight, green_weight, blue_weight] = match self {
+            Self::Bt709 => [0.2126, 0.7152, 0.0722],
+            Self::Bt2020 => [0.2627, 0.6780, 0.0593],
+            Self::DisplayP3 => [0.2290, 0.6917, 0.0793],
+        };
+        red_weight * red + green_weight * green + blue_weight * blue
+    }
+}
+
+/// Converts float and 16-bit HDR screenshots to 8-bit sRGB, so they can be encoded as JPEG
+/// without clipping. `exposure` is in stops and `white_point` is the brightness, relative to SDR
+/// white, that's mapped to white. Colors are converted from `primaries` before they're mapped.
+/// 8-bit screenshots are returned unchanged.
+#[must_use]
+pub fn to_sdr(
+    img: DynamicImage,
+    operator: ToneMapOperator,
+    exposure: f32,
+    white_point: f32,
+    transfer: HdrTransfer,
+    primaries: Primaries,
+) -> DynamicImage {
+    let transfer = match img {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => HdrTransfer::Linear,
+        DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => transfer,
+        _ => return img,
+    };
+    let has_alpha = img.color().has_alpha();
+
+    let mut pixels = match transfer {
+        HdrTransfer::Srgb => return to_8_bit(&img, has_alpha),
+        HdrTransfer::Linear | HdrTransfer::Pq | HdrTransfer::Hlg => img.into_rgba32f(),
+    };
+
+    let scale = exposure.exp2();
+    let white_point = white_point.max(1.0);
+    pixels.par_chunks_mut(4).for_each(|pixel| {
+        let rgb = [pixel[0], pixel[1], pixel[2]];
+        let linear = match transfer {
+            HdrTransfer::Pq => rgb.map(pq_to_linear),
+            HdrTransfer::Hlg => hlg_to_linear(rgb, primaries),
+            HdrTransfer::Srgb | HdrTransfer::Linear => rgb,
+        };
+        for (channel, linear) in pixel[..3].iter_mut().zip(primaries.to_bt709(linear)) {
+            *channel = linear_to_srgb(map(linear.max(0.0) * scale, operator, white_point));
+        }
+    });
+
+    to_8_bit(&DynamicImage::ImageRgba32F(pixels), has_alpha)
+}
+
+fn to_8_bit(img: &DynamicImage, has_alpha: bool) -> DynamicImage {
+    if has_alpha {
+        DynamicImage::ImageRgba8(img.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(img.to_rgb8())
+    }
+}
+
+/// Maps a linear value, where 1.0 is SDR white, to the 0-1 range.
+fn map(value: f32, operator: ToneMapOperator, white_point: f32) -> f32 {
+    let mapped = match operator {
+        ToneMapOperator::Clip => value,
+        ToneMapOperator::Reinhard => {
+            value * (1.0 + value / (white_point * white_point)) / (1.0 + value)
+        }
+        ToneMapOperator::AcesFilmic => aces_filmic(value) / aces_filmic(white_point),
+    };
+    mapped.clamp(0.0, 1.0)
+}
+
+/// Krzysztof Narkowicz's fit of the ACES filmic tone curve.
+fn aces_filmic(value: f32) -> f32 {
+    (value * (2.51 * value + 0.03)) / (value * (2.43 * value + 0.59) + 0.14)
+}
+
+/// Decodes a PQ value to linear light, where 1.0 is SDR white.
+fn pq_to_linear(value: f32) -> f32 {
+    const M1: f32 = 0.159_301_76;
+    const M2: f32 = 78.843_75;
+    const C1: f32 = 0.835_937_5;
+    const C2: f32 = 18.851_563;
+    const C3: f32 = 18.6875;
+
+    let power = value.clamp(0.0, 1.0).powf(1.0 / M2);
+    let luminance = ((power - C1).max(0.0) / (C2 - C3 * power)).powf(1.0 / M1);
+    luminance * PQ_MAX_LUMINANCE / REFERENCE_WHITE
+}
+
+/// Decodes HLG values to the linear light they're shown as on a 1000 nit display, where 1.0 is
+/// SDR white. The brightness of HLG depends on the display, unlike PQ.
+fn hlg_to_linear(rgb: [f32; 3], primaries: Primaries) -> [f32; 3] {
+    const A: f32 = 0.178_832_77;
+    const B: f32 = 0.284_668_92;
+    const C: f32 = 0.559_910_7;
+
+    let scene = rgb.map(|value| {
+        let value = value.clamp(0.0, 1.0);
+        if value <= 0.5 {
+            value * value / 3.0
+        } else {
+            (((value - C) / A).exp() + B) / 12.0
+        }
+    });
+    // The system gamma is applied to the luminance, so it doesn't shift hues
+    let gain = primaries.luminance(scene).powf(HLG_SYSTEM_GAMMA - 1.0) * HLG_DISPLAY_LUMINANCE
+        / REFERENCE_WHITE;
+    scene.map(|value| value * gain)
+}
+
+pub(crate) fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub(crate) fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{actual} is not close to {expected}"
+        );
+    }
+
+    #[test]
+    fn operators_map_the_white_point_to_white() {
+        for operator in [ToneMapOperator::Reinhard, ToneMapOperator::AcesFilmic] {
+            assert_close(map(4.0, operator, 4.0), 1.0);
+            assert_close(map(100.0, operator, 4.0), 1.0);
+            assert_close(map(0.0, operator, 4.0), 0.0);
+        }
+        assert_close(map(0.5, ToneMapOperator::Clip, 4.0), 0.5);
+        assert_close(map(2.0, ToneMapOperator::Clip, 4.0), 1.0);
+    }
+
+    #[test]
+    fn operators_keep_the_order_of_values() {
+        for operator in [ToneMapOperator::Reinhard, ToneMapOperator::AcesFilmic] {
+            let mapped: Vec<_> = (0..=40_u8)
+                .map(|step| map(f32::from(step) / 10.0, operator, 4.0))
+                .collect();
+            assert!(mapped.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+    }
+
+    #[test]
+    fn reference_white_decodes_to_sdr_white() {
+        // 203 nits in PQ, and 75% in HLG
+        assert_close(pq_to_linear(0.580_7), 1.0);
+        assert_close(pq_to_linear(1.0), PQ_MAX_LUMINANCE / REFERENCE_WHITE);
+        for value in hlg_to_linear([0.75; 3], Primaries::Bt2020) {
+            assert_close(value, 1.0);
+        }
+    }
+
+    #[test]
+    fn srgb_curve_round_trips() {
+        for step in 0..=20_u8 {
+            let value = f32::from(step) / 20.0;
+            assert_close(linear_to_srgb(srgb_to_linear(value)), value);
+        }
+    }
+
+    #[test]
+    fn primaries_keep_white() {
+        for primaries in [Primaries::Bt709, Primaries::Bt2020, Primaries::DisplayP3] {
+            for value in primaries.to_bt709([1.0; 3]) {
+                assert_close(value, 1.0);
+            }
+            assert_close(primaries.luminance([1.0; 3]), 1.0);
+        }
+        let [red, green, blue] = Primaries::Bt2020.to_bt709([0.0, 1.0, 0.0]);
+        assert!(red < 0.0 && green > 1.0 && blue < 0.0);
+    }
+
+    #[test]
+    fn only_hdr_transfers_are_tone_mapped() {
+        let pixel = |img: &DynamicImage| img.to_rgb8().get_pixel(0, 0).0;
+        let gray = || DynamicImage::ImageRgb16(ImageBuffer::from_pixel(1, 1, Rgb([32_768; 3])));
+        let to_sdr = |transfer| {
+            to_sdr(
+                gray(),
+                ToneMapOperator::Clip,
+                0.0,
+                DEFAULT_WHITE_POINT,
+                transfer,
+                Primaries::Bt709,
+            )
+        };
+
+        assert_eq!(pixel(&to_sdr(HdrTransfer::Srgb)), [128; 3]);
+        // Half of linear light is brighter once encoded as sRGB
+        assert_eq!(pixel(&to_sdr(HdrTransfer::Linear)), [188; 3]);
+        // Half of PQ is 92 nits, below SDR white
+        assert!(pixel(&to_sdr(HdrTransfer::Pq))[0] < 188);
+        assert!(matches!(
+            to_sdr(HdrTransfer::Pq),
+            DynamicImage::ImageRgb8(_)
+        ));
+    }
+}
@@ -0,0 +1,503 @@
+use crate::app_dirs::PROJECT_DIRS;
+use crate::screenshot_library::{Screenshot, ScreenshotHandle, ScreenshotLibrary};
+use crate::steam::{self, open_steam_section};
+use crate::steam_locate::most_recent_account_id;
+use crate::vdf::{self, Object, Value};
+use chrono::{Local, TimeZone};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions, copy, create_dir_all, metadata, read_to_string, rename, write};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SCREENSHOTS_APP_ID: &str = "760";
+const SCREENSHOTS_VDF: &str = "screenshots.vdf";
+const CREATION_FIXUPS_FILE: &str = "creation_fixups.json";
+/// Locked while the pending capture times are read and written, which the Steamworks helper
+/// processes of each `AppID` and the app itself do at the same time
+const CREATION_FIXUPS_LOCK_FILE: &str = "creation_fixups.lock";
+// Steam uses an invalid handle for screenshots it hasn't loaded into the current session
+const UNLOADED_SCREENSHOT_HANDLE: &str = "18446744073709551615";
+
+/// The `screenshots.vdf` files that were already backed up in this session
+static BACKED_UP_VDFS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Imports screenshots by writing them straight into the user's Steam userdata folder.
+///
+/// This works without Steamworks, but Steam must not be running as it keeps its own copy of
+/// `screenshots.vdf` in memory and would overwrite the new entries.
+pub struct UserdataLibrary {
+    app_id: u32,
+    screenshots_dir: PathBuf,
+    vdf_path: PathBuf,
+    screenshots: Mutex<Object>,
+}
+
+impl UserdataLibrary {
+    /// Opens the screenshot library of the most recent Steam user. Its `screenshots.vdf` is backed
+    /// up before the first screenshot is added to it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message if Steam is running or the user's screenshot library could not be read.
+    pub fn new(app_id: u32) -> Result<Self, String> {
+        ensure_steam_closed()?;
+
+        if let Err(error) = apply_creation_fixups() {
+            warn!("Failed to apply pending capture times: {error}");
+        }
+
+        let account_id = most_recent_account_id()?;
+        let screenshots_dir = app_screenshots_dir(account_id, app_id)?;
+        let vdf_path = library_dir(account_id)?.join(SCREENSHOTS_VDF);
+
+        create_dir_all(screenshots_dir.join("thumbnails"))
+            .map_err(|error| format!("Failed to create screenshot folder: {error}"))?;
+        let screenshots = load_screenshots_vdf(&vdf_path)?;
+
+        info!(
+            "Importing into userdata of account {account_id} at {}",
+            screenshots_dir.display()
+        );
+
+        Ok(Self {
+            app_id,
+            screenshots_dir,
+            vdf_path,
+            screenshots: Mutex::new(screenshots),
+        })
+    }
+
+    fn unused_file_name(&self, app_screenshots: &Object, creation: i64) -> Result<String, String> {
+        let timestamp = Local
+            .timestamp_opt(creation, 0)
+            .earliest()
+            .ok_or_else(|| format!("Invalid capture time: {creation}"))?
+            .format("%Y%m%d%H%M%S");
+
+        (1..=u32::MAX)
+            .map(|index| format!("{timestamp}_{index}.jpg"))
+            .find(|file_name| {
+                let relative_path = format!("{}/screenshots/{file_name}", self.app_id);
+                !self.screenshots_dir.join(file_name).exists()
+                    && !app_screenshots.iter().any(|(_, entry)| {
+                        matches!(entry, Value::Object(entry)
+                            if entry.get_str("filename") == Some(relative_path.as_str()))
+                    })
+            })
+            .ok_or_else(|| "Failed to find an unused screenshot file name".to_string())
+    }
+}
+
+impl ScreenshotLibrary for UserdataLibrary {
+    fn add_screenshot(&self, screenshot: &Screenshot<'_>) -> Result<ScreenshotHandle, String> {
+        if screenshot.vr.is_some() {
+            return Err("VR screenshots can only be imported through Steamworks".to_string());
+        }
+        let mut screenshots = self
+            .screenshots
+            .lock()
+            .map_err(|error| format!("Failed to access screenshots.vdf: {error}"))?;
+        let app_screenshots = screenshots
+            .object_mut("Screenshots")
+            .object_mut(&self.app_id.to_string());
+
+        let creation = match screenshot.capture_time {
+            Some(capture_time) => capture_time,
+            None => unix_time()?,
+        };
+        let file_name = self.unused_file_name(app_screenshots, creation)?;
+        let new_screenshot_path = self.screenshots_dir.join(&file_name);
+        let new_thumbnail_path = self.screenshots_dir.join("thumbnails").join(&file_name);
+
+        info!(
+            "Copying {} to {}",
+            screenshot.path.display(),
+            new_screenshot_path.display()
+        );
+        copy(screenshot.path, &new_screenshot_path).map_err(|error| {
+            format!("Failed to copy {}: {error}", new_screenshot_path.display())
+        })?;
+        copy(screenshot.thumbnail_path, &new_thumbnail_path)
+            .map_err(|error| format!("Failed to copy {}: {error}", new_thumbnail_path.display()))?;
+
+        let index = app_screenshots
+            .iter()
+            .filter_map(|(key, _)| key.parse::<ScreenshotHandle>().ok())
+            .max()
+            .map_or(0, |index| index + 1);
+
+        let mut entry = Object::default();
+        entry.insert_str("type", "1");
+        entry.insert_str(
+            "filename",
+            format!("{}/screenshots/{file_name}", self.app_id),
+        );
+        entry.insert_str(
+            "thumbnail",
+            format!("{}/screenshots/thumbnails/{file_name}", self.app_id),
+        );
+        entry.insert_str("vrfilename", "");
+        entry.insert_str("imported", "0");
+        entry.insert_str("width", screenshot.width.to_string());
+        entry.insert_str("height", screenshot.height.to_string());
+        entry.insert_str("gameid", self.app_id.to_string());
+        entry.insert_str("creation", creation.to_string());
+        entry.insert_str(
+            "caption",
+            screenshot.tags.location.as_deref().unwrap_or_default(),
+        );
+        entry.insert_str("Permissions", "2");
+        entry.insert_str("hscreenshot", UNLOADED_SCREENSHOT_HANDLE);
+        app_screenshots.insert(index.to_string(), Value::Object(entry));
+
+        save_screenshots_vdf(&self.vdf_path, &screenshots)?;
+        // Steam only stores tags in the cloud, which can't be reached without Steamworks
+        if !screenshot.tags.tagged_users.is_empty() || !screenshot.tags.workshop_items.is_empty() {
+            warn!(
+                "Direct imports can't tag users or Workshop items, {} was imported without tags",
+                screenshot.path.display()
+            );
+        }
+        Ok(index)
+    }
+
+    fn run_callbacks(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn open_section(&self, section: &str) -> Result<(), String> {
+        open_steam_section(section)
+    }
+}
+
+/// A screenshot imported through Steamworks whose `creation` time still has to be changed to
+/// its original capture time.
+#[derive(Serialize, Deserialize)]
+struct CreationFixup {
+    account_id: u32,
+    app_id: u32,
+    size: u64,
+    sha256: String,
+    creation: i64,
+}
+
+/// Remembers that the screenshot Steam copied from `screenshot_path` should be dated at
+/// `creation` once Steam is closed and `screenshots.vdf` can safely be edited.
+///
+/// Steam copies imported screenshots verbatim, so the copy in its library is found again by
+/// comparing file contents.
+pub fn queue_creation_fixup(
+    app_id: u32,
+    screenshot_path: &Path,
+    creation: i64,
+) -> Result<(), String> {
+    let fixup = CreationFixup {
+        account_id: most_recent_account_id()?,
+        app_id,
+        size: metadata(screenshot_path)
+            .map_err(|error| format!("Failed to read {}: {error}", screenshot_path.display()))?
+            .len(),
+        sha256: file_sha256(screenshot_path)?,
+        creation,
+    };
+
+    let _lock = lock_creation_fixups()?;
+    let mut fixups = load_creation_fixups()?;
+    fixups.push(fixup);
+    save_creation_fixups(&fixups)
+}
+
+/// Applies capture times queued by [`queue_creation_fixup`] to `screenshots.vdf`, returning how
+/// many screenshots were updated.
+pub fn apply_creation_fixups() -> Result<usize, String> {
+    let _lock = lock_creation_fixups()?;
+    let mut fixups = load_creation_fixups()?;
+    if fixups.is_empty() {
+        return Ok(0);
+    }
+    ensure_steam_closed()?;
+
+    let mut account_ids: Vec<u32> = fixups.iter().map(|fixup| fixup.account_id).collect();
+    account_ids.sort_unstable();
+    account_ids.dedup();
+
+    let mut applied = 0;
+    for account_id in account_ids {
+        let library_dir = library_dir(account_id)?;
+        let vdf_path = library_dir.join(SCREENSHOTS_VDF);
+        let mut screenshots = load_screenshots_vdf(&vdf_path)?;
+        let mut changed = false;
+
+        for (app_id, app_screenshots) in screenshots.object_mut("Screenshots").iter_mut() {
+            let Value::Object(app_screenshots) = app_screenshots else {
+                continue;
+            };
+
+            for (_, entry) in app_screenshots.iter_mut() {
+                let Value::Object(entry) = entry else {
+                    continue;
+                };
+                let Some(file_name) = entry.get_str("filename") else {
+                    continue;
+                };
+                let path = library_dir.join("remote").join(file_name);
+                let Some(index) = matching_fixup(&fixups, account_id, app_id, &path) else {
+                    continue;
+                };
+
+                let fixup = fixups.swap_remove(index);
+                info!("Dating {} at {}", path.display(), fixup.creation);
+                entry.insert_str("creation", fixup.creation.to_string());
+                changed = true;
+                applied += 1;
+            }
+        }
+
+        if changed {
+            save_screenshots_vdf(&vdf_path, &screenshots)?;
+        }
+    }
+
+    if !fixups.is_empty() {
+        warn!(
+            "{} imported screenshots could not be found in the Steam library to restore their \
+             capture time",
+            fixups.len()
+        );
+    }
+    save_creation_fixups(&fixups)?;
+
+    Ok(applied)
+}
+
+fn matching_fixup(
+    fixups: &[CreationFixup],
+    account_id: u32,
+    app_id: &str,
+    path: &Path,
+) -> Option<usize> {
+    let size = metadata(path).ok()?.len();
+    let mut candidates = fixups.iter().enumerate().filter(|(_, fixup)| {
+        fixup.account_id == account_id && fixup.app_id.to_string() == app_id && fixup.size == size
+    });
+
+    let first = candidates.next()?;
+    let sha256 = file_sha256(path).ok()?;
+    std::iter::once(first)
+        .chain(candidates)
+        .find(|(_, fixup)| fixup.sha256 == sha256)
+        .map(|(index, _)| index)
+}
+
+/// Waits for an exclusive lock on the pending capture times, which is held until the returned
+/// file is closed.
+fn lock_creation_fixups() -> Result<File, String> {
+    let data_dir = PROJECT_DIRS.data_dir();
+    create_dir_all(data_dir)
+        .map_err(|error| format!("Failed to create {}: {error}", data_dir.display()))?;
+    lock_file(&data_dir.join(CREATION_FIXUPS_LOCK_FILE))
+}
+
+fn lock_file(path: &Path) -> Result<File, String> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(|error| format!("Failed to open {}: {error}", path.display()))?;
+    file.lock()
+        .map_err(|error| format!("Failed to lock {}: {error}", path.display()))?;
+    Ok(file)
+}
+
+fn load_creation_fixups() -> Result<Vec<CreationFixup>, String> {
+    let path = PROJECT_DIRS.data_dir().join(CREATION_FIXUPS_FILE);
+    match read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text)
+            .map_err(|error| format!("Failed to parse {}: {error}", path.display())),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(error) => Err(format!("Failed to read {}: {error}", path.display())),
+    }
+}
+
+fn save_creation_fixups(fixups: &[CreationFixup]) -> Result<(), String> {
+    let data_dir = PROJECT_DIRS.data_dir();
+    create_dir_all(data_dir)
+        .map_err(|error| format!("Failed to create {}: {error}", data_dir.display()))?;
+    let path = data_dir.join(CREATION_FIXUPS_FILE);
+    let text = serde_json::to_string(fixups)
+        .map_err(|error| format!("Failed to serialize pending capture times: {error}"))?;
+    write(&path, text).map_err(|error| format!("Failed to write {}: {error}", path.display()))
+}
+
+fn ensure_steam_closed() -> Result<(), String> {
+    if steam::is_running() {
+        return Err(
+            "Steam must be closed to edit the screenshots in its userdata folder".to_string(),
+        );
+    }
+    Ok(())
+}
+
+/// Returns the folder Steam stores `app_id`'s screenshots for `account_id` in.
+pub fn app_screenshots_dir(account_id: u32, app_id: u32) -> Result<PathBuf, String> {
+    Ok(library_dir(account_id)?
+        .join("remote")
+        .join(app_id.to_string())
+        .join("screenshots"))
+}
+
+fn library_dir(account_id: u32) -> Result<PathBuf, String> {
+    let steam_dir = steamlocate::locate().map_err(|_| "Failed to locate Steam installation")?;
+    Ok(steam_dir
+        .path()
+        .join("userdata")
+        .join(account_id.to_string())
+        .join(SCREENSHOTS_APP_ID))
+}
+
+/// Parses `screenshots.vdf`, returning an empty library if it doesn't exist yet.
+fn load_screenshots_vdf(vdf_path: &Path) -> Result<Object, String> {
+    if !vdf_path.exists() {
+        return Ok(Object::default());
+    }
+
+    vdf::load(vdf_path)
+}
+
+/// Writes `screenshots.vdf`, backing up the original the first time it's replaced.
+fn save_screenshots_vdf(vdf_path: &Path, screenshots: &Object) -> Result<(), String> {
+    backup_vdf_once(vdf_path)?;
+    let temp_path = vdf_path.with_extension("vdf.tmp");
+    write(&temp_path, vdf::to_string(screenshots))
+        .map_err(|error| format!("Failed to write {}: {error}", temp_path.display()))?;
+    rename(&temp_path, vdf_path)
+        .map_err(|error| format!("Failed to replace {}: {error}", vdf_path.display()))
+}
+
+/// Backs up `vdf_path` the first time it's written in this session, so the backup holds the
+/// library as it was before the importer changed it.
+fn backup_vdf_once(vdf_path: &Path) -> Result<(), String> {
+    let mut backed_up = BACKED_UP_VDFS
+        .lock()
+        .map_err(|error| format!("Failed to access screenshots.vdf backups: {error}"))?;
+    if backed_up.iter().any(|path| path == vdf_path) {
+        return Ok(());
+    }
+
+    if vdf_path.exists() {
+        backup_vdf(vdf_path)?;
+    }
+    backed_up.push(vdf_path.to_path_buf());
+    Ok(())
+}
+
+/// Copies `vdf_path` to a backup next to it, never replacing an earlier backup.
+fn backup_vdf(vdf_path: &Path) -> Result<(), String> {
+    let timestamp = unix_time()?;
+    for index in 0..=u32::MAX {
+        let backup_path = if index == 0 {
+            vdf_path.with_extension(format!("vdf.{timestamp}.bak"))
+        } else {
+            vdf_path.with_extension(format!("vdf.{timestamp}_{index}.bak"))
+        };
+        let mut backup = match File::create_new(&backup_path) {
+            Ok(backup) => backup,
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => {
+                return Err(format!(
+                    "Failed to create {}: {error}",
+                    backup_path.display()
+                ));
+            }
+        };
+
+        info!(
+            "Backing up {} to {}",
+            vdf_path.display(),
+            backup_path.display()
+        );
+        let mut original = File::open(vdf_path)
+            .map_err(|error| format!("Failed to open {}: {error}", vdf_path.display()))?;
+        return io::copy(&mut original, &mut backup)
+            .map(|_| ())
+            .map_err(|error| format!("Failed to back up {}: {error}", vdf_path.display()));
+    }
+
+    Err(format!(
+        "Failed to find an unused backup name for {}",
+        vdf_path.display()
+    ))
+}
+
+pub fn file_sha256(path: &Path) -> Result<String, String> {
+    let mut file =
+        File::open(path).map_err(|error| format!("Failed to open {}: {error}", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .map_err(|error| format!("Failed to read {}: {error}", path.display()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn unix_time() -> Result<i64, String> {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|error| format!("Invalid system time: {error}"))?
+        .as_secs();
+    i64::try_from(seconds).map_err(|error| format!("Invalid system time: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn file_locks_exclude_other_handles() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(CREATION_FIXUPS_LOCK_FILE);
+        let lock = lock_file(&path).unwrap();
+        // Other processes open their own handle, which can't be locked at the same time
+        let other = File::options().write(true).open(&path).unwrap();
+        assert!(other.try_lock().is_err());
+        drop(lock);
+        assert!(other.try_lock().is_ok());
+    }
+
+    #[test]
+    fn backups_never_replace_earlier_ones() {
+        let dir = TempDir::new().unwrap();
+        let vdf_path = dir.path().join(SCREENSHOTS_VDF);
+        write(&vdf_path, "original").unwrap();
+        backup_vdf(&vdf_path).unwrap();
+        write(&vdf_path, "modified").unwrap();
+        backup_vdf(&vdf_path).unwrap();
+
+        let mut backups: Vec<String> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path != &vdf_path)
+            .map(|path| read_to_string(path).unwrap())
+            .collect();
+        backups.sort();
+        assert_eq!(backups, ["modified", "original"]);
+    }
+
+    #[test]
+    fn vdf_is_backed_up_once_per_session() {
+        let dir = TempDir::new().unwrap();
+        let vdf_path = dir.path().join(SCREENSHOTS_VDF);
+        write(&vdf_path, "\"Screenshots\" {}").unwrap();
+
+        let screenshots = load_screenshots_vdf(&vdf_path).unwrap();
+        save_screenshots_vdf(&vdf_path, &screenshots).unwrap();
+        save_screenshots_vdf(&vdf_path, &screenshots).unwrap();
+
+        let backups = std::fs::read_dir(dir.path()).unwrap().count() - 1;
+        assert_eq!(backups, 1);
+    }
+}
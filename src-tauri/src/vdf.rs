@@ -0,0 +1,398 @@
+use std::fmt::Write as _;
+use std::fs::read_to_string;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+/// A value in a text `KeyValues` (VDF) document.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Object(Object),
+}
+
+/// An ordered list of key/value pairs, as stored in a VDF file.
+///
+/// Keys are matched case-insensitively like Steam does, but their original case and order is
+/// kept so files can be written back without reshuffling them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Object {
+    entries: Vec<(String, Value)>,
+}
+
+impl Object {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries
+            .iter()
+            .find(|(entry_key, _)| entry_key.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value)
+    }
+
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            Value::String(value) => Some(value),
+            Value::Object(_) => None,
+        }
+    }
+
+    pub fn get_object(&self, key: &str) -> Option<&Object> {
+        match self.get(key)? {
+            Value::Object(object) => Some(object),
+            Value::String(_) => None,
+        }
+    }
+
+    /// Returns the object stored under `key`, replacing any string value and creating the
+    /// object if it doesn't exist.
+    pub fn object_mut(&mut self, key: &str) -> &mut Object {
+        let existing = self
+            .entries
+            .iter()
+            .position(|(entry_key, _)| entry_key.eq_ignore_ascii_case(key));
+        let index = if let Some(index) = existing {
+            index
+        } else {
+            self.entries
+                .push((key.to_string(), Value::Object(Object::default())));
+            self.entries.len() - 1
+        };
+
+        let value = &mut self.entries[index].1;
+        if let Value::String(_) = value {
+            *value = Value::Object(Object::default());
+        }
+        match value {
+            Value::Object(object) => object,
+            Value::String(_) => unreachable!("string values were replaced above"),
+        }
+    }
+
+    /// Sets `key` to `value`, keeping the position of an existing entry.
+    pub fn insert(&mut self, key: impl Into<String>, value: Value) {
+        let key = key.into();
+        match self
+            .entries
+            .iter_mut()
+            .find(|(entry_key, _)| entry_key.eq_ignore_ascii_case(&key))
+        {
+            Some((_, existing)) => *existing = value,
+            None => self.entries.push((key, value)),
+        }
+    }
+
+    pub fn insert_str(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.insert(key, Value::String(value.into()));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&str, &mut Value)> {
+        self.entries
+            .iter_mut()
+            .map(|(key, value)| (key.as_str(), &mut *value))
+    }
+}
+
+/// Parses the contents of a text VDF file into its root object.
+pub fn parse(text: &str) -> Result<Object, String> {
+    let mut chars = text.chars().peekable();
+    let object = parse_object(&mut chars, false)?;
+    Ok(object)
+}
+
+/// Reads and parses a text VDF file.
+pub fn load(path: &Path) -> Result<Object, String> {
+    let text = read_to_string(path)
+        .map_err(|error| format!("Failed to read {}: {error}", path.display()))?;
+    parse(&text).map_err(|error| format!("Failed to parse {}: {error}", path.display()))
+}
+
+/// Serializes `object` in the tab indented layout Steam writes.
+pub fn to_string(object: &Object) -> String {
+    let mut output = String::new();
+    write_object(&mut output, object, 0);
+    output
+}
+
+enum Token {
+    String(String),
+    Open,
+    Close,
+}
+
+fn parse_object(chars: &mut Peekable<Chars<'_>>, nested: bool) -> Result<Object, String> {
+    let mut object = Object::default();
+
+    loop {
+        let key = match next_token(chars)? {
+            Some(Token::String(key)) => key,
+            Some(Token::Close) if nested => return Ok(object),
+            None if !nested => return Ok(object),
+            Some(Token::Close) => return Err("Unexpected '}' at the root of the file".to_string()),
+            Some(Token::Open) => return Err("Expected a key but found '{'".to_string()),
+            None => return Err("Unexpected end of file, missing '}'".to_string()),
+        };
+
+        let value = match next_token(chars)? {
+            Some(Token::String(value)) => Value::String(value),
+            Some(Token::Open) => Value::Object(parse_object(chars, true)?),
+            Some(Token::Close) | None => {
+                return Err(format!("Missing value for key \"{key}\""));
+            }
+        };
+
+        skip_conditional(chars);
+        object.entries.push((key, value));
+    }
+}
+
+fn next_token(chars: &mut Peekable<Chars<'_>>) -> Result<Option<Token>, String> {
+    skip_whitespace_and_comments(chars);
+
+    let Some(&first) = chars.peek() else {
+        return Ok(None);
+    };
+
+    match first {
+        '{' => {
+            chars.next();
+            Ok(Some(Token::Open))
+        }
+        '}' => {
+            chars.next();
+            Ok(Some(Token::Close))
+        }
+        '"' => {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => return Ok(Some(Token::String(value))),
+                    Some('\\') => match chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some(escaped) => value.push(escaped),
+                        None => return Err("Unterminated escape sequence".to_string()),
+                    },
+                    Some(character) => value.push(character),
+                    None => return Err("Unterminated string".to_string()),
+                }
+            }
+        }
+        _ => {
+            let mut value = String::new();
+            while let Some(&character) = chars.peek() {
+                if character.is_whitespace() || matches!(character, '{' | '}' | '"') {
+                    break;
+                }
+                value.push(character);
+                chars.next();
+            }
+            Ok(Some(Token::String(value)))
+        }
+    }
+}
+
+fn skip_whitespace_and_comments(chars: &mut Peekable<Chars<'_>>) {
+    while let Some(&character) = chars.peek() {
+        if character.is_whitespace() {
+            chars.next();
+        } else if character == '/' {
+            let mut lookahead = chars.clone();
+            lookahead.next();
+            if lookahead.peek() != Some(&'/') {
+                return;
+            }
+            for character in chars.by_ref() {
+                if character == '\n' {
+                    break;
+                }
+            }
+        } else {
+            return;
+        }
+    }
+}
+
+/// Skips platform conditionals such as `[$WIN32]` that may follow a value.
+fn skip_conditional(chars: &mut Peekable<Chars<'_>>) {
+    let mut lookahead = chars.clone();
+    while lookahead
+        .next_if(|character| *character == ' ' || *character == '\t')
+        .is_some()
+    {}
+    if lookahead.peek() != Some(&'[') {
+        return;
+    }
+
+    *chars = lookahead;
+    for character in chars.by_ref() {
+        if character == ']' {
+            break;
+        }
+    }
+}
+
+fn write_object(output: &mut String, object: &Object, depth: usize) {
+    let indent = "\t".repeat(depth);
+
+    for (key, value) in &object.entries {
+        match value {
+            Value::String(value) => {
+                let _ = writeln!(
+                    output,
+                    "{indent}\"{}\"\t\t\"{}\"",
+                    escape(key),
+                    escape(value)
+                );
+            }
+            Value::Object(object) => {
+                let _ = writeln!(output, "{indent}\"{}\"", escape(key));
+                let _ = writeln!(output, "{indent}{{");
+                write_object(output, object, depth + 1);
+                let _ = writeln!(output, "{indent}}}");
+            }
+        }
+    }
+}
+
+/// Escapes the characters [`next_token`] unescapes, so written values are read back unchanged.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        match character {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREENSHOTS_VDF: &str = r#""Screenshots"
+{
+	// Entries Steam wrote
+	"620"
+	{
+		"0"
+		{
+			"filename"		"620/screenshots/20240101120000_1.jpg"
+			"caption"		"Chell \"the\" test subject"
+			"creation"		"1704110400" [$WIN32]
+		}
+	}
+}
+"#;
+
+    #[test]
+    fn loads_files_like_loginusers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("loginusers.vdf");
+        std::fs::write(
+            &path,
+            "\"users\"\n{\n\t\"76561197960287930\"\n\t{\n\t\t\"PersonaName\"\t\t\"Rabscuttle\"\n\
+             \t\t\"MostRecent\"\t\t\"1\"\n\t}\n}\n",
+        )
+        .unwrap();
+
+        let loginusers = load(&path).unwrap();
+        let user = loginusers
+            .get_object("Users")
+            .and_then(|users| users.get_object("76561197960287930"))
+            .unwrap();
+        assert_eq!(user.get_str("personaname"), Some("Rabscuttle"));
+        assert_eq!(user.get_object("MostRecent"), None);
+        assert!(load(&dir.path().join("missing.vdf")).is_err());
+    }
+
+    #[test]
+    fn parses_nested_objects_comments_and_conditionals() {
+        let root = parse(SCREENSHOTS_VDF).unwrap();
+        let Some(Value::Object(screenshots)) = root.get("screenshots") else {
+            panic!("missing Screenshots object");
+        };
+        let Some(Value::Object(app)) = screenshots.get("620") else {
+            panic!("missing app object");
+        };
+        let Some(Value::Object(entry)) = app.get("0") else {
+            panic!("missing screenshot entry");
+        };
+
+        assert_eq!(
+            entry.get_str("FileName"),
+            Some("620/screenshots/20240101120000_1.jpg")
+        );
+        assert_eq!(entry.get_str("caption"), Some("Chell \"the\" test subject"));
+        assert_eq!(entry.get_str("creation"), Some("1704110400"));
+    }
+
+    #[test]
+    fn parses_unquoted_tokens() {
+        let root = parse("key value\nobject { nested 1 }").unwrap();
+
+        assert_eq!(root.get_str("key"), Some("value"));
+        let Some(Value::Object(object)) = root.get("object") else {
+            panic!("missing object");
+        };
+        assert_eq!(object.get_str("nested"), Some("1"));
+    }
+
+    #[test]
+    fn round_trips_written_files() {
+        let root = parse(SCREENSHOTS_VDF).unwrap();
+
+        assert_eq!(parse(&to_string(&root)).unwrap(), root);
+    }
+
+    #[test]
+    fn round_trips_escaped_characters() {
+        let mut root = Object::default();
+        root.insert_str("caption", "line\nbreak\ttab \"quoted\" back\\slash");
+        root.object_mut("nested \"key\"").insert_str("empty", "");
+
+        let written = to_string(&root);
+
+        assert!(!written.contains("line\nbreak"));
+        assert_eq!(parse(&written).unwrap(), root);
+    }
+
+    #[test]
+    fn keeps_the_order_and_case_of_keys() {
+        let mut root = Object::default();
+        root.insert_str("B", "1");
+        root.insert_str("a", "2");
+        root.insert_str("b", "3");
+
+        assert_eq!(to_string(&root), "\"B\"\t\t\"3\"\n\"a\"\t\t\"2\"\n");
+    }
+
+    #[test]
+    fn object_mut_replaces_string_values() {
+        let mut root = parse("\"Screenshots\" \"\"").unwrap();
+        root.object_mut("screenshots").insert_str("620", "x");
+
+        let Some(Value::Object(screenshots)) = root.get("Screenshots") else {
+            panic!("string was not replaced with an object");
+        };
+        assert_eq!(screenshots.get_str("620"), Some("x"));
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert!(parse("\"key\" { \"nested\" \"value\"").is_err());
+        assert!(parse("\"key\" \"value\" }").is_err());
+        assert!(parse("\"key\"").is_err());
+        assert!(parse("{ \"key\" \"value\" }").is_err());
+        assert!(parse("\"key\" \"unterminated").is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use image::{DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+
+/// How far an aspect ratio may be off, as a fraction, to still count as a layout's.
+const ASPECT_TOLERANCE: f64 = 0.02;
+/// Aspect ratio of regular side-by-side stereo screenshots above which they're told apart from
+/// ultrawide ones, two 4:3 eyes being 2.67.
+const MIN_SIDE_BY_SIDE_ASPECT: f64 = 2.4;
+/// Aspect ratio of regular over-under stereo screenshots below which they're told apart from
+/// square ones, two 16:9 eyes being 0.89.
+const MAX_OVER_UNDER_ASPECT: f64 = 0.95;
+
+/// Whether screenshots are imported as regular screenshots or as VR screenshots, which Steam
+/// shows in VR along with a regular preview.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Deserialize,
+    Serialize,
+    specta::Type,
+    clap::ValueEnum,
+)]
+pub enum VrMode {
+    /// Regular screenshots
+    #[default]
+    Off,
+    /// VR screenshots whose type is detected from their aspect ratio
+    Auto,
+    /// A single flat image
+    Mono,
+    /// A flat image for each eye
+    Stereo,
+    /// Six square cube faces next to each other
+    MonoCubemap,
+    /// A 360° equirectangular panorama
+    MonoPanorama,
+    /// A 360° equirectangular panorama for each eye
+    StereoPanorama,
+}
+
+/// How the images for both eyes of stereo screenshots are arranged.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Deserialize,
+    Serialize,
+    specta::Type,
+    clap::ValueEnum,
+)]
+pub enum StereoLayout {
+    /// Detected from the aspect ratio
+    #[default]
+    Auto,
+    /// Left eye on the left, right eye on the right
+    SideBySide,
+    /// Left eye on top, right eye below
+    OverUnder,
+}
+
+/// The type of a VR screenshot that's added to the library.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, specta::Type)]
+pub enum VrScreenshotType {
+    Mono,
+    Stereo,
+    MonoCubemap,
+    MonoPanorama,
+    StereoPanorama,
+}
+
+/// The type and layout of a VR screenshot.
+#[derive(Clone, Copy, Debug)]
+pub struct VrLayout {
+    pub kind: VrScreenshotType,
+    side_by_side: bool,
+}
+
+impl VrMode {
+    /// Works out how a screenshot of `width` by `height` pixels is laid out, or `None` if it's
+    /// imported as a regular screenshot.
+    #[must_use]
+    pub fn layout(self, stereo_layout: StereoLayout, width: u32, height: u32) -> Option<VrLayout> {
+        let aspect = f64::from(width) / f64::from(height.max(1));
+        let kind = match self {
+            Self::Off => return None,
+            Self::Auto => detect_type(aspect),
+            Self::Mono => VrScreenshotType::Mono,
+            Self::Stereo => VrScreenshotType::Stereo,
+            Self::MonoCubemap => VrScreenshotType::MonoCubemap,
+            Self::MonoPanorama => VrScreenshotType::MonoPanorama,
+            Self::StereoPanorama => VrScreenshotType::StereoPanorama,
+        };
+        let side_by_side = match stereo_layout {
+            StereoLayout::SideBySide => true,
+            StereoLayout::OverUnder => false,
+            // Both eyes' 2:1 panoramas are 4:1 next to each other and 1:1 on top of each other
+            StereoLayout::Auto if kind == VrScreenshotType::StereoPanorama => aspect > 2.0,
+            StereoLayout::Auto => aspect >= 1.0,
+        };
+        Some(VrLayout { kind, side_by_side })
+    }
+}
+
+/// Guesses the type of a VR screenshot from its aspect ratio. Square images are taken for
+/// over-under stereo panoramas and 2:1 ones for mono panoramas, as that's how they're usually
+/// captured.
+fn detect_type(aspect: f64) -> VrScreenshotType {
+    let is_about = |expected: f64| (aspect / expected - 1.0).abs() <= ASPECT_TOLERANCE;
+    if is_about(6.0) {
+        VrScreenshotType::MonoCubemap
+    } else if is_about(4.0) || is_about(1.0) {
+        VrScreenshotType::StereoPanorama
+    } else if is_about(2.0) {
+        VrScreenshotType::MonoPanorama
+    } else if !(MAX_OVER_UNDER_ASPECT..=MIN_SIDE_BY_SIDE_ASPECT).contains(&aspect) {
+        VrScreenshotType::Stereo
+    } else {
+        VrScreenshotType::Mono
+    }
+}
+
+impl VrLayout {
+    /// The part of the screenshot that's shown outside VR: what the left eye sees, the front
+    /// quarter of panoramas, or the first face of cubemaps.
+    #[must_use]
+    pub fn preview(&self, img: &DynamicImage) -> DynamicImage {
+        let (width, height) = img.dimensions();
+        let (eye_width, eye_height) = match self.kind {
+            VrScreenshotType::Stereo | VrScreenshotType::StereoPanorama if self.side_by_side => {
+                (width / 2, height)
+            }
+            VrScreenshotType::Stereo | VrScreenshotType::StereoPanorama => (width, height / 2),
+            _ => (width, height),
+        };
+
+        let (x, y, crop_width, crop_height) = match self.kind {
+            VrScreenshotType::Mono | VrScreenshotType::Stereo => (0, 0, eye_width, eye_height),
+            VrScreenshotType::MonoCubemap => {
+                let side = height.min(width / 6);
+                (0, 0, side, side)
+            }
+            // The middle of an equirectangular panorama is straight ahead, a quarter of it is a
+            // 90° view that's cropped to 16:9
+            VrScreenshotType::MonoPanorama | VrScreenshotType::StereoPanorama => {
+                let view_width = eye_width / 4;
+                let view_height = (view_width * 9 / 16).min(eye_height);
+                (
+                    (eye_width - view_width) / 2,
+                    (eye_height - view_height) / 2,
+                    view_width,
+                    view_height,
+                )
+            }
+        };
+        img.crop_imm(x, y, crop_width.max(1), crop_height.max(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    fn detected(width: u32, height: u32) -> VrScreenshotType {
+        VrMode::Auto
+            .layout(StereoLayout::Auto, width, height)
+            .unwrap()
+            .kind
+    }
+
+    fn preview_size(layout: VrLayout, width: u32, height: u32) -> (u32, u32) {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(width, height));
+        layout.preview(&img).dimensions()
+    }
+
+    #[test]
+    fn detects_types_from_the_aspect_ratio() {
+        assert_eq!(detected(6144, 1024), VrScreenshotType::MonoCubemap);
+        assert_eq!(detected(8192, 2048), VrScreenshotType::StereoPanorama);
+        assert_eq!(detected(4096, 4096), VrScreenshotType::StereoPanorama);
+        assert_eq!(detected(4096, 2048), VrScreenshotType::MonoPanorama);
+        assert_eq!(detected(3840, 1080), VrScreenshotType::Stereo);
+        assert_eq!(detected(1920, 2160), VrScreenshotType::Stereo);
+        assert_eq!(detected(1920, 1080), VrScreenshotType::Mono);
+        // Ultrawide screenshots are still flat
+        assert_eq!(detected(3440, 1440), VrScreenshotType::Mono);
+    }
+
+    #[test]
+    fn off_imports_regular_screenshots() {
+        assert!(VrMode::Off.layout(StereoLayout::Auto, 4096, 2048).is_none());
+    }
+
+    #[test]
+    fn explicit_modes_ignore_the_aspect_ratio() {
+        let layout = VrMode::MonoCubemap
+            .layout(StereoLayout::Auto, 1920, 1080)
+            .unwrap();
+        assert_eq!(layout.kind, VrScreenshotType::MonoCubemap);
+    }
+
+    #[test]
+    fn previews_show_the_left_eye() {
+        let side_by_side = VrMode::Stereo
+            .layout(StereoLayout::Auto, 3840, 1080)
+            .unwrap();
+        assert_eq!(preview_size(side_by_side, 3840, 1080), (1920, 1080));
+
+        let over_under = VrMode::Stereo
+            .layout(StereoLayout::Auto, 1920, 2160)
+            .unwrap();
+        assert_eq!(preview_size(over_under, 1920, 2160), (1920, 1080));
+
+        let forced = VrMode::Stereo
+            .layout(StereoLayout::OverUnder, 3840, 1080)
+            .unwrap();
+        assert_eq!(preview_size(forced, 3840, 1080), (3840, 540));
+    }
+
+    #[test]
+    fn stereo_panoramas_are_split_by_their_aspect_ratio() {
+        let side_by_side = VrMode::StereoPanorama
+            .layout(StereoLayout::Auto, 8192, 2048)
+            .unwrap();
+        assert_eq!(preview_size(side_by_side, 8192, 2048), (1024, 576));
+
+        let over_under = VrMode::StereoPanorama
+            .layout(StereoLayout::Auto, 4096, 4096)
+            .unwrap();
+        assert_eq!(preview_size(over_under, 4096, 4096), (1024, 576));
+    }
+
+    #[test]
+    fn previews_of_cubemaps_are_their_first_face() {
+        let layout = VrMode::MonoCubemap
+            .layout(StereoLayout::Auto, 6144, 1024)
+            .unwrap();
+        assert_eq!(preview_size(layout, 6144, 1024), (1024, 1024));
+    }
+}
@@ -0,0 +1,532 @@
+use crate::AppRuntime;
+use crate::annotations::ImportAnnotations;
+use crate::app_dirs::PROJECT_DIRS;
+use crate::image_import::{ImportError, ImportOptions, ImportReport, SUPPORTED_EXTENSIONS};
+use crate::import_jobs::ImportJobs;
+use crate::{steam, steam_locate};
+use chrono::{Local, SecondsFormat};
+use log::{error, info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{File, create_dir_all, metadata, read_to_string, write};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, channel};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+
+const RULES_FILE: &str = "watch_rules.json";
+const ACTIVITY_EVENT: &str = "watchActivity";
+const MAX_ACTIVITY: usize = 200;
+/// How long a new file has to stop growing before it's imported
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+type WatchEvent = notify::Result<Event>;
+
+/// A folder whose new screenshots are imported automatically.
+#[derive(Clone, Serialize, Deserialize, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchRule {
+    id: u32,
+    path: String,
+    /// Game to import the screenshots for, or `null` to detect it when they're created
+    app_id: Option<u32>,
+    recursive: bool,
+    enabled: bool,
+    options: ImportOptions,
+}
+
+impl WatchRule {
+    fn matches(&self, path: &Path) -> bool {
+        let folder = Path::new(&self.path);
+        self.enabled
+            && if self.recursive {
+                path.starts_with(folder)
+            } else {
+                path.parent() == Some(folder)
+            }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, specta::Type)]
+pub enum WatchActivityKind {
+    Imported,
+    Skipped,
+    Failed,
+    Cancelled,
+}
+
+/// What happened to a screenshot found in a watched folder.
+#[derive(Clone, Serialize, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchActivity {
+    /// When the screenshot was handled, as an RFC 3339 timestamp
+    time: String,
+    rule_id: u32,
+    file_path: String,
+    app_id: Option<u32>,
+    kind: WatchActivityKind,
+    message: String,
+}
+
+/// A new file that's waiting to stop growing.
+struct PendingFile {
+    rule_id: u32,
+    size: Option<u64>,
+    changed: Instant,
+}
+
+struct WatchState {
+    app: AppHandle<AppRuntime>,
+    rules: Mutex<Vec<WatchRule>>,
+    activity: Mutex<VecDeque<WatchActivity>>,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+    events: Sender<WatchEvent>,
+}
+
+impl WatchState {
+    fn rules(&self) -> Result<Vec<WatchRule>, String> {
+        self.rules
+            .lock()
+            .map(|rules| rules.clone())
+            .map_err(|error| format!("Failed to access watch rules: {error}"))
+    }
+
+    fn rule(&self, id: u32) -> Option<WatchRule> {
+        self.rules().ok()?.into_iter().find(|rule| rule.id == id)
+    }
+
+    /// Changes the rules, then saves them and restarts watching.
+    fn update_rules<T>(
+        &self,
+        update: impl FnOnce(&mut Vec<WatchRule>) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut rules = self
+            .rules
+            .lock()
+            .map_err(|error| format!("Failed to access watch rules: {error}"))?;
+        let result = update(&mut rules)?;
+        save_rules(&rules)?;
+        let rules = rules.clone();
+
+        self.watch(&rules)?;
+        Ok(result)
+    }
+
+    /// Replaces the watcher with one watching the folders of the enabled `rules`.
+    fn watch(&self, rules: &[WatchRule]) -> Result<(), String> {
+        let mut watcher = self
+            .watcher
+            .lock()
+            .map_err(|error| format!("Failed to access folder watcher: {error}"))?;
+        *watcher = None;
+
+        let mut new_watcher = notify::recommended_watcher(self.events.clone())
+            .map_err(|error| format!("Failed to create folder watcher: {error}"))?;
+        for rule in rules.iter().filter(|rule| rule.enabled) {
+            let mode = if rule.recursive {
+                RecursiveMode::Recursive
+            } else {
+                RecursiveMode::NonRecursive
+            };
+            match new_watcher.watch(Path::new(&rule.path), mode) {
+                Ok(()) => info!("Watching {} for new screenshots", rule.path),
+                Err(error) => warn!("Failed to watch {}: {error}", rule.path),
+            }
+        }
+
+        *watcher = Some(new_watcher);
+        Ok(())
+    }
+
+    fn matching_rule(&self, path: &Path) -> Option<u32> {
+        let is_supported = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                SUPPORTED_EXTENSIONS
+                    .iter()
+                    .any(|supported| supported.eq_ignore_ascii_case(extension))
+            });
+        if !is_supported {
+            return None;
+        }
+
+        self.rules()
+            .ok()?
+            .iter()
+            .find(|rule| rule.matches(path))
+            .map(|rule| rule.id)
+    }
+
+    fn log(&self, activity: WatchActivity) {
+        info!(
+            "Watched screenshot {}: {}",
+            activity.file_path, activity.message
+        );
+        if let Err(error) = self.app.emit(ACTIVITY_EVENT, activity.clone()) {
+            error!("Failed to emit watch activity: {error}");
+        }
+        if let Ok(mut log) = self.activity.lock() {
+            log.push_front(activity);
+            log.truncate(MAX_ACTIVITY);
+        }
+    }
+
+    /// Queues an import for the settled files, one job per game.
+    fn import(self: &Arc<Self>, files: Vec<(PathBuf, u32)>) {
+        let mut batches: BTreeMap<(u32, u32), Vec<String>> = BTreeMap::new();
+
+        for (path, rule_id) in files {
+            let file_path = path.to_string_lossy().to_string();
+            let Some(rule) = self.rule(rule_id) else {
+                continue;
+            };
+            let Some(app_id) = rule.app_id.or_else(|| detect_app_id(&path)) else {
+                self.log(activity(
+                    rule_id,
+                    file_path,
+                    None,
+                    WatchActivityKind::Failed,
+                    "Couldn't detect which game the screenshot is from".to_string(),
+                ));
+                continue;
+            };
+            batches
+                .entry((rule_id, app_id))
+                .or_default()
+                .push(file_path);
+        }
+
+        for ((rule_id, app_id), file_paths) in batches {
+            let Some(rule) = self.rule(rule_id) else {
+                continue;
+            };
+            let state = Arc::clone(self);
+            let batch = file_paths.clone();
+            let on_finish = Box::new(move |result: &Result<ImportReport, ImportError>| {
+                state.log_results(rule_id, app_id, &batch, result);
+            });
+
+            let jobs = self.app.state::<ImportJobs>();
+            if let Err(error) = jobs.start(
+                file_paths.clone(),
+                rule.options.with_app_id(app_id),
+                ImportAnnotations::default(),
+                None,
+                Some(on_finish),
+            ) {
+                for file_path in file_paths {
+                    self.log(activity(
+                        rule_id,
+                        file_path,
+                        Some(app_id),
+                        WatchActivityKind::Failed,
+                        error.clone(),
+                    ));
+                }
+            }
+        }
+    }
+
+    fn log_results(
+        &self,
+        rule_id: u32,
+        app_id: u32,
+        file_paths: &[String],
+        result: &Result<ImportReport, ImportError>,
+    ) {
+        let (failures, duplicates, cancelled) = match result {
+            Ok(report) => (&[][..], report.duplicates(), report.cancelled()),
+            Err(error) => (error.errors(), error.duplicates(), error.cancelled()),
+        };
+
+        for file_path in file_paths {
+            let (kind, message) = if let Some(failure) = failures
+                .iter()
+                .find(|failure| failure.file_path() == file_path)
+            {
+                (WatchActivityKind::Failed, failure.message().to_string())
+            } else if cancelled.contains(file_path) {
+                (WatchActivityKind::Cancelled, "Import cancelled".to_string())
+            } else if let Some(duplicate) = duplicates
+                .iter()
+                .find(|duplicate| duplicate.file_path() == file_path)
+            {
+                let kind = if duplicate.skipped() {
+                    WatchActivityKind::Skipped
+                } else {
+                    WatchActivityKind::Imported
+                };
+                (kind, duplicate.message().to_string())
+            } else if let Err(error) = result
+                && error.errors().is_empty()
+            {
+                // The whole import failed before any screenshot was processed
+                (WatchActivityKind::Failed, error.to_string())
+            } else {
+                (WatchActivityKind::Imported, "Imported".to_string())
+            };
+
+            self.log(activity(
+                rule_id,
+                file_path.clone(),
+                Some(app_id),
+                kind,
+                message,
+            ));
+        }
+    }
+}
+
+fn activity(
+    rule_id: u32,
+    file_path: String,
+    app_id: Option<u32>,
+    kind: WatchActivityKind,
+    message: String,
+) -> WatchActivity {
+    WatchActivity {
+        time: Local::now().to_rfc3339_opts(SecondsFormat::Secs, false),
+        rule_id,
+        file_path,
+        app_id,
+        kind,
+        message,
+    }
+}
+
+/// Uses the game running in Steam, or else the name of the screenshot's folder, since capture
+/// tools like the NVIDIA overlay sort captures into folders named after the game.
+fn detect_app_id(path: &Path) -> Option<u32> {
+    steam::running_app_id().or_else(|| {
+        path.parent()
+            .and_then(Path::file_name)
+            .and_then(|name| name.to_str())
+            .and_then(steam_locate::find_app_id_by_name)
+    })
+}
+
+/// Watches the folders of the configured rules and imports new screenshots once they've been
+/// completely written.
+pub struct WatchService {
+    state: Arc<WatchState>,
+}
+
+impl WatchService {
+    pub fn new(app: AppHandle<AppRuntime>) -> Self {
+        let rules = load_rules().unwrap_or_else(|error| {
+            error!("{error}");
+            Vec::new()
+        });
+        let (events, receiver) = channel();
+        let state = Arc::new(WatchState {
+            app,
+            rules: Mutex::new(rules.clone()),
+            activity: Mutex::new(VecDeque::new()),
+            watcher: Mutex::new(None),
+            events,
+        });
+
+        if let Err(error) = state.watch(&rules) {
+            error!("{error}");
+        }
+
+        let worker_state = Arc::clone(&state);
+        thread::Builder::new()
+            .name("watch-folders".to_string())
+            .spawn(move || run_watcher(&worker_state, &receiver))
+            .expect("failed to spawn folder watcher thread");
+
+        Self { state }
+    }
+}
+
+fn run_watcher(state: &Arc<WatchState>, events: &Receiver<WatchEvent>) {
+    let mut pending: HashMap<PathBuf, PendingFile> = HashMap::new();
+
+    loop {
+        match events.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    continue;
+                }
+                for path in event.paths {
+                    let Some(rule_id) = state.matching_rule(&path) else {
+                        continue;
+                    };
+                    let file = pending.entry(path).or_insert(PendingFile {
+                        rule_id,
+                        size: None,
+                        changed: Instant::now(),
+                    });
+                    file.changed = Instant::now();
+                }
+            }
+            Ok(Err(error)) => warn!("Folder watcher error: {error}"),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let settled = take_settled(&mut pending);
+        if !settled.is_empty() {
+            state.import(settled);
+        }
+    }
+}
+
+/// Removes and returns the files that haven't changed size for [`SETTLE_TIME`] and can be opened,
+/// which capture tools that are still writing them often prevent.
+fn take_settled(pending: &mut HashMap<PathBuf, PendingFile>) -> Vec<(PathBuf, u32)> {
+    let mut settled = Vec::new();
+
+    pending.retain(|path, file| {
+        let Ok(metadata) = metadata(path) else {
+            // Removed or renamed before it settled
+            return false;
+        };
+        if !metadata.is_file() {
+            return false;
+        }
+
+        let size = metadata.len();
+        if file.size != Some(size) {
+            file.size = Some(size);
+            file.changed = Instant::now();
+            return true;
+        }
+        if size == 0 || file.changed.elapsed() < SETTLE_TIME || File::open(path).is_err() {
+            return true;
+        }
+
+        settled.push((path.clone(), file.rule_id));
+        false
+    });
+
+    settled
+}
+
+fn load_rules() -> Result<Vec<WatchRule>, String> {
+    let path = PROJECT_DIRS.config_dir().join(RULES_FILE);
+    match read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text)
+            .map_err(|error| format!("Failed to parse {}: {error}", path.display())),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(error) => Err(format!("Failed to read {}: {error}", path.display())),
+    }
+}
+
+fn save_rules(rules: &[WatchRule]) -> Result<(), String> {
+    let config_dir = PROJECT_DIRS.config_dir();
+    create_dir_all(config_dir)
+        .map_err(|error| format!("Failed to create {}: {error}", config_dir.display()))?;
+    let path = config_dir.join(RULES_FILE);
+    let text = serde_json::to_string_pretty(rules)
+        .map_err(|error| format!("Failed to serialize watch rules: {error}"))?;
+    write(&path, text).map_err(|error| format!("Failed to write {}: {error}", path.display()))
+}
+
+/// Lists the watched folders.
+///
+/// # Errors
+///
+/// Returns an error if the rules could not be accessed.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::needless_pass_by_value)]
+pub fn get_watch_rules(watch: tauri::State<'_, WatchService>) -> Result<Vec<WatchRule>, String> {
+    watch.state.rules()
+}
+
+/// Starts watching a folder, importing new screenshots for `app_id` or the detected game.
+///
+/// # Errors
+///
+/// Returns an error if the folder doesn't exist or the rules could not be saved.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::needless_pass_by_value)]
+pub fn add_watch_rule(
+    path: String,
+    app_id: Option<u32>,
+    recursive: bool,
+    options: ImportOptions,
+    watch: tauri::State<'_, WatchService>,
+) -> Result<WatchRule, String> {
+    if !Path::new(&path).is_dir() {
+        return Err(format!("{path} is not a folder"));
+    }
+
+    watch.state.update_rules(|rules| {
+        let rule = WatchRule {
+            id: rules.iter().map(|rule| rule.id).max().unwrap_or(0) + 1,
+            path,
+            app_id,
+            recursive,
+            enabled: true,
+            options,
+        };
+        rules.push(rule.clone());
+        Ok(rule)
+    })
+}
+
+/// Replaces the watch rule with the same ID.
+///
+/// # Errors
+///
+/// Returns an error if there's no such rule or the rules could not be saved.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::needless_pass_by_value)]
+pub fn update_watch_rule(
+    rule: WatchRule,
+    watch: tauri::State<'_, WatchService>,
+) -> Result<(), String> {
+    watch.state.update_rules(|rules| {
+        let existing = rules
+            .iter_mut()
+            .find(|existing| existing.id == rule.id)
+            .ok_or_else(|| format!("Unknown watch rule {}", rule.id))?;
+        *existing = rule;
+        Ok(())
+    })
+}
+
+/// Stops watching a folder.
+///
+/// # Errors
+///
+/// Returns an error if the rules could not be saved.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::needless_pass_by_value)]
+pub fn remove_watch_rule(id: u32, watch: tauri::State<'_, WatchService>) -> Result<(), String> {
+    watch.state.update_rules(|rules| {
+        rules.retain(|rule| rule.id != id);
+        Ok(())
+    })
+}
+
+/// Lists the most recently handled screenshots from watched folders, newest first.
+///
+/// # Errors
+///
+/// Returns an error if the activity log could not be accessed.
+#[tauri::command]
+#[specta::specta]
+#[allow(clippy::needless_pass_by_value)]
+pub fn get_watch_activity(
+    watch: tauri::State<'_, WatchService>,
+) -> Result<Vec<WatchActivity>, String> {
+    watch
+        .state
+        .activity
+        .lock()
+        .map(|log| log.iter().cloned().collect())
+        .map_err(|error| format!("Failed to access watch activity: {error}"))
+}
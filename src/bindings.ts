@@ -6,20 +6,89 @@ import { invoke as __TAURI_INVOKE } from "@tauri-apps/api/core";
 export const commands = {
 	getGames: () => __TAURI_INVOKE<Game[]>("get_games"),
 	getRecentSteamUser: () => __TAURI_INVOKE<string>("get_recent_steam_user"),
-	importScreenshots: (filePaths: string[], appId: number, jpegQuality: number, filterType: ResizeFilterType) => __TAURI_INVOKE<null>("import_screenshots", { filePaths, appId, jpegQuality, filterType }),
+	importScreenshots: (paths: string[], options: ImportOptions, annotations: ImportAnnotations | null, library: LibraryBackend | null, filter: FileFilter | null) => __TAURI_INVOKE<ImportJobId>("import_screenshots", { paths, options, annotations, library, filter }),
+	cancelImport: (jobId: ImportJobId) => __TAURI_INVOKE<null>("cancel_import", { jobId }),
+	getImportJobs: () => __TAURI_INVOKE<ImportJobInfo[]>("get_import_jobs"),
+	getImportJob: (jobId: ImportJobId) => __TAURI_INVOKE<ImportJobInfo>("get_import_job", { jobId }),
+	getImportJobResult: (jobId: ImportJobId) => __TAURI_INVOKE<ImportReport>("get_import_job_result", { jobId }),
+	getUnfinishedImports: () => __TAURI_INVOKE<UnfinishedImport[]>("get_unfinished_imports"),
+	resumeImport: (journalId: string) => __TAURI_INVOKE<ImportJobId>("resume_import", { journalId }),
+	discardImport: (journalId: string) => __TAURI_INVOKE<null>("discard_import", { journalId }),
+	getWatchRules: () => __TAURI_INVOKE<WatchRule[]>("get_watch_rules"),
+	addWatchRule: (path: string, appId: number | null, recursive: boolean, options: ImportOptions) => __TAURI_INVOKE<WatchRule>("add_watch_rule", { path, appId, recursive, options }),
+	updateWatchRule: (rule: WatchRule) => __TAURI_INVOKE<null>("update_watch_rule", { rule }),
+	removeWatchRule: (id: number) => __TAURI_INVOKE<null>("remove_watch_rule", { id }),
+	getWatchActivity: () => __TAURI_INVOKE<WatchActivity[]>("get_watch_activity"),
 	pickScreenshotFiles: () => __TAURI_INVOKE<string[]>("pick_screenshot_files"),
+	pickScreenshotFolders: () => __TAURI_INVOKE<string[]>("pick_screenshot_folders"),
+	previewImport: (paths: string[], filter: FileFilter) => __TAURI_INVOKE<string[]>("preview_import", { paths, filter }),
+	pickFolder: () => __TAURI_INVOKE<string | null>("pick_folder"),
 };
 
 /* Types */
+export type Background = "Solid" | "Checkerboard" | "BlurredFill";
+
+export type CaptureTimeSource = "ImportTime" | "Metadata" | "FileModified";
+
+export type DuplicatePolicy = "Import" | "Skip" | "Report";
+
+/**
+ * Which screenshots in the selected folders and archives are imported. Files that are selected directly are
+ * always imported.
+ */
+export type FileFilter = {
+	/**
+	 * Glob patterns of which a screenshot has to match at least one, or all screenshots if empty
+	 */
+	include: string[],
+	/**
+	 * Glob patterns of screenshots to leave out
+	 */
+	exclude: string[],
+	/**
+	 * Earliest capture date to import, as `YYYY-MM-DD`
+	 */
+	capturedAfter: string | null,
+	/**
+	 * Latest capture date to import, as `YYYY-MM-DD`
+	 */
+	capturedBefore: string | null,
+};
+
 export type Game = {
 	appId: number,
 	imageSrc: string,
 	appName: string,
 };
 
+export type HdrTransfer = "Srgb" | "Linear" | "Pq" | "Hlg";
+
+/**
+ * Annotations of an import, for all of its screenshots and for single files.
+ */
+export type ImportAnnotations = {
+	all: ScreenshotAnnotations,
+	/**
+	 * Annotations by file path, whose caption replaces the one for all screenshots and whose
+	 * tags are added to theirs
+	 */
+	files: Partial<{ [key in string]: ScreenshotAnnotations }>,
+};
+
+/**
+ * A screenshot that matched one already in the Steam library.
+ */
+export type ImportDuplicate = {
+	filePath: string,
+	message: string,
+	skipped: boolean,
+};
+
 export type ImportError = {
 	summary: string,
 	errors: ImportFailure[],
+	duplicates: ImportDuplicate[],
+	cancelled: string[],
 };
 
 export type ImportFailure = {
@@ -27,5 +96,162 @@ export type ImportFailure = {
 	message: string,
 };
 
+export type ImportJobId = number;
+
+/**
+ * Status of an import job, emitted to the frontend whenever it changes.
+ */
+export type ImportJobInfo = {
+	id: ImportJobId,
+	appId: number,
+	fileCount: number,
+	status: ImportJobStatus,
+	/**
+	 * Percentage of the job's screenshots that have been processed
+	 */
+	progress: number,
+};
+
+export type ImportJobStatus = "Queued" | "Running" | "Completed" | "Failed" | "Cancelled";
+
+export type ImportOptions = {
+	appId: number,
+	jpegQuality: number,
+	filterType: ResizeFilterType,
+	/**
+	 * Resizes in linear light instead of on sRGB values
+	 */
+	linearResize: boolean,
+	/**
+	 * Strength of the unsharp mask applied after resizing, 0 to not sharpen
+	 */
+	sharpenAmount: number,
+	/**
+	 * Blur radius of the unsharp mask in pixels
+	 */
+	sharpenRadius: number,
+	toneMap: ToneMapOperator,
+	/**
+	 * Exposure adjustment of HDR screenshots in stops
+	 */
+	exposure: number,
+	/**
+	 * Brightness of HDR screenshots, relative to SDR white, that's mapped to white
+	 */
+	whitePoint: number,
+	hdrTransfer: HdrTransfer,
+	background: Background,
+	/**
+	 * Color of solid backgrounds, as RGB
+	 */
+	backgroundColor: [number, number, number],
+	/**
+	 * Embeds an sRGB ICC profile in re-encoded JPEGs
+	 */
+	embedSrgbProfile: boolean,
+	metadata: MetadataPolicy,
+	captureTime: CaptureTimeSource,
+	duplicates: DuplicatePolicy,
+	/**
+	 * Maximum perceptual hash distance for a screenshot to count as a duplicate
+	 */
+	duplicateThreshold: number,
+	/**
+	 * Maximum number of screenshots processed at the same time, 0 for one per CPU core
+	 */
+	maxWorkers: number,
+	/**
+	 * Memory in MiB that the screenshots processed at the same time may take together
+	 */
+	memoryBudgetMb: number,
+	vrMode: VrMode,
+	stereoLayout: StereoLayout,
+};
+
+export type ImportReport = {
+	imported: number,
+	duplicates: ImportDuplicate[],
+	/**
+	 * Screenshots that weren't imported because the import was cancelled
+	 */
+	cancelled: string[],
+};
+
+export type LibraryBackend = "Steamworks" | "Userdata" | "Recording";
+
+export type MetadataPolicy = "KeepSelected" | "StripAll";
+
 export type ResizeFilterType = "Nearest" | "Triangle" | "CatmullRom" | "Gaussian" | "Lanczos3";
 
+/**
+ * Caption and tags to add to screenshots when they're registered with Steam. IDs are decimal
+ * strings because they don't fit in a JavaScript number.
+ */
+export type ScreenshotAnnotations = {
+	/**
+	 * Shown as the screenshot's location. `{name}`, `{date}` and `{folder}` are replaced with the
+	 * file name, capture date and name of the folder the screenshot is in
+	 */
+	caption: string | null,
+	/**
+	 * 64-bit `SteamID`s of the friends in the screenshot
+	 */
+	taggedUsers: string[],
+	/**
+	 * IDs of the Workshop items in the screenshot
+	 */
+	workshopItems: string[],
+};
+
+export type StereoLayout = "Auto" | "SideBySide" | "OverUnder";
+
+export type ToneMapOperator = "Clip" | "Reinhard" | "AcesFilmic";
+
+/**
+ * An import that was interrupted before it finished, e.g. because the app crashed.
+ */
+export type UnfinishedImport = {
+	id: string,
+	appId: number,
+	/**
+	 * When the import was first started, as an RFC 3339 timestamp
+	 */
+	started: string,
+	fileCount: number,
+	registered: number,
+	failed: number,
+};
+
+export type VrMode = "Off" | "Auto" | "Mono" | "Stereo" | "MonoCubemap" | "MonoPanorama" | "StereoPanorama";
+
+/**
+ * What happened to a screenshot found in a watched folder.
+ */
+export type WatchActivity = {
+	/**
+	 * When the screenshot was handled, as an RFC 3339 timestamp
+	 */
+	time: string,
+	ruleId: number,
+	filePath: string,
+	appId: number | null,
+	kind: WatchActivityKind,
+	message: string,
+};
+
+export type WatchActivityKind = "Imported" | "Skipped" | "Failed" | "Cancelled";
+
+/**
+ * A folder whose new screenshots are imported automatically.
+ */
+export type WatchRule = {
+	id: number,
+	path: string,
+	/**
+	 * Game to import the screenshots for, or `null` to detect it when they're created
+	 */
+	appId: number | null,
+	recursive: boolean,
+	enabled: boolean,
+	options: ImportOptions,
+};
\ No newline at end of file
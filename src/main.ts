@@ -4,11 +4,16 @@ import { syncThemeWithDocument } from './stores.svelte';
 import runUpdateCheck from './updater';
 import { screenshotSettings } from './settings.store.svelte';
 import { exampleMode } from './example-mode';
+import { resumeUnfinishedImports } from './screenshots';
 
 syncThemeWithDocument();
 
-if (!exampleMode && screenshotSettings.checkUpdatesOnStartup) {
-	runUpdateCheck();
+if (!exampleMode) {
+	// Both show a dialog, so offer to resume unfinished imports once the update prompt is closed
+	const updateCheck = screenshotSettings.checkUpdatesOnStartup
+		? runUpdateCheck()
+		: Promise.resolve();
+	updateCheck.then(resumeUnfinishedImports);
 }
 
 mount(App, { target: document.body });
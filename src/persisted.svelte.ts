@@ -43,6 +43,18 @@ export const asIntInRange =
 		return !isNaN(parsed) && parsed >= min && parsed <= max ? parsed : undefined;
 	};
 
+export const asNumberInRange =
+	(min: number, max: number): Validator<number> =>
+	(raw) => {
+		const parsed = parseFloat(raw);
+		return !isNaN(parsed) && parsed >= min && parsed <= max ? parsed : undefined;
+	};
+
+export const asText: Validator<string> = (raw) => raw;
+
+export const asHexColor: Validator<string> = (raw) =>
+	/^#[0-9a-f]{6}$/i.test(raw) ? raw : undefined;
+
 export const asEnum =
 	<T extends string>(values: readonly T[]): Validator<T> =>
 	(raw) =>
@@ -2,15 +2,78 @@ import Swal from 'sweetalert2';
 import { listen } from '@tauri-apps/api/event';
 import { mount, unmount } from 'svelte';
 import { screenshotSettings } from './settings.store.svelte';
-import { commands, type ImportError, type ImportFailure } from './bindings';
+import {
+	commands,
+	type FileFilter,
+	type ImportAnnotations,
+	type ImportDuplicate,
+	type ImportError,
+	type ImportFailure,
+	type ImportJobId,
+	type ImportJobInfo,
+	type ImportJobStatus,
+	type ImportOptions,
+	type ImportReport,
+	type UnfinishedImport
+} from './bindings';
 import ImportErrorList from './ImportErrorList.svelte';
 
+function hexToRgb(hex: string): [number, number, number] {
+	const value = parseInt(hex.slice(1), 16);
+	return [(value >> 16) & 0xff, (value >> 8) & 0xff, value & 0xff];
+}
+
+function importOptions(appID: number): ImportOptions {
+	return {
+		appId: appID,
+		jpegQuality: screenshotSettings.jpegQuality,
+		filterType: screenshotSettings.filterType,
+		linearResize: screenshotSettings.linearResize,
+		sharpenAmount: screenshotSettings.sharpenAmount,
+		sharpenRadius: screenshotSettings.sharpenRadius,
+		toneMap: screenshotSettings.toneMap,
+		exposure: screenshotSettings.exposure,
+		whitePoint: screenshotSettings.whitePoint,
+		hdrTransfer: screenshotSettings.hdrTransfer,
+		background: screenshotSettings.background,
+		backgroundColor: hexToRgb(screenshotSettings.backgroundColor),
+		embedSrgbProfile: screenshotSettings.embedSrgbProfile,
+		metadata: screenshotSettings.metadata,
+		captureTime: screenshotSettings.captureTime,
+		duplicates: screenshotSettings.duplicates,
+		duplicateThreshold: screenshotSettings.duplicateThreshold,
+		maxWorkers: screenshotSettings.maxWorkers,
+		memoryBudgetMb: screenshotSettings.memoryBudgetGb * 1024,
+		vrMode: screenshotSettings.vrMode,
+		stereoLayout: screenshotSettings.stereoLayout
+	};
+}
+
+function idList(value: string): string[] {
+	return value
+		.split(/[\s,]+/)
+		.map((id) => id.trim())
+		.filter((id) => id !== '');
+}
+
+function importAnnotations(): ImportAnnotations {
+	return {
+		all: {
+			caption: screenshotSettings.caption.trim() || null,
+			taggedUsers: idList(screenshotSettings.taggedUsers),
+			workshopItems: idList(screenshotSettings.workshopItems)
+		},
+		files: {}
+	};
+}
+
 function sendScreenshots(paths: string[], appID: number) {
 	return commands.importScreenshots(
 		paths,
-		appID,
-		screenshotSettings.jpegQuality,
-		screenshotSettings.filterType
+		importOptions(appID),
+		importAnnotations(),
+		screenshotSettings.libraryBackend,
+		null
 	);
 }
 
@@ -37,7 +100,7 @@ function isImportError(error: unknown): error is ImportError {
 	);
 }
 
-async function showErrorDetails(errors: ImportFailure[]) {
+async function showErrorDetails(errors: ImportFailure[], title = 'Import errors') {
 	const container = document.createElement('div');
 	const component = mount(ImportErrorList, {
 		target: container,
@@ -46,7 +109,7 @@ async function showErrorDetails(errors: ImportFailure[]) {
 
 	try {
 		await Swal.fire({
-			title: `Import errors (${errors.length})`,
+			title: `${title} (${errors.length})`,
 			html: container,
 			width: 'min(48rem, calc(100vw - 2rem))',
 			confirmButtonText: 'Close',
@@ -62,7 +125,7 @@ async function showErrorDetails(errors: ImportFailure[]) {
 async function showImportError(error: unknown) {
 	const importError: ImportError = isImportError(error)
 		? error
-		: { summary: errorMessage(error), errors: [] };
+		: { summary: errorMessage(error), errors: [], duplicates: [], cancelled: [] };
 	const hasDetails = importError.errors.length > 0;
 
 	console.error(importError.summary, ...importError.errors);
@@ -81,60 +144,144 @@ async function showImportError(error: unknown) {
 	await showErrorDetails(importError.errors);
 }
 
-let progress = 0;
+function duplicateSummary(duplicates: ImportDuplicate[]): string {
+	const skipped = duplicates.filter((duplicate) => duplicate.skipped).length;
+	const imported = duplicates.length - skipped;
+	const parts = [];
 
-listen('screenshotImportProgress', (event) => {
-	progress = typeof event.payload === 'number' ? event.payload : progress;
+	if (skipped > 0) parts.push(`${skipped} duplicate screenshot(s) skipped`);
+	if (imported > 0) parts.push(`${imported} duplicate screenshot(s) imported anyway`);
 
-	if (!Swal.isVisible()) {
-		Swal.fire({
-			title: 'Importing Screenshots',
-			html: `
-				<div>${Math.floor(progress)}%</div>
-				<progress value="${progress}" max="100"></progress>
-			`,
-			showConfirmButton: false,
-			allowOutsideClick: false,
-			allowEscapeKey: false
-		});
-	} else {
-		const container = Swal.getHtmlContainer();
+	return parts.join(', ');
+}
+
+async function showDuplicates(report: ImportReport) {
+	const result = await Swal.fire({
+		title: 'Screenshots imported',
+		text: `${report.imported} screenshot(s) imported. ${duplicateSummary(report.duplicates)}.`,
+		icon: 'info',
+		confirmButtonText: 'View duplicates',
+		showCancelButton: true,
+		cancelButtonText: 'Close'
+	});
+
+	if (!result.isConfirmed) return;
+
+	await showErrorDetails(report.duplicates, 'Duplicates');
+}
+
+const FINISHED_STATUSES: ImportJobStatus[] = ['Completed', 'Failed', 'Cancelled'];
+
+let activeJobId: ImportJobId | null = null;
+let cancelling = false;
+
+async function cancelImport() {
+	cancelling = true;
+
+	void Swal.fire({
+		title: 'Cancelling Import',
+		text: 'Waiting for the screenshots that are being processed...',
+		showConfirmButton: false,
+		allowOutsideClick: false,
+		allowEscapeKey: false
+	});
+
+	// The job is cancelled as soon as it's been queued if it hasn't been yet
+	if (activeJobId === null) return;
+
+	try {
+		await commands.cancelImport(activeJobId);
+	} catch (error) {
+		console.error('Failed to cancel import', error);
+	}
+}
+
+function progressHtml(progress: number) {
+	return `
+		<div>${Math.floor(progress)}%</div>
+		<progress value="${progress}" max="100"></progress>
+	`;
+}
 
-		if (container) {
-			container.innerHTML = `
-				<div>${Math.floor(progress)}%</div>
-				<progress value="${progress}" max="100"></progress>
-			`;
+function showProgressDialog(options: { html?: string; text?: string }) {
+	void Swal.fire({
+		title: 'Importing Screenshots',
+		...options,
+		showConfirmButton: false,
+		showCancelButton: true,
+		cancelButtonText: 'Cancel',
+		allowOutsideClick: false,
+		allowEscapeKey: true
+	}).then((result) => {
+		if (
+			result.dismiss === Swal.DismissReason.cancel ||
+			result.dismiss === Swal.DismissReason.esc
+		) {
+			void cancelImport();
 		}
+	});
+}
+
+function showProgress(job: ImportJobInfo) {
+	if (cancelling || job.status !== 'Running') return;
+
+	const container = Swal.getHtmlContainer();
+
+	if (Swal.isVisible() && container) {
+		container.innerHTML = progressHtml(job.progress);
+	} else {
+		showProgressDialog({ html: progressHtml(job.progress) });
 	}
+}
+
+async function waitForJob(jobId: ImportJobId) {
+	let finish = () => {};
+	const finished = new Promise<void>((resolve) => {
+		finish = () => resolve();
+	});
+
+	const unlisten = await listen<ImportJobInfo>('importJobUpdated', (event) => {
+		if (event.payload.id !== jobId) return;
 
-	if (progress >= 100) {
-		progress = 0;
+		showProgress(event.payload);
+		if (FINISHED_STATUSES.includes(event.payload.status)) finish();
+	});
+
+	try {
+		// The job may have finished before the listener was registered
+		const job = await commands.getImportJob(jobId);
+		if (FINISHED_STATUSES.includes(job.status)) finish();
+
+		await finished;
+	} finally {
+		unlisten();
 	}
-});
+}
 
-async function importScreenshots(appID: number) {
+async function runImportJob(startJob: () => Promise<ImportJobId>) {
 	try {
-		const files = await commands.pickScreenshotFiles();
+		cancelling = false;
+		showProgressDialog({ text: 'Loading...' });
 
-		if (files.length === 0) {
+		activeJobId = await startJob();
+		if (cancelling) await commands.cancelImport(activeJobId);
+
+		await waitForJob(activeJobId);
+		const report = await commands.getImportJobResult(activeJobId);
+
+		if (report.cancelled.length > 0) {
 			await Swal.fire({
-				title: 'Error',
-				text: 'No files selected',
-				icon: 'error'
+				title: 'Import cancelled',
+				text: `${report.imported} screenshot(s) imported, ${report.cancelled.length} cancelled.`,
+				icon: 'info'
 			});
 			return;
 		}
 
-		void Swal.fire({
-			title: 'Importing Screenshots',
-			text: 'Loading...',
-			showConfirmButton: false,
-			allowOutsideClick: false,
-			allowEscapeKey: false
-		});
-
-		await sendScreenshots(files, appID);
+		if (report.duplicates.length > 0) {
+			await showDuplicates(report);
+			return;
+		}
 
 		await Swal.fire({
 			title: 'Success',
@@ -145,7 +292,149 @@ async function importScreenshots(appID: number) {
 		});
 	} catch (error) {
 		await showImportError(error);
+	} finally {
+		activeJobId = null;
+	}
+}
+
+async function importScreenshots(appID: number) {
+	let files: string[];
+
+	try {
+		files = await commands.pickScreenshotFiles();
+	} catch (error) {
+		await showImportError(error);
+		return;
+	}
+
+	if (files.length === 0) {
+		await Swal.fire({
+			title: 'Error',
+			text: 'No files selected',
+			icon: 'error'
+		});
+		return;
+	}
+
+	await runImportJob(() => sendScreenshots(files, appID));
+}
+
+function filterHtml() {
+	return `
+		<input id="filter-include" class="swal2-input" placeholder="Include, e.g. *.png, 2024-*">
+		<input id="filter-exclude" class="swal2-input" placeholder="Exclude, e.g. thumbnails/**">
+		<label for="filter-after">Captured from</label>
+		<input id="filter-after" class="swal2-input" type="date">
+		<label for="filter-before">Captured until</label>
+		<input id="filter-before" class="swal2-input" type="date">
+	`;
+}
+
+function readFilter(popup: HTMLElement): FileFilter {
+	const value = (id: string) => popup.querySelector<HTMLInputElement>(`#${id}`)?.value.trim() ?? '';
+	const patterns = (id: string) =>
+		value(id)
+			.split(',')
+			.map((pattern) => pattern.trim())
+			.filter((pattern) => pattern !== '');
+
+	return {
+		include: patterns('filter-include'),
+		exclude: patterns('filter-exclude'),
+		capturedAfter: value('filter-after') || null,
+		capturedBefore: value('filter-before') || null
+	};
+}
+
+async function importFolder(appID: number) {
+	let folders: string[];
+
+	try {
+		folders = await commands.pickScreenshotFolders();
+	} catch (error) {
+		await showImportError(error);
+		return;
+	}
+
+	if (folders.length === 0) {
+		await Swal.fire({
+			title: 'Error',
+			text: 'No folders selected',
+			icon: 'error'
+		});
+		return;
+	}
+
+	const result = await Swal.fire({
+		title: 'Filter Screenshots',
+		html: filterHtml(),
+		confirmButtonText: 'Preview',
+		showCancelButton: true,
+		cancelButtonText: 'Cancel',
+		showLoaderOnConfirm: true,
+		preConfirm: async () => {
+			try {
+				const files = await commands.previewImport(folders, readFilter(Swal.getPopup()!));
+				if (files.length === 0) {
+					Swal.showValidationMessage('No screenshots match the filters');
+					return false;
+				}
+				return files;
+			} catch (error) {
+				Swal.showValidationMessage(errorMessage(error));
+				return false;
+			}
+		}
+	});
+	if (!result.isConfirmed) return;
+
+	const files: string[] = result.value;
+	const confirmation = await Swal.fire({
+		title: 'Import Screenshots',
+		text: `Import ${files.length} screenshot(s) from ${folders.length} folder(s)?`,
+		icon: 'question',
+		confirmButtonText: 'Import',
+		showCancelButton: true,
+		cancelButtonText: 'Cancel'
+	});
+	if (!confirmation.isConfirmed) return;
+
+	await runImportJob(() => sendScreenshots(files, appID));
+}
+
+async function offerToResume(unfinished: UnfinishedImport) {
+	const started = new Date(unfinished.started).toLocaleString();
+	const remaining = unfinished.fileCount - unfinished.registered;
+	const failed = unfinished.failed > 0 ? `, ${unfinished.failed} of them failed` : '';
+
+	const result = await Swal.fire({
+		title: 'Unfinished import',
+		text: `An import of ${unfinished.fileCount} screenshot(s) for AppID ${unfinished.appId} started at ${started} didn't finish. ${remaining} screenshot(s) weren't imported${failed}.`,
+		icon: 'warning',
+		confirmButtonText: 'Resume',
+		showDenyButton: true,
+		denyButtonText: 'Discard',
+		showCancelButton: true,
+		cancelButtonText: 'Later'
+	});
+
+	if (result.isConfirmed) {
+		await runImportJob(() => commands.resumeImport(unfinished.id));
+	} else if (result.isDenied) {
+		await commands.discardImport(unfinished.id);
+	}
+}
+
+async function resumeUnfinishedImports() {
+	try {
+		const unfinishedImports = await commands.getUnfinishedImports();
+
+		for (const unfinished of unfinishedImports) {
+			await offerToResume(unfinished);
+		}
+	} catch (error) {
+		await showImportError(error);
 	}
 }
 
-export { importScreenshots };
+export { importScreenshots, importFolder, importOptions, resumeUnfinishedImports };
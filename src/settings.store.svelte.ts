@@ -1,5 +1,24 @@
-import type { ResizeFilterType } from './bindings';
-import { Persisted, asBoolean, asIntInRange, asEnum } from './persisted.svelte';
+import type {
+	Background,
+	CaptureTimeSource,
+	DuplicatePolicy,
+	HdrTransfer,
+	LibraryBackend,
+	MetadataPolicy,
+	ResizeFilterType,
+	StereoLayout,
+	ToneMapOperator,
+	VrMode
+} from './bindings';
+import {
+	Persisted,
+	asBoolean,
+	asIntInRange,
+	asNumberInRange,
+	asHexColor,
+	asText,
+	asEnum
+} from './persisted.svelte';
 
 export type FilterType = ResizeFilterType;
 
@@ -13,9 +32,123 @@ export const FILTER_LABELS = {
 
 export const FILTER_TYPES = Object.keys(FILTER_LABELS) as ResizeFilterType[];
 
+export const TONE_MAP_LABELS = {
+	Clip: 'Clip: cut off highlights',
+	Reinhard: 'Reinhard: soft highlights',
+	AcesFilmic: 'ACES filmic: cinematic contrast'
+} satisfies Record<ToneMapOperator, string>;
+
+export const TONE_MAP_OPERATORS = Object.keys(TONE_MAP_LABELS) as ToneMapOperator[];
+
+export const HDR_TRANSFER_LABELS = {
+	Srgb: 'Regular SDR images',
+	Linear: 'Linear HDR',
+	Pq: 'HDR10 (PQ)',
+	Hlg: 'HLG'
+} satisfies Record<HdrTransfer, string>;
+
+export const HDR_TRANSFERS = Object.keys(HDR_TRANSFER_LABELS) as HdrTransfer[];
+
+export const BACKGROUND_LABELS = {
+	Solid: 'Solid color',
+	Checkerboard: 'Checkerboard',
+	BlurredFill: 'Blurred fill: colors of the surrounding image'
+} satisfies Record<Background, string>;
+
+export const BACKGROUNDS = Object.keys(BACKGROUND_LABELS) as Background[];
+
+export const CAPTURE_TIME_LABELS = {
+	ImportTime: 'Import time',
+	Metadata: 'Original capture time from EXIF/PNG metadata',
+	FileModified: 'File modification time'
+} satisfies Record<CaptureTimeSource, string>;
+
+export const CAPTURE_TIME_SOURCES = Object.keys(CAPTURE_TIME_LABELS) as CaptureTimeSource[];
+
+export const METADATA_POLICY_LABELS = {
+	KeepSelected: 'Keep capture date, game and description',
+	StripAll: 'Remove all metadata'
+} satisfies Record<MetadataPolicy, string>;
+
+export const METADATA_POLICIES = Object.keys(METADATA_POLICY_LABELS) as MetadataPolicy[];
+
+export const DUPLICATE_POLICY_LABELS = {
+	Import: 'Import everything',
+	Skip: 'Skip screenshots already in the library',
+	Report: 'Import, but report screenshots already in the library'
+} satisfies Record<DuplicatePolicy, string>;
+
+export const DUPLICATE_POLICIES = Object.keys(DUPLICATE_POLICY_LABELS) as DuplicatePolicy[];
+
+export const VR_MODE_LABELS = {
+	Off: 'Regular screenshots',
+	Auto: 'VR: detect the type from the aspect ratio',
+	Mono: 'VR: flat',
+	Stereo: 'VR: stereo, an image for each eye',
+	MonoCubemap: 'VR: cubemap, six faces next to each other',
+	MonoPanorama: 'VR: 360° panorama',
+	StereoPanorama: 'VR: stereo 360° panorama'
+} satisfies Record<VrMode, string>;
+
+export const VR_MODES = Object.keys(VR_MODE_LABELS) as VrMode[];
+
+export const STEREO_LAYOUT_LABELS = {
+	Auto: 'Detect from the aspect ratio',
+	SideBySide: 'Side by side',
+	OverUnder: 'Over-under'
+} satisfies Record<StereoLayout, string>;
+
+export const STEREO_LAYOUTS = Object.keys(STEREO_LAYOUT_LABELS) as StereoLayout[];
+
+export const LIBRARY_BACKEND_LABELS = {
+	Steamworks: 'Steamworks: import through the running Steam client',
+	Userdata: 'Direct: write into the Steam userdata folder while Steam is closed',
+	Recording: 'Dry run: process screenshots without importing them'
+} satisfies Record<LibraryBackend, string>;
+
+export const LIBRARY_BACKENDS = Object.keys(LIBRARY_BACKEND_LABELS) as LibraryBackend[];
+
 class ScreenshotSettings {
 	#quality = new Persisted('jpegQuality', 95, asIntInRange(1, 100));
 	#filterType = new Persisted<FilterType>('filterType', 'Lanczos3', asEnum(FILTER_TYPES));
+	#linearResize = new Persisted('linearResize', false, asBoolean);
+	#sharpenAmount = new Persisted('sharpenAmount', 0, asNumberInRange(0, 2));
+	#sharpenRadius = new Persisted('sharpenRadius', 1, asNumberInRange(0.5, 3));
+	#toneMap = new Persisted<ToneMapOperator>('toneMap', 'AcesFilmic', asEnum(TONE_MAP_OPERATORS));
+	#exposure = new Persisted('exposure', 0, asNumberInRange(-4, 4));
+	#whitePoint = new Persisted('whitePoint', 4, asNumberInRange(1, 16));
+	#hdrTransfer = new Persisted<HdrTransfer>('hdrTransfer', 'Srgb', asEnum(HDR_TRANSFERS));
+	#background = new Persisted<Background>('background', 'Solid', asEnum(BACKGROUNDS));
+	#backgroundColor = new Persisted('backgroundColor', '#000000', asHexColor);
+	#embedSrgbProfile = new Persisted('embedSrgbProfile', false, asBoolean);
+	#metadata = new Persisted<MetadataPolicy>(
+		'metadata',
+		'KeepSelected',
+		asEnum(METADATA_POLICIES)
+	);
+	#captureTime = new Persisted<CaptureTimeSource>(
+		'captureTime',
+		'ImportTime',
+		asEnum(CAPTURE_TIME_SOURCES)
+	);
+	#duplicates = new Persisted<DuplicatePolicy>(
+		'duplicates',
+		'Import',
+		asEnum(DUPLICATE_POLICIES)
+	);
+	#duplicateThreshold = new Persisted('duplicateThreshold', 4, asIntInRange(0, 64));
+	#maxWorkers = new Persisted('maxWorkers', 0, asIntInRange(0, 32));
+	#memoryBudgetGb = new Persisted('memoryBudgetGb', 4, asIntInRange(1, 64));
+	#vrMode = new Persisted<VrMode>('vrMode', 'Off', asEnum(VR_MODES));
+	#stereoLayout = new Persisted<StereoLayout>('stereoLayout', 'Auto', asEnum(STEREO_LAYOUTS));
+	#caption = new Persisted('caption', '', asText);
+	#taggedUsers = new Persisted('taggedUsers', '', asText);
+	#workshopItems = new Persisted('workshopItems', '', asText);
+	#libraryBackend = new Persisted<LibraryBackend>(
+		'libraryBackend',
+		'Steamworks',
+		asEnum(LIBRARY_BACKENDS)
+	);
 	#checkUpdatesOnStartup = new Persisted('checkUpdatesOnStartup', true, asBoolean);
 
 	get jpegQuality() {
@@ -24,6 +157,72 @@ class ScreenshotSettings {
 	get filterType() {
 		return this.#filterType.value;
 	}
+	get linearResize() {
+		return this.#linearResize.value;
+	}
+	get sharpenAmount() {
+		return this.#sharpenAmount.value;
+	}
+	get sharpenRadius() {
+		return this.#sharpenRadius.value;
+	}
+	get toneMap() {
+		return this.#toneMap.value;
+	}
+	get exposure() {
+		return this.#exposure.value;
+	}
+	get whitePoint() {
+		return this.#whitePoint.value;
+	}
+	get hdrTransfer() {
+		return this.#hdrTransfer.value;
+	}
+	get background() {
+		return this.#background.value;
+	}
+	get backgroundColor() {
+		return this.#backgroundColor.value;
+	}
+	get embedSrgbProfile() {
+		return this.#embedSrgbProfile.value;
+	}
+	get metadata() {
+		return this.#metadata.value;
+	}
+	get captureTime() {
+		return this.#captureTime.value;
+	}
+	get duplicates() {
+		return this.#duplicates.value;
+	}
+	get duplicateThreshold() {
+		return this.#duplicateThreshold.value;
+	}
+	get maxWorkers() {
+		return this.#maxWorkers.value;
+	}
+	get memoryBudgetGb() {
+		return this.#memoryBudgetGb.value;
+	}
+	get vrMode() {
+		return this.#vrMode.value;
+	}
+	get stereoLayout() {
+		return this.#stereoLayout.value;
+	}
+	get caption() {
+		return this.#caption.value;
+	}
+	get taggedUsers() {
+		return this.#taggedUsers.value;
+	}
+	get workshopItems() {
+		return this.#workshopItems.value;
+	}
+	get libraryBackend() {
+		return this.#libraryBackend.value;
+	}
 	get checkUpdatesOnStartup() {
 		return this.#checkUpdatesOnStartup.value;
 	}
@@ -36,6 +235,94 @@ class ScreenshotSettings {
 		this.#filterType.set(value);
 	}
 
+	setLinearResize(value: boolean) {
+		this.#linearResize.set(value);
+	}
+
+	setSharpenAmount(value: number) {
+		this.#sharpenAmount.set(Math.min(2, Math.max(0, value)));
+	}
+
+	setSharpenRadius(value: number) {
+		this.#sharpenRadius.set(Math.min(3, Math.max(0.5, value)));
+	}
+
+	setToneMap(value: ToneMapOperator) {
+		this.#toneMap.set(value);
+	}
+
+	setExposure(value: number) {
+		this.#exposure.set(Math.min(4, Math.max(-4, value)));
+	}
+
+	setWhitePoint(value: number) {
+		this.#whitePoint.set(Math.min(16, Math.max(1, value)));
+	}
+
+	setHdrTransfer(value: HdrTransfer) {
+		this.#hdrTransfer.set(value);
+	}
+
+	setBackground(value: Background) {
+		this.#background.set(value);
+	}
+
+	setBackgroundColor(value: string) {
+		this.#backgroundColor.set(value);
+	}
+
+	setEmbedSrgbProfile(value: boolean) {
+		this.#embedSrgbProfile.set(value);
+	}
+
+	setMetadata(value: MetadataPolicy) {
+		this.#metadata.set(value);
+	}
+
+	setCaptureTime(value: CaptureTimeSource) {
+		this.#captureTime.set(value);
+	}
+
+	setDuplicates(value: DuplicatePolicy) {
+		this.#duplicates.set(value);
+	}
+
+	setDuplicateThreshold(value: number) {
+		this.#duplicateThreshold.set(Math.min(64, Math.max(0, Math.round(value))));
+	}
+
+	setMaxWorkers(value: number) {
+		this.#maxWorkers.set(Math.min(32, Math.max(0, Math.round(value))));
+	}
+
+	setMemoryBudgetGb(value: number) {
+		this.#memoryBudgetGb.set(Math.min(64, Math.max(1, Math.round(value))));
+	}
+
+	setVrMode(value: VrMode) {
+		this.#vrMode.set(value);
+	}
+
+	setStereoLayout(value: StereoLayout) {
+		this.#stereoLayout.set(value);
+	}
+
+	setCaption(value: string) {
+		this.#caption.set(value);
+	}
+
+	setTaggedUsers(value: string) {
+		this.#taggedUsers.set(value);
+	}
+
+	setWorkshopItems(value: string) {
+		this.#workshopItems.set(value);
+	}
+
+	setLibraryBackend(value: LibraryBackend) {
+		this.#libraryBackend.set(value);
+	}
+
 	setCheckUpdatesOnStartup(value: boolean) {
 		this.#checkUpdatesOnStartup.set(value);
 	}